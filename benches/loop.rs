@@ -0,0 +1,84 @@
+//! Loop-heavy script exercising local variable access. The same loop runs
+//! inside a function, where variables are slots, and at the top level, where
+//! they are globals looked up by name like every variable was before slots.
+//!
+//! Run with `cargo bench --bench loop`.
+
+extern crate jazz_ml_vm;
+
+use jazz_ml_vm::codegen::Compiler;
+use jazz_ml_vm::lexer::*;
+use jazz_ml_vm::parser::*;
+use jazz_ml_vm::source::*;
+use jazz_ml_vm::vm::VirtualMachine;
+
+use std::time::{Duration, Instant};
+
+const LOOP: &str = "
+  var i = 0
+  var acc = 0
+  while i < n {
+    var j = i % 7
+    acc = acc + j * 2 - j
+    i = i + 1
+  }
+";
+
+const N: usize = 300000;
+
+const RUNS: usize = 10;
+
+fn run_once(script: &str) -> Duration {
+    let source = Source::from("<bench>", script.lines().map(|x| x.into()).collect());
+    let tokens = Lexer::default(script.chars().collect(), &source)
+        .map(|token| token.expect("lexing failed"))
+        .collect();
+    let ast = Parser::new(tokens, &source)
+        .parse()
+        .expect("parsing failed");
+
+    let mut vm = VirtualMachine::new();
+    vm.init_builtins();
+    let ins = {
+        let mut compiler = Compiler::new(&mut vm);
+        compiler.compile(ast);
+        compiler.finish()
+    };
+
+    let start = Instant::now();
+    vm.run_instructions(ins).expect("runtime error");
+    start.elapsed()
+}
+
+// median and min of `RUNS` runs
+fn measure(script: &str) -> (Duration, Duration) {
+    let mut times = (0..RUNS).map(|_| run_once(script)).collect::<Vec<_>>();
+    times.sort();
+    (times[RUNS / 2], times[0])
+}
+
+fn main() {
+    let slots = format!(
+        "let sum = func(n: int) : int {{{}  return acc\n}}\nvar result = sum({})\n",
+        LOOP, N
+    );
+    let names = format!("var n = {}{}var result = acc\n", N, LOOP);
+
+    let ms = |d: Duration| d.as_secs() as f64 * 1e3 + d.subsec_nanos() as f64 / 1e6;
+    let (slot_median, slot_min) = measure(&slots);
+    let (name_median, name_min) = measure(&names);
+
+    println!(
+        "loop, slots: median {:.2} ms, min {:.2} ms over {} runs",
+        ms(slot_median),
+        ms(slot_min),
+        RUNS
+    );
+    println!(
+        "loop, names: median {:.2} ms, min {:.2} ms over {} runs",
+        ms(name_median),
+        ms(name_min),
+        RUNS
+    );
+    println!("slots are {:.2}x faster", ms(name_median) / ms(slot_median));
+}
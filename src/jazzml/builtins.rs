@@ -1,89 +1,152 @@
+use super::error::RuntimeError;
 use super::value::*;
 use super::vm::VirtualMachine;
-use fnv::FnvHashMap;
 use std::f64;
 
 pub fn value(value: Value) -> Value {
     value
 }
-use std::iter::FromIterator;
 
-pub fn chars(vm: &mut VirtualMachine, args: Vec<Value>) -> Value {
-    let string: String = args[0].clone().as_str(vm);
-    let chars = string.chars();
-    let vec = Vec::from_iter(chars);
-    let mut map = FnvHashMap::default();
+fn unsupported(op: &str, x: &Value, y: &Value, vm: &VirtualMachine) -> RuntimeError {
+    RuntimeError::new(format!(
+        "Can't apply `{}` to `{}` and `{}`",
+        op,
+        x.as_str(vm),
+        y.as_str(vm)
+    ))
+}
 
-    for (idx, value) in vec.iter().enumerate() {
-        map.insert(Value::Int(idx as i64), Value::Str(value.to_string()));
+// `x op y` on ints, an error instead of a panic on overflow or division by zero
+fn checked(
+    op: &str,
+    x: i64,
+    y: i64,
+    apply: fn(i64, i64) -> Option<i64>,
+) -> Result<Value, RuntimeError> {
+    if y == 0 && (op == "/" || op == "%") {
+        return Err(RuntimeError::new(format!(
+            "Division by zero in `{} {} {}`",
+            x, op, y
+        )));
+    }
+
+    match apply(x, y) {
+        Some(z) => Ok(Value::Int(z)),
+        None => Err(RuntimeError::new(format!(
+            "Integer overflow in `{} {} {}`",
+            x, op, y
+        ))),
     }
-    let obj_id = vm.new_object();
-    let obj: &mut Object = &mut vm.get_object(&obj_id).borrow_mut();
-    obj.map = map;
+}
 
-    return Value::ObjectRef(obj_id);
+fn expected_array(value: &Value, vm: &VirtualMachine) -> RuntimeError {
+    RuntimeError::new(format!("Expected array, found `{}`", value.as_str(vm)))
 }
 
-pub fn arr_len(vm: &mut VirtualMachine, args: Vec<Value>) -> Value {
-    let arr = args[0].as_object_id();
-    let obj: &Object = &vm.get_object(&arr).borrow();
+pub fn chars(vm: &mut VirtualMachine, args: Vec<Value>) -> Result<Value, RuntimeError> {
+    let string: String = args[0].clone().as_str(vm);
+    let chars = string.chars().map(|c| Value::Str(c.to_string())).collect();
 
-    return Value::Int(obj.map.len() as i64);
+    return Ok(Value::Array(vm.new_array(chars)));
 }
 
-pub fn arr_push(vm: &mut VirtualMachine, args: Vec<Value>) -> Value {
+pub fn arr_len(vm: &mut VirtualMachine, args: Vec<Value>) -> Result<Value, RuntimeError> {
+    let len = match args[0] {
+        Value::Array(id) => vm.get_array(&id)?.vec.borrow().len(),
+        Value::Str(ref s) => s.chars().count(),
+        Value::ObjectRef(id) => vm.get_object(&id)?.borrow().map.len(),
+        ref v => return Err(expected_array(v, vm)),
+    };
+
+    return Ok(Value::Int(len as i64));
+}
+
+pub fn arr_push(vm: &mut VirtualMachine, args: Vec<Value>) -> Result<Value, RuntimeError> {
     let arr = args[0].clone();
     let value = args[1].clone();
     match arr {
-        Value::ObjectRef(obj_id) => {
-            let obj: &mut Object = &mut vm.get_object(&obj_id).borrow_mut();
-            let len = obj.map.len();
-            obj.map.insert(Value::Int(len as i64), value);
-        }
-        _ => panic!("Expected array object"),
+        Value::Array(id) => vm.get_array(&id)?.vec.borrow_mut().push(value),
+        v => return Err(expected_array(&v, vm)),
     }
 
-    return Value::Null;
+    return Ok(Value::Null);
 }
 
-pub fn arr_pop(vm: &mut VirtualMachine, args: Vec<Value>) -> Value {
+pub fn arr_pop(vm: &mut VirtualMachine, args: Vec<Value>) -> Result<Value, RuntimeError> {
     let arr = args[0].clone();
     match arr {
-        Value::ObjectRef(obj_id) => {
-            let obj: &mut Object = &mut vm.get_object(&obj_id).borrow_mut();
-            let len = obj.map.len() - 1;
-            let value = obj
-                .map
-                .remove(&Value::Int(len as i64))
-                .unwrap_or(Value::Null);
-            return value;
+        Value::Array(id) => {
+            let value = vm.get_array(&id)?.vec.borrow_mut().pop();
+            return Ok(value.unwrap_or(Value::Null));
         }
-        _ => panic!("Expected array object"),
+        v => return Err(expected_array(&v, vm)),
     }
 }
 
-pub fn concat(vm: &mut VirtualMachine, args: Vec<Value>) -> Value {
+fn expected_map(value: &Value, vm: &VirtualMachine) -> RuntimeError {
+    RuntimeError::new(format!("Expected map, found `{}`", value.as_str(vm)))
+}
+
+fn map_id(value: &Value, vm: &VirtualMachine) -> Result<usize, RuntimeError> {
+    match value {
+        Value::ObjectRef(id) => Ok(*id),
+        v => Err(expected_map(v, vm)),
+    }
+}
+
+/// Keys of a map, in no particular order
+pub fn map_keys(vm: &mut VirtualMachine, args: Vec<Value>) -> Result<Value, RuntimeError> {
+    let id = map_id(&args[0], vm)?;
+    let keys = vm.get_object(&id)?.borrow().map.keys().cloned().collect();
+
+    return Ok(Value::Array(vm.new_array(keys)));
+}
+
+/// Values of a map, in the order `keys` gives their keys
+pub fn map_values(vm: &mut VirtualMachine, args: Vec<Value>) -> Result<Value, RuntimeError> {
+    let id = map_id(&args[0], vm)?;
+    let values = vm.get_object(&id)?.borrow().map.values().cloned().collect();
+
+    return Ok(Value::Array(vm.new_array(values)));
+}
+
+pub fn map_has(vm: &mut VirtualMachine, args: Vec<Value>) -> Result<Value, RuntimeError> {
+    let id = map_id(&args[0], vm)?;
+    let has = vm.get_object(&id)?.borrow().map.contains_key(&args[1]);
+
+    return Ok(Value::Bool(has));
+}
+
+/// Removes a key, returning its value or null if it wasn't there
+pub fn map_remove(vm: &mut VirtualMachine, args: Vec<Value>) -> Result<Value, RuntimeError> {
+    let id = map_id(&args[0], vm)?;
+    let value = vm.get_object(&id)?.borrow_mut().map.remove(&args[1]);
+
+    return Ok(value.unwrap_or(Value::Null));
+}
+
+pub fn concat(vm: &mut VirtualMachine, args: Vec<Value>) -> Result<Value, RuntimeError> {
     let mut buff = String::new();
     for value in args.iter() {
         buff.push_str(&value.as_str(vm));
     }
-    return Value::Str(buff);
+    return Ok(Value::Str(buff));
 }
 
-pub fn print(vm: &mut VirtualMachine, args: Vec<Value>) -> Value {
+pub fn print(vm: &mut VirtualMachine, args: Vec<Value>) -> Result<Value, RuntimeError> {
     for value in args.iter() {
         print!("{}", value.as_str(vm));
     }
 
-    Value::Null
+    Ok(Value::Null)
 }
 
-pub fn println(vm: &mut VirtualMachine, args: Vec<Value>) -> Value {
+pub fn println(vm: &mut VirtualMachine, args: Vec<Value>) -> Result<Value, RuntimeError> {
     for value in args.iter() {
         print!("{}", value.as_str(vm));
     }
     println!("");
-    Value::Null
+    Ok(Value::Null)
 }
 
 extern "C" {
@@ -91,291 +154,410 @@ extern "C" {
     fn putchar(c: u32);
 }
 
-pub fn get_char(_: &mut VirtualMachine, _args: Vec<Value>) -> Value {
+pub fn get_char(_: &mut VirtualMachine, _args: Vec<Value>) -> Result<Value, RuntimeError> {
     use std::char;
-    let character = char::from_u32(unsafe { getchar() }).unwrap();
-    return Value::Str(character.to_string());
+    match char::from_u32(unsafe { getchar() }) {
+        Some(character) => return Ok(Value::Str(character.to_string())),
+        None => return Ok(Value::Null),
+    }
 }
 
 use std::char;
-pub fn put_char(vm: &mut VirtualMachine, _args: Vec<Value>) -> Value {
-    
+pub fn put_char(vm: &mut VirtualMachine, _args: Vec<Value>) -> Result<Value, RuntimeError> {
     let v = match &_args[0] {
-        Value::Str(s) => s.chars().nth(0).unwrap(),
-        Value::Int(ch) => char::from_u32(*ch as u32).unwrap(),
-        _ => panic!(),
+        Value::Str(s) => s.chars().nth(0),
+        Value::Int(ch) => char::from_u32(*ch as u32),
+        _ => None,
+    };
+    let v = match v {
+        Some(v) => v,
+        None => {
+            return Err(RuntimeError::new(format!(
+                "Can't write `{}` as a character",
+                _args[0].as_str(vm)
+            )));
+        }
     };
     unsafe {
         putchar(v as u32);
     }
-    return Value::Null;
+    return Ok(Value::Null);
 }
 
-pub fn add(vm: &mut VirtualMachine, args: Vec<Value>) -> Value {
+pub fn add(vm: &mut VirtualMachine, args: Vec<Value>) -> Result<Value, RuntimeError> {
     if args.len() == 0 || args.len() > 2 {
-        return value(Value::Null);
+        return Ok(Value::Null);
     }
     let x: &Value = &args[0];
     let y: &Value = &args[1];
     match (x, y) {
         (Value::Float(f), v2) => {
-            return value(Value::Float((f64::from_bits(*f) + v2.as_f64(vm)).to_bits()));
+            return Ok(Value::Float(
+                (f64::from_bits(*f) + v2.as_f64(vm)?).to_bits(),
+            ));
         }
         (Value::Int(i), Value::Float(f2)) => {
-            return value(Value::Float((*i as f64 + f64::from_bits(*f2)).to_bits()));
+            return Ok(Value::Float((*i as f64 + f64::from_bits(*f2)).to_bits()));
         }
-        (Value::Int(i), v2) => return value(Value::Int(*i + v2.as_int(vm))),
+        (Value::Int(i), v2) => return checked("+", *i, v2.as_int(vm)?, i64::checked_add),
         (Value::Str(s), v) => {
             let str: &str = &v.as_str(vm);
             let mut buff = s.clone();
             buff.push_str(str);
-            return value(Value::Str(buff));
+            return Ok(Value::Str(buff));
+        }
+        _ => return Err(unsupported("+", x, y, vm)),
+    }
+}
+
+pub fn sub(vm: &mut VirtualMachine, args: Vec<Value>) -> Result<Value, RuntimeError> {
+    if args.len() == 0 || args.len() > 2 {
+        return Ok(Value::Null);
+    }
+    let x: &Value = &args[0];
+    let y: &Value = &args[1];
+    match (x, y) {
+        (Value::Float(f), v2) => {
+            return Ok(Value::Float(
+                (f64::from_bits(*f) - v2.as_f64(vm)?).to_bits(),
+            ));
+        }
+        (Value::Int(i), Value::Float(f2)) => {
+            return Ok(Value::Float((*i as f64 - f64::from_bits(*f2)).to_bits()));
         }
-        (Value::Array(arr1), Value::Array(arr2)) => unimplemented!(),
-        _ => panic!(""),
+        (Value::Int(i), v2) => return checked("-", *i, v2.as_int(vm)?, i64::checked_sub),
+        _ => return Err(unsupported("-", x, y, vm)),
     }
 }
 
-pub fn sub(vm: &mut VirtualMachine, args: Vec<Value>) -> Value {
+pub fn mul(vm: &mut VirtualMachine, args: Vec<Value>) -> Result<Value, RuntimeError> {
     if args.len() == 0 || args.len() > 2 {
-        return value(Value::Null);
+        return Ok(Value::Null);
     }
     let x: &Value = &args[0];
     let y: &Value = &args[1];
     match (x, y) {
         (Value::Float(f), v2) => {
-            return value(Value::Float((f64::from_bits(*f) - v2.as_f64(vm)).to_bits()));
+            return Ok(Value::Float(
+                (f64::from_bits(*f) * v2.as_f64(vm)?).to_bits(),
+            ));
         }
         (Value::Int(i), Value::Float(f2)) => {
-            return value(Value::Float((*i as f64 - f64::from_bits(*f2)).to_bits()));
+            return Ok(Value::Float((*i as f64 * f64::from_bits(*f2)).to_bits()));
         }
-        (Value::Int(i), v2) => return value(Value::Int(*i - v2.as_int(vm))),
-        _ => panic!(""),
+        (Value::Int(i), v2) => return checked("*", *i, v2.as_int(vm)?, i64::checked_mul),
+        _ => return Err(unsupported("*", x, y, vm)),
     }
 }
 
-pub fn mul(vm: &mut VirtualMachine, args: Vec<Value>) -> Value {
+pub fn div(vm: &mut VirtualMachine, args: Vec<Value>) -> Result<Value, RuntimeError> {
     if args.len() == 0 || args.len() > 2 {
-        return value(Value::Null);
+        return Ok(Value::Null);
     }
     let x: &Value = &args[0];
     let y: &Value = &args[1];
     match (x, y) {
         (Value::Float(f), v2) => {
-            return value(Value::Float((f64::from_bits(*f) * v2.as_f64(vm)).to_bits()));
+            return Ok(Value::Float(
+                (f64::from_bits(*f) / v2.as_f64(vm)?).to_bits(),
+            ));
         }
         (Value::Int(i), Value::Float(f2)) => {
-            return value(Value::Float((*i as f64 * f64::from_bits(*f2)).to_bits()));
+            return Ok(Value::Float((*i as f64 / f64::from_bits(*f2)).to_bits()));
         }
-        (Value::Int(i), v2) => return value(Value::Int(*i * v2.as_int(vm))),
-        _ => panic!(""),
+        (Value::Int(i), v2) => return checked("/", *i, v2.as_int(vm)?, i64::checked_div),
+        _ => return Err(unsupported("/", x, y, vm)),
     }
 }
 
-pub fn div(vm: &mut VirtualMachine, args: Vec<Value>) -> Value {
+pub fn rem(vm: &mut VirtualMachine, args: Vec<Value>) -> Result<Value, RuntimeError> {
     if args.len() == 0 || args.len() > 2 {
-        return value(Value::Null);
+        return Ok(Value::Null);
     }
     let x: &Value = &args[0];
     let y: &Value = &args[1];
     match (x, y) {
         (Value::Float(f), v2) => {
-            return value(Value::Float((f64::from_bits(*f) / v2.as_f64(vm)).to_bits()));
+            return Ok(Value::Float(
+                (f64::from_bits(*f) % v2.as_f64(vm)?).to_bits(),
+            ));
         }
         (Value::Int(i), Value::Float(f2)) => {
-            return value(Value::Float((*i as f64 / f64::from_bits(*f2)).to_bits()));
+            return Ok(Value::Float((*i as f64 % f64::from_bits(*f2)).to_bits()));
         }
-        (Value::Int(i), v2) => return value(Value::Int(*i / v2.as_int(vm))),
-        _ => panic!(""),
+        (Value::Int(i), v2) => return checked("%", *i, v2.as_int(vm)?, i64::checked_rem),
+        _ => return Err(unsupported("%", x, y, vm)),
     }
 }
 
-pub fn rem(vm: &mut VirtualMachine, args: Vec<Value>) -> Value {
+/// Integer powers need a non-negative exponent, anything else is a float
+pub fn pow(vm: &mut VirtualMachine, args: Vec<Value>) -> Result<Value, RuntimeError> {
     if args.len() == 0 || args.len() > 2 {
-        return value(Value::Null);
+        return Ok(Value::Null);
     }
     let x: &Value = &args[0];
     let y: &Value = &args[1];
     match (x, y) {
         (Value::Float(f), v2) => {
-            return value(Value::Float((f64::from_bits(*f) % v2.as_f64(vm)).to_bits()));
+            return Ok(Value::Float(
+                f64::from_bits(*f).powf(v2.as_f64(vm)?).to_bits(),
+            ));
         }
         (Value::Int(i), Value::Float(f2)) => {
-            return value(Value::Float((*i as f64 % f64::from_bits(*f2)).to_bits()));
+            return Ok(Value::Float(
+                (*i as f64).powf(f64::from_bits(*f2)).to_bits(),
+            ));
         }
-        (Value::Int(i), v2) => return value(Value::Int(*i % v2.as_int(vm))),
-        _ => panic!(""),
+        (Value::Int(i), Value::Int(e)) => {
+            if *e < 0 || *e > u32::max_value() as i64 {
+                return Err(unsupported("^", x, y, vm));
+            }
+
+            match i.checked_pow(*e as u32) {
+                Some(z) => return Ok(Value::Int(z)),
+                None => return Err(RuntimeError::new(format!("Overflow in `{} ^ {}`", i, e))),
+            }
+        }
+        _ => return Err(unsupported("^", x, y, vm)),
+    }
+}
+
+pub fn neg(vm: &mut VirtualMachine, args: Vec<Value>) -> Result<Value, RuntimeError> {
+    match args.first() {
+        Some(Value::Int(i)) => match i.checked_neg() {
+            Some(z) => Ok(Value::Int(z)),
+            None => Err(RuntimeError::new(format!("Integer overflow in `-{}`", i))),
+        },
+        Some(Value::Float(f)) => Ok(Value::Float((-f64::from_bits(*f)).to_bits())),
+        Some(v) => Err(RuntimeError::new(format!(
+            "Can't negate `{}`",
+            v.as_str(vm)
+        ))),
+        None => Ok(Value::Null),
+    }
+}
+
+pub fn not(vm: &mut VirtualMachine, args: Vec<Value>) -> Result<Value, RuntimeError> {
+    match args.first() {
+        Some(Value::Bool(b)) => Ok(Value::Bool(!b)),
+        Some(v) => Err(RuntimeError::new(format!(
+            "Can't apply `not` to `{}`",
+            v.as_str(vm)
+        ))),
+        None => Ok(Value::Null),
+    }
+}
+
+pub fn shr(_vm: &mut VirtualMachine, args: Vec<Value>) -> Result<Value, RuntimeError> {
+    if args.len() == 0 || args.len() > 2 {
+        return Ok(Value::Null);
+    }
+    let x: &Value = &args[0];
+    let y: &Value = &args[1];
+    match (x, y) {
+        (Value::Int(i1), Value::Int(i2)) => return Ok(Value::Int(i1 >> i2)),
+        _ => return Ok(Value::Null),
     }
 }
 
-pub fn shr(_vm: &mut VirtualMachine, args: Vec<Value>) -> Value {
+pub fn shl(_vm: &mut VirtualMachine, args: Vec<Value>) -> Result<Value, RuntimeError> {
     if args.len() == 0 || args.len() > 2 {
-        return value(Value::Null);
+        return Ok(Value::Null);
     }
     let x: &Value = &args[0];
     let y: &Value = &args[1];
     match (x, y) {
-        (Value::Int(i1), Value::Int(i2)) => return value(Value::Int(i1 >> i2)),
-        _ => return value(Value::Null),
+        (Value::Int(i1), Value::Int(i2)) => return Ok(Value::Int(i1 << i2)),
+        _ => return Ok(Value::Null),
     }
 }
 
-pub fn shl(_vm: &mut VirtualMachine, args: Vec<Value>) -> Value {
+pub fn band(_vm: &mut VirtualMachine, args: Vec<Value>) -> Result<Value, RuntimeError> {
     if args.len() == 0 || args.len() > 2 {
-        return value(Value::Null);
+        return Ok(Value::Null);
     }
     let x: &Value = &args[0];
     let y: &Value = &args[1];
     match (x, y) {
-        (Value::Int(i1), Value::Int(i2)) => return value(Value::Int(i1 << i2)),
-        _ => return value(Value::Null),
+        (Value::Int(i1), Value::Int(i2)) => return Ok(Value::Int(i1 & i2)),
+        _ => return Ok(Value::Null),
     }
 }
 
-pub fn band(_vm: &mut VirtualMachine, args: Vec<Value>) -> Value {
+pub fn bor(_vm: &mut VirtualMachine, args: Vec<Value>) -> Result<Value, RuntimeError> {
+    if args.len() == 0 || args.len() > 2 {
+        return Ok(Value::Null);
+    }
+    let x: &Value = &args[0];
+    let y: &Value = &args[1];
+    match (x, y) {
+        (Value::Int(i1), Value::Int(i2)) => return Ok(Value::Int(i1 | i2)),
+        _ => return Ok(Value::Null),
+    }
+}
+pub fn bxor(_vm: &mut VirtualMachine, args: Vec<Value>) -> Result<Value, RuntimeError> {
     if args.len() == 0 || args.len() > 2 {
-        return value(Value::Null);
+        return Ok(Value::Null);
     }
     let x: &Value = &args[0];
     let y: &Value = &args[1];
     match (x, y) {
-        (Value::Int(i1), Value::Int(i2)) => return value(Value::Int(i1 & i2)),
-        _ => return value(Value::Null),
+        (Value::Int(i1), Value::Int(i2)) => return Ok(Value::Int(i1 | i2)),
+        _ => return Ok(Value::Null),
     }
 }
 
-pub fn bor(_vm: &mut VirtualMachine, args: Vec<Value>) -> Value {
+pub fn gt(vm: &mut VirtualMachine, args: Vec<Value>) -> Result<Value, RuntimeError> {
     if args.len() == 0 || args.len() > 2 {
-        return value(Value::Null);
+        return Ok(Value::Null);
     }
     let x: &Value = &args[0];
     let y: &Value = &args[1];
     match (x, y) {
-        (Value::Int(i1), Value::Int(i2)) => return value(Value::Int(i1 | i2)),
-        _ => return value(Value::Null),
+        (Value::Float(f), v2) => {
+            return Ok(Value::Bool(f64::from_bits(*f) > v2.as_f64(vm)?));
+        }
+        (Value::Int(i), Value::Float(f2)) => {
+            return Ok(Value::Bool(*i as f64 > f64::from_bits(*f2)));
+        }
+        (Value::Int(i), v2) => return Ok(Value::Bool(*i > v2.as_int(vm)?)),
+        //(Value::Array(a1), Value::Array(a2)) => return Ok(Value::Bool(a1.len() > a2.len())),
+        (Value::Str(s1), Value::Str(s2)) => return Ok(Value::Bool(s1 > s2)),
+        _ => return Err(unsupported(">", x, y, vm)),
     }
 }
-pub fn bxor(_vm: &mut VirtualMachine, args: Vec<Value>) -> Value {
+
+pub fn lt(vm: &mut VirtualMachine, args: Vec<Value>) -> Result<Value, RuntimeError> {
     if args.len() == 0 || args.len() > 2 {
-        return value(Value::Null);
+        return Ok(Value::Null);
     }
     let x: &Value = &args[0];
     let y: &Value = &args[1];
     match (x, y) {
-        (Value::Int(i1), Value::Int(i2)) => return value(Value::Int(i1 | i2)),
-        _ => return value(Value::Null),
+        (Value::Float(f), v2) => {
+            return Ok(Value::Bool(f64::from_bits(*f) < v2.as_f64(vm)?));
+        }
+        (Value::Int(i), Value::Float(f2)) => {
+            return Ok(Value::Bool((*i as f64) < f64::from_bits(*f2)));
+        }
+        (Value::Int(i), v2) => return Ok(Value::Bool(*i < v2.as_int(vm)?)),
+        //(Value::Array(a1), Value::Array(a2)) => return Ok(Value::Bool(a1.len() < a2.len())),
+        (Value::Str(s1), Value::Str(s2)) => return Ok(Value::Bool(s1 < s2)),
+        _ => return Err(unsupported("<", x, y, vm)),
     }
 }
 
-pub fn gt(vm: &mut VirtualMachine, args: Vec<Value>) -> Value {
+pub fn ge(vm: &mut VirtualMachine, args: Vec<Value>) -> Result<Value, RuntimeError> {
     if args.len() == 0 || args.len() > 2 {
-        return value(Value::Null);
+        return Ok(Value::Null);
     }
     let x: &Value = &args[0];
     let y: &Value = &args[1];
     match (x, y) {
         (Value::Float(f), v2) => {
-            return value(Value::Bool(f64::from_bits(*f) > v2.as_f64(vm)));
+            return Ok(Value::Bool(f64::from_bits(*f) >= v2.as_f64(vm)?));
         }
         (Value::Int(i), Value::Float(f2)) => {
-            return value(Value::Bool(*i as f64 > f64::from_bits(*f2)));
+            return Ok(Value::Bool(*i as f64 >= f64::from_bits(*f2)));
         }
-        (Value::Int(i), v2) => return value(Value::Bool(*i > v2.as_int(vm))),
-        //(Value::Array(a1), Value::Array(a2)) => return value(Value::Bool(a1.len() > a2.len())),
-        (Value::Str(s1), Value::Str(s2)) => return value(Value::Bool(s1 < s2)),
-        _ => panic!(""),
+        (Value::Int(i), v2) => return Ok(Value::Bool(*i >= v2.as_int(vm)?)),
+        (Value::Str(s1), Value::Str(s2)) => return Ok(Value::Bool(s1 >= s2)),
+        _ => return Err(unsupported(">=", x, y, vm)),
     }
 }
 
-pub fn lt(vm: &mut VirtualMachine, args: Vec<Value>) -> Value {
+pub fn le(vm: &mut VirtualMachine, args: Vec<Value>) -> Result<Value, RuntimeError> {
     if args.len() == 0 || args.len() > 2 {
-        return value(Value::Null);
+        return Ok(Value::Null);
     }
     let x: &Value = &args[0];
     let y: &Value = &args[1];
     match (x, y) {
         (Value::Float(f), v2) => {
-            return value(Value::Bool(f64::from_bits(*f) < v2.as_f64(vm)));
+            return Ok(Value::Bool(f64::from_bits(*f) <= v2.as_f64(vm)?));
         }
         (Value::Int(i), Value::Float(f2)) => {
-            return value(Value::Bool((*i as f64) < f64::from_bits(*f2)));
+            return Ok(Value::Bool((*i as f64) <= f64::from_bits(*f2)));
         }
-        (Value::Int(i), v2) => return value(Value::Bool(*i < v2.as_int(vm))),
-        //(Value::Array(a1), Value::Array(a2)) => return value(Value::Bool(a1.len() < a2.len())),
-        (Value::Str(s1), Value::Str(s2)) => return value(Value::Bool(s1 < s2)),
-        _ => panic!(""),
+        (Value::Int(i), v2) => return Ok(Value::Bool(*i <= v2.as_int(vm)?)),
+        (Value::Str(s1), Value::Str(s2)) => return Ok(Value::Bool(s1 <= s2)),
+        _ => return Err(unsupported("<=", x, y, vm)),
     }
 }
 
-pub fn eq(vm: &mut VirtualMachine, args: Vec<Value>) -> Value {
+pub fn eq(vm: &mut VirtualMachine, args: Vec<Value>) -> Result<Value, RuntimeError> {
     if args.len() == 0 || args.len() > 2 {
-        return Value::Null;
+        return Ok(Value::Null);
     }
     let x: &Value = &args[0];
     let y: &Value = &args[1];
     match (x, y) {
         (Value::Float(f), v2) => {
-            return Value::Bool(f64::from_bits(*f) == v2.as_f64(vm));
+            return Ok(Value::Bool(f64::from_bits(*f) == v2.as_f64(vm)?));
         }
         (Value::Int(i), Value::Float(f2)) => {
-            return Value::Bool((*i as f64) == f64::from_bits(*f2));
+            return Ok(Value::Bool((*i as f64) == f64::from_bits(*f2)));
         }
-        (Value::Int(i), v2) => return value(Value::Bool(*i == v2.as_int(vm))),
-        (Value::Array(a1), Value::Array(a2)) => return Value::Bool(a1 == a2),
-        (Value::Str(s1), Value::Str(s2)) => return Value::Bool(s1 == s2),
+        (Value::Int(i), v2) => return Ok(Value::Bool(*i == v2.as_int(vm)?)),
+        (Value::Array(a1), Value::Array(a2)) => return Ok(Value::Bool(a1 == a2)),
+        (Value::Str(s1), Value::Str(s2)) => return Ok(Value::Bool(s1 == s2)),
+        (Value::Bool(b1), Value::Bool(b2)) => return Ok(Value::Bool(b1 == b2)),
+        (Value::Variant(..), Value::Variant(..)) => return Ok(Value::Bool(x == y)),
 
-        v => panic!("{:?}",v),
+        _ => return Err(unsupported("==", x, y, vm)),
     }
 }
 
-pub fn neq(vm: &mut VirtualMachine, args: Vec<Value>) -> Value {
+pub fn neq(vm: &mut VirtualMachine, args: Vec<Value>) -> Result<Value, RuntimeError> {
     if args.len() == 0 || args.len() > 2 {
-        return Value::Null;
+        return Ok(Value::Null);
     }
     let x: &Value = &args[0];
     let y: &Value = &args[1];
     match (x, y) {
         (Value::Float(f), v2) => {
-            return Value::Bool(f64::from_bits(*f) != v2.as_f64(vm));
+            return Ok(Value::Bool(f64::from_bits(*f) != v2.as_f64(vm)?));
         }
         (Value::Int(i), Value::Float(f2)) => {
-            return Value::Bool((*i as f64) != f64::from_bits(*f2));
+            return Ok(Value::Bool((*i as f64) != f64::from_bits(*f2)));
         }
-        (Value::Int(i), v2) => return Value::Bool(*i != v2.as_int(vm)),
-        (Value::Array(a1), Value::Array(a2)) => return Value::Bool(a1 != a2),
-        (Value::Str(s1), Value::Str(s2)) => return Value::Bool(s1 != s2),
-        (v1, Value::Int(i)) => return Value::Bool(*i != v1.as_int(vm)),
-        (v1, Value::Float(f)) => return Value::Bool(f64::from_bits(*f) != v1.as_f64(vm)),
-        _ => panic!(""),
+        (Value::Int(i), v2) => return Ok(Value::Bool(*i != v2.as_int(vm)?)),
+        (Value::Array(a1), Value::Array(a2)) => return Ok(Value::Bool(a1 != a2)),
+        (Value::Str(s1), Value::Str(s2)) => return Ok(Value::Bool(s1 != s2)),
+        (Value::Bool(b1), Value::Bool(b2)) => return Ok(Value::Bool(b1 != b2)),
+        (Value::Variant(..), Value::Variant(..)) => return Ok(Value::Bool(x != y)),
+        (v1, Value::Int(i)) => return Ok(Value::Bool(*i != v1.as_int(vm)?)),
+        (v1, Value::Float(f)) => return Ok(Value::Bool(f64::from_bits(*f) != v1.as_f64(vm)?)),
+        _ => return Err(unsupported("!=", x, y, vm)),
     }
 }
 
-pub fn or(vm: &mut VirtualMachine, args: Vec<Value>) -> Value {
+pub fn or(vm: &mut VirtualMachine, args: Vec<Value>) -> Result<Value, RuntimeError> {
     if args.len() == 0 || args.len() > 2 {
-        return Value::Null;
+        return Ok(Value::Null);
     }
     let x: &Value = &args[0];
     let y: &Value = &args[1];
 
     match (x, y) {
-        (Value::Bool(b1), Value::Bool(b2)) => return Value::Bool(*b1 || *b2),
-        _ => return Value::Null,
+        (Value::Bool(b1), Value::Bool(b2)) => return Ok(Value::Bool(*b1 || *b2)),
+        _ => return Ok(Value::Null),
     }
 }
 
-pub fn and(vm: &mut VirtualMachine, args: Vec<Value>) -> Value {
+pub fn and(vm: &mut VirtualMachine, args: Vec<Value>) -> Result<Value, RuntimeError> {
     if args.len() == 0 || args.len() > 2 {
-        return Value::Null;
+        return Ok(Value::Null);
     }
     let x: &Value = &args[0];
     let y: &Value = &args[1];
 
     match (x, y) {
-        (Value::Bool(b1), Value::Bool(b2)) => return Value::Bool(*b1 && *b2),
-        _ => return Value::Null,
+        (Value::Bool(b1), Value::Bool(b2)) => return Ok(Value::Bool(*b1 && *b2)),
+        _ => return Ok(Value::Null),
     }
 }
 
-pub fn new_obj(vm: &mut VirtualMachine, _args: Vec<Value>) -> Value {
-    return Value::ObjectRef(vm.new_object());
+pub fn new_obj(vm: &mut VirtualMachine, _args: Vec<Value>) -> Result<Value, RuntimeError> {
+    return Ok(Value::ObjectRef(vm.new_object()));
 }
@@ -0,0 +1,1035 @@
+//! Compiled programs stored in `.jmlc` files, loaded back without lexing,
+//! parsing or type checking.
+//!
+//! A file holds, little endian:
+//!
+//! - the magic bytes `JMLC` and the format `VERSION`
+//! - a pool of every string used below, which refers to them by index
+//! - a table of the functions the code uses. Script functions are written
+//!   whole, natives only by the global they're bound to and looked up again
+//!   when loading. `PushFunc` and `MakeClosure` refer to functions by index
+//!   in the table, as function ids differ from one VM to the next
+//! - the units: top level code of the imported modules, then of the program
+
+use super::frame::Frame;
+use super::lexer::Pos;
+use super::opcodes::Opcode;
+use super::value::{Capture, FuncKind, LineTable, Value, VAR_ARGS};
+use super::visitor::{Type, TypeMode, TypeNode};
+use super::vm::VirtualMachine;
+
+use fnv::FnvHashMap;
+use std::fmt;
+use std::mem;
+use std::rc::Rc;
+
+pub const MAGIC: &[u8; 4] = b"JMLC";
+/// Bumped whenever the encoding changes, files of other versions are rejected
+pub const VERSION: u32 = 3;
+
+/// Top level code of the program or of a module it imports
+#[derive(Clone, Debug)]
+pub struct Unit {
+    /// Source file, which the module object is bound to
+    pub file: String,
+    /// Bindings held by the module object, none for the program itself
+    pub exports: Option<Vec<String>>,
+    /// Natives declared with `extern`, with their signatures
+    pub externs: Vec<(String, Type, Pos)>,
+    pub code: Vec<Opcode>,
+    pub lines: LineTable,
+}
+
+/// Why code couldn't be written or read back
+#[derive(Debug, Clone, PartialEq)]
+pub struct BytecodeError {
+    pub message: String,
+}
+
+impl BytecodeError {
+    pub fn new<T: Into<String>>(message: T) -> Self {
+        BytecodeError {
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for BytecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+/// Encodes `units` and every function they reach from `vm`
+pub fn write(vm: &VirtualMachine, units: &[Unit]) -> Result<Vec<u8>, BytecodeError> {
+    let mut writer = Writer {
+        vm,
+        out: vec![],
+        strings: vec![],
+        string_ids: FnvHashMap::default(),
+        functions: vec![],
+        function_ids: FnvHashMap::default(),
+    };
+
+    writer.usize(units.len());
+    for unit in units.iter() {
+        writer.unit(unit)?;
+    }
+    let units = mem::take(&mut writer.out);
+
+    // writing a function may find more of them
+    let mut i = 0;
+    while i < writer.functions.len() {
+        let id = writer.functions[i];
+        writer.function(id)?;
+        i += 1;
+    }
+    let functions = mem::take(&mut writer.out);
+
+    let mut out = MAGIC.to_vec();
+    out.extend_from_slice(&VERSION.to_le_bytes());
+    let strings = mem::take(&mut writer.strings);
+    writer.usize(strings.len());
+    for s in strings.iter() {
+        writer.usize(s.len());
+        writer.out.extend_from_slice(s.as_bytes());
+    }
+    writer.usize(writer.functions.len());
+    out.extend(writer.out);
+    out.extend(functions);
+    out.extend(units);
+
+    Ok(out)
+}
+
+/// Decodes units written by `write`, adding their functions to `vm`
+pub fn read(vm: &mut VirtualMachine, bytes: &[u8]) -> Result<Vec<Unit>, BytecodeError> {
+    if bytes.len() < 8 || &bytes[..4] != MAGIC {
+        return Err(BytecodeError::new("not a compiled JazzML file"));
+    }
+
+    let mut reader = Reader {
+        bytes,
+        pos: 4,
+        strings: vec![],
+    };
+    let version = reader.u32()?;
+    if version != VERSION {
+        return Err(BytecodeError::new(format!(
+            "compiled for bytecode version {}, this VM reads version {}",
+            version, VERSION
+        )));
+    }
+
+    let nstrings = reader.usize()?;
+    for _ in 0..nstrings {
+        let len = reader.usize()?;
+        let bytes = reader.take(len)?.to_vec();
+        let string = String::from_utf8(bytes)
+            .map_err(|_| BytecodeError::new("invalid UTF-8 in string pool"))?;
+        reader.strings.push(string);
+    }
+
+    let nfunctions = reader.usize()?;
+    let mut entries = Vec::new();
+    for _ in 0..nfunctions {
+        entries.push(reader.function()?);
+    }
+
+    let nunits = reader.usize()?;
+    let mut units = Vec::new();
+    for _ in 0..nunits {
+        units.push(reader.unit()?);
+    }
+    if reader.pos != bytes.len() {
+        return Err(BytecodeError::new("trailing bytes after the last unit"));
+    }
+    validate(&entries, &units)?;
+
+    // natives are resolved first so that a missing one leaves `vm` untouched
+    let mut natives = FnvHashMap::default();
+    for (i, entry) in entries.iter().enumerate() {
+        if let Entry::Native(ref name) = entry {
+            natives.insert(i, native(vm, name)?);
+        }
+    }
+
+    let ids = entries
+        .iter()
+        .enumerate()
+        .map(|(i, entry)| match entry {
+            Entry::Native(_) => natives[&i],
+            Entry::Script { nargs, args, .. } => vm.new_func(*nargs, args.clone()),
+        })
+        .collect::<Vec<usize>>();
+
+    for (entry, id) in entries.into_iter().zip(ids.iter()) {
+        if let Entry::Script {
+            name,
+            captures,
+            locals,
+            code,
+            lines,
+            ..
+        } = entry
+        {
+            let code = relink(code, &ids)?;
+            let mut func = vm.get_func(id).borrow_mut();
+            func.name = name;
+            func.kind = FuncKind::Interpret(code);
+            func.captures = captures;
+            func.locals = locals;
+            func.lines = lines;
+        }
+    }
+
+    units
+        .into_iter()
+        .map(|unit| {
+            Ok(Unit {
+                code: relink(unit.code, &ids)?,
+                ..unit
+            })
+        })
+        .collect()
+}
+
+/// Most locals a function can have, slots being 16 bits
+pub const MAX_LOCALS: usize = 1 << 16;
+
+// the interpreter trusts slots and upvalue indices, so code reading past its
+// frame is rejected here instead of panicking when it runs
+fn validate(entries: &[Entry], units: &[Unit]) -> Result<(), BytecodeError> {
+    for entry in entries.iter() {
+        if let Entry::Script {
+            name,
+            nargs,
+            args,
+            captures,
+            locals,
+            code,
+            ..
+        } = entry
+        {
+            let invalid =
+                |message: String| BytecodeError::new(format!("function `{}`: {}", name, message));
+
+            if *locals > MAX_LOCALS {
+                return Err(invalid(format!("{} locals is too many", locals)));
+            }
+            if *nargs < VAR_ARGS {
+                return Err(invalid(format!("invalid number of arguments {}", nargs)));
+            }
+            let params = if *nargs == VAR_ARGS {
+                args.len()
+            } else {
+                args.len().max(*nargs as usize)
+            };
+            if *locals < params {
+                return Err(invalid(format!(
+                    "{} locals can't hold {} arguments",
+                    locals, params
+                )));
+            }
+
+            check_operands(code, *locals, captures.len(), entries).map_err(invalid)?;
+        }
+    }
+
+    // top level code gets as many locals as its slots need and no upvalues
+    for unit in units.iter() {
+        let locals = Frame::slots_used(&unit.code);
+        check_operands(&unit.code, locals, 0, entries)
+            .map_err(|message| BytecodeError::new(format!("`{}`: {}", unit.file, message)))?;
+    }
+
+    Ok(())
+}
+
+// operands of `code` must fit a frame of `locals` slots and `upvalues`
+// upvalues, as must the captures of the closures it makes
+fn check_operands(
+    code: &[Opcode],
+    locals: usize,
+    upvalues: usize,
+    entries: &[Entry],
+) -> Result<(), String> {
+    let slot = |slot: u16| {
+        if slot as usize >= locals {
+            return Err(format!("slot {} out of {} locals", slot, locals));
+        }
+        Ok(())
+    };
+    let upvalue = |i: usize| {
+        if i >= upvalues {
+            return Err(format!("upvalue {} out of {} captures", i, upvalues));
+        }
+        Ok(())
+    };
+
+    for op in code.iter() {
+        match *op {
+            Opcode::LoadSlot(s)
+            | Opcode::StoreSlot(s)
+            | Opcode::LoadCell(s)
+            | Opcode::StoreCell(s) => slot(s)?,
+            Opcode::LoadUpvalue(i) | Opcode::StoreUpvalue(i) => upvalue(i)?,
+            Opcode::MakeClosure(i) => {
+                if let Some(Entry::Script { captures, .. }) = entries.get(i) {
+                    for capture in captures.iter() {
+                        match *capture {
+                            Capture::Local(s) => slot(s)?,
+                            Capture::Upvalue(i) => upvalue(i)?,
+                        }
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+
+    Ok(())
+}
+
+// id of the native function bound to the global `name`
+fn native(vm: &VirtualMachine, name: &str) -> Result<usize, BytecodeError> {
+    if let Some(Value::FuncRef(id)) = vm.globals.get(&Value::Str(name.to_owned())) {
+        if let FuncKind::Native(_) = vm.get_func(id).borrow().kind {
+            return Ok(*id);
+        }
+    }
+
+    Err(BytecodeError::new(format!(
+        "native function `{}` isn't registered",
+        name
+    )))
+}
+
+// replaces function table indices with the ids the functions got
+fn relink(code: Vec<Opcode>, ids: &[usize]) -> Result<Vec<Opcode>, BytecodeError> {
+    let id = |i: usize| {
+        ids.get(i)
+            .cloned()
+            .ok_or_else(|| BytecodeError::new(format!("no function {} in the table", i)))
+    };
+
+    code.into_iter()
+        .map(|op| match op {
+            Opcode::PushFunc(i) => Ok(Opcode::PushFunc(id(i)?)),
+            Opcode::MakeClosure(i) => Ok(Opcode::MakeClosure(id(i)?)),
+            op => Ok(op),
+        })
+        .collect()
+}
+
+/// Function as read from the table, before it gets an id
+enum Entry {
+    Native(String),
+    Script {
+        name: String,
+        nargs: i32,
+        args: Vec<String>,
+        captures: Vec<Capture>,
+        locals: usize,
+        code: Vec<Opcode>,
+        lines: LineTable,
+    },
+}
+
+const NATIVE: u8 = 0;
+const SCRIPT: u8 = 1;
+
+struct Writer<'a> {
+    vm: &'a VirtualMachine,
+    out: Vec<u8>,
+    strings: Vec<String>,
+    string_ids: FnvHashMap<String, usize>,
+    /// Ids of the functions to write, in table order
+    functions: Vec<usize>,
+    function_ids: FnvHashMap<usize, usize>,
+}
+
+impl<'a> Writer<'a> {
+    fn u8(&mut self, n: u8) {
+        self.out.push(n)
+    }
+
+    fn u32(&mut self, n: u32) {
+        self.out.extend_from_slice(&n.to_le_bytes())
+    }
+
+    fn u64(&mut self, n: u64) {
+        self.out.extend_from_slice(&n.to_le_bytes())
+    }
+
+    fn usize(&mut self, n: usize) {
+        self.u64(n as u64)
+    }
+
+    fn i32(&mut self, n: i32) {
+        self.u32(n as u32)
+    }
+
+    fn bool(&mut self, b: bool) {
+        self.u8(b as u8)
+    }
+
+    fn str(&mut self, s: &str) {
+        let id = match self.string_ids.get(s) {
+            Some(&id) => id,
+            None => {
+                let id = self.strings.len();
+                self.strings.push(s.to_owned());
+                self.string_ids.insert(s.to_owned(), id);
+                id
+            }
+        };
+        self.usize(id)
+    }
+
+    fn strs(&mut self, strs: &[String]) {
+        self.usize(strs.len());
+        for s in strs.iter() {
+            self.str(s);
+        }
+    }
+
+    // index of function `id` in the table, adding it if needed
+    fn function_index(&mut self, id: usize) -> usize {
+        match self.function_ids.get(&id) {
+            Some(&i) => i,
+            None => {
+                let i = self.functions.len();
+                self.functions.push(id);
+                self.function_ids.insert(id, i);
+                i
+            }
+        }
+    }
+
+    fn function(&mut self, id: usize) -> Result<(), BytecodeError> {
+        let func = self.vm.get_func(&id).borrow().clone();
+
+        match func.kind {
+            FuncKind::Native(_) => {
+                self.u8(NATIVE);
+                self.str(&func.name);
+            }
+            FuncKind::Interpret(ref code) => {
+                self.u8(SCRIPT);
+                self.str(&func.name);
+                self.i32(func.nargs);
+                self.strs(&func.args);
+                self.usize(func.captures.len());
+                for capture in func.captures.iter() {
+                    match capture {
+                        Capture::Local(slot) => {
+                            self.u8(0);
+                            self.u32(u32::from(*slot));
+                        }
+                        Capture::Upvalue(i) => {
+                            self.u8(1);
+                            self.usize(*i);
+                        }
+                    }
+                }
+                self.usize(func.locals);
+                self.code(code)?;
+                self.lines(&func.lines);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn unit(&mut self, unit: &Unit) -> Result<(), BytecodeError> {
+        self.str(&unit.file);
+        match unit.exports {
+            Some(ref exports) => {
+                self.bool(true);
+                self.strs(exports);
+            }
+            None => self.bool(false),
+        }
+        self.usize(unit.externs.len());
+        for (name, t, pos) in unit.externs.iter() {
+            self.str(name);
+            self.ty(t);
+            self.pos(pos);
+        }
+        self.code(&unit.code)?;
+        self.lines(&unit.lines);
+
+        Ok(())
+    }
+
+    // types the host can register natives with, others are written as `any`
+    fn ty(&mut self, t: &Type) {
+        self.bool(t.is_splat());
+        match t.node {
+            TypeNode::Int => self.u8(1),
+            TypeNode::Float => self.u8(2),
+            TypeNode::Bool => self.u8(3),
+            TypeNode::Str => self.u8(4),
+            TypeNode::Char => self.u8(5),
+            TypeNode::Nil => self.u8(6),
+            TypeNode::Array(ref element, len) => {
+                self.u8(7);
+                self.ty(element);
+                match len {
+                    Some(len) => {
+                        self.bool(true);
+                        self.usize(len);
+                    }
+                    None => self.bool(false),
+                }
+            }
+            TypeNode::Map(ref key, ref value) => {
+                self.u8(8);
+                self.ty(key);
+                self.ty(value);
+            }
+            TypeNode::Func(ref params, ref retty, ..) => {
+                self.u8(9);
+                self.usize(params.len());
+                for param in params.iter() {
+                    self.ty(param);
+                }
+                self.ty(retty);
+            }
+            _ => self.u8(0),
+        }
+    }
+
+    fn pos(&mut self, pos: &Pos) {
+        self.usize((pos.0).0);
+        self.str(&(pos.0).1);
+        self.usize((pos.1).0);
+        self.usize((pos.1).1);
+    }
+
+    fn lines(&mut self, lines: &LineTable) {
+        self.str(&lines.file);
+        self.usize(lines.entries().len());
+        for (pc, pos) in lines.entries().iter() {
+            self.usize(*pc);
+            self.pos(pos);
+        }
+    }
+
+    fn constant(&mut self, value: &Value) -> Result<(), BytecodeError> {
+        match value {
+            Value::Null => self.u8(0),
+            Value::Int(n) => {
+                self.u8(1);
+                self.u64(*n as u64);
+            }
+            Value::Float(bits) => {
+                self.u8(2);
+                self.u64(*bits);
+            }
+            Value::Bool(b) => {
+                self.u8(3);
+                self.bool(*b);
+            }
+            Value::Str(s) => {
+                self.u8(4);
+                self.str(s);
+            }
+            value => {
+                return Err(BytecodeError::new(format!(
+                    "can't write constant `{:?}`",
+                    value
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn code(&mut self, code: &[Opcode]) -> Result<(), BytecodeError> {
+        self.usize(code.len());
+        for op in code.iter() {
+            self.op(op)?;
+        }
+
+        Ok(())
+    }
+
+    fn op(&mut self, op: &Opcode) -> Result<(), BytecodeError> {
+        use self::Opcode::*;
+
+        match op {
+            PushInt(n) => {
+                self.u8(0);
+                self.u64(*n as u64);
+            }
+            PushFloat(f) => {
+                self.u8(1);
+                self.u64(f.to_bits());
+            }
+            PushObject(_) => return Err(BytecodeError::new("can't write object constants")),
+            PushFunc(id) => {
+                self.u8(2);
+                let i = self.function_index(*id);
+                self.usize(i);
+            }
+            MakeClosure(id) => {
+                self.u8(3);
+                let i = self.function_index(*id);
+                self.usize(i);
+            }
+            PushStr(s) => {
+                self.u8(4);
+                self.str(s);
+            }
+            PushNull => self.u8(5),
+            PushBool(b) => {
+                self.u8(6);
+                self.bool(*b);
+            }
+            Pop => self.u8(7),
+            Amake(n) => {
+                self.u8(8);
+                self.usize(*n);
+            }
+            Mmake(n) => {
+                self.u8(9);
+                self.usize(*n);
+            }
+            Aget => self.u8(10),
+            Aset => self.u8(11),
+            Slice => self.u8(12),
+            Range => self.u8(13),
+            MakeStruct(name, fields) => {
+                self.u8(14);
+                self.str(name);
+                self.usize(fields.len());
+                for (field, has_default) in fields.iter() {
+                    self.str(field);
+                    self.bool(*has_default);
+                }
+            }
+            Construct(n) => {
+                self.u8(15);
+                self.usize(*n);
+            }
+            MakeEnum(name, variants) => {
+                self.u8(16);
+                self.str(name);
+                self.strs(variants);
+            }
+            CallObj(n) => {
+                self.u8(17);
+                self.usize(*n);
+            }
+            Call(n) => {
+                self.u8(18);
+                self.usize(*n);
+            }
+            TailCall(n) => {
+                self.u8(19);
+                self.usize(*n);
+            }
+            Add => self.u8(20),
+            Sub => self.u8(21),
+            Div => self.u8(22),
+            Mul => self.u8(23),
+            Rem => self.u8(24),
+            Pow => self.u8(25),
+            Concat => self.u8(26),
+            Bxor => self.u8(27),
+            Bor => self.u8(28),
+            Band => self.u8(29),
+            And => self.u8(30),
+            Nop => self.u8(31),
+            Or => self.u8(32),
+            Shr => self.u8(33),
+            Shl => self.u8(34),
+            Lt => self.u8(35),
+            Gt => self.u8(36),
+            Le => self.u8(37),
+            Ge => self.u8(38),
+            Eq => self.u8(39),
+            Neq => self.u8(40),
+            Neg => self.u8(41),
+            Not => self.u8(42),
+            JmpF(pc) => {
+                self.u8(43);
+                self.usize(*pc);
+            }
+            JmpT(pc) => {
+                self.u8(44);
+                self.usize(*pc);
+            }
+            Jmp(pc) => {
+                self.u8(45);
+                self.usize(*pc);
+            }
+            JmpTable(table, default) => {
+                self.u8(46);
+                self.usize(table.len());
+                for (value, pc) in table.iter() {
+                    self.constant(value)?;
+                    self.usize(*pc);
+                }
+                self.usize(*default);
+            }
+            JmpVariant(table, default) => {
+                self.u8(47);
+                self.usize(table.len());
+                for (variant, pc) in table.iter() {
+                    self.str(variant);
+                    self.usize(*pc);
+                }
+                self.usize(*default);
+            }
+            MatchArray(n) => {
+                self.u8(48);
+                self.usize(*n);
+            }
+            MatchStruct => self.u8(49),
+            Iter => self.u8(50),
+            IterNext(pairs) => {
+                self.u8(51);
+                self.bool(*pairs);
+            }
+            StoreField => self.u8(52),
+            LoadField => self.u8(53),
+            LoadGlobal => self.u8(54),
+            StoreGlobal => self.u8(55),
+            LoadSlot(slot) => {
+                self.u8(56);
+                self.u32(u32::from(*slot));
+            }
+            StoreSlot(slot) => {
+                self.u8(57);
+                self.u32(u32::from(*slot));
+            }
+            LoadCell(slot) => {
+                self.u8(58);
+                self.u32(u32::from(*slot));
+            }
+            StoreCell(slot) => {
+                self.u8(59);
+                self.u32(u32::from(*slot));
+            }
+            LoadUpvalue(i) => {
+                self.u8(60);
+                self.usize(*i);
+            }
+            StoreUpvalue(i) => {
+                self.u8(61);
+                self.usize(*i);
+            }
+            Ret => self.u8(62),
+        }
+
+        Ok(())
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    strings: Vec<String>,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], BytecodeError> {
+        if self.bytes.len() - self.pos < n {
+            return Err(BytecodeError::new("unexpected end of bytecode"));
+        }
+
+        let bytes = &self.bytes[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, BytecodeError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, BytecodeError> {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    fn u64(&mut self) -> Result<u64, BytecodeError> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    fn usize(&mut self) -> Result<usize, BytecodeError> {
+        Ok(self.u64()? as usize)
+    }
+
+    fn i32(&mut self) -> Result<i32, BytecodeError> {
+        Ok(self.u32()? as i32)
+    }
+
+    fn u16(&mut self) -> Result<u16, BytecodeError> {
+        let n = self.u32()?;
+        if n > u32::from(u16::MAX) {
+            return Err(BytecodeError::new(format!("slot {} out of range", n)));
+        }
+        Ok(n as u16)
+    }
+
+    fn bool(&mut self) -> Result<bool, BytecodeError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            b => Err(BytecodeError::new(format!("invalid bool {}", b))),
+        }
+    }
+
+    fn str(&mut self) -> Result<String, BytecodeError> {
+        let id = self.usize()?;
+        self.strings
+            .get(id)
+            .cloned()
+            .ok_or_else(|| BytecodeError::new(format!("no string {} in the pool", id)))
+    }
+
+    fn strs(&mut self) -> Result<Vec<String>, BytecodeError> {
+        let len = self.usize()?;
+        (0..len).map(|_| self.str()).collect()
+    }
+
+    fn function(&mut self) -> Result<Entry, BytecodeError> {
+        match self.u8()? {
+            NATIVE => Ok(Entry::Native(self.str()?)),
+            SCRIPT => {
+                let name = self.str()?;
+                let nargs = self.i32()?;
+                let args = self.strs()?;
+                let ncaptures = self.usize()?;
+                let mut captures = Vec::new();
+                for _ in 0..ncaptures {
+                    captures.push(match self.u8()? {
+                        0 => Capture::Local(self.u16()?),
+                        1 => Capture::Upvalue(self.usize()?),
+                        tag => {
+                            return Err(BytecodeError::new(format!("invalid capture {}", tag)));
+                        }
+                    });
+                }
+                let locals = self.usize()?;
+                let code = self.code()?;
+                let lines = self.lines()?;
+
+                Ok(Entry::Script {
+                    name,
+                    nargs,
+                    args,
+                    captures,
+                    locals,
+                    code,
+                    lines,
+                })
+            }
+            tag => Err(BytecodeError::new(format!("invalid function kind {}", tag))),
+        }
+    }
+
+    fn unit(&mut self) -> Result<Unit, BytecodeError> {
+        let file = self.str()?;
+        let exports = if self.bool()? {
+            Some(self.strs()?)
+        } else {
+            None
+        };
+        let nexterns = self.usize()?;
+        let mut externs = Vec::new();
+        for _ in 0..nexterns {
+            externs.push((self.str()?, self.ty()?, self.pos()?));
+        }
+        let code = self.code()?;
+        let lines = self.lines()?;
+
+        Ok(Unit {
+            file,
+            exports,
+            externs,
+            code,
+            lines,
+        })
+    }
+
+    fn ty(&mut self) -> Result<Type, BytecodeError> {
+        let splat = self.bool()?;
+        let node = match self.u8()? {
+            0 => TypeNode::Any,
+            1 => TypeNode::Int,
+            2 => TypeNode::Float,
+            3 => TypeNode::Bool,
+            4 => TypeNode::Str,
+            5 => TypeNode::Char,
+            6 => TypeNode::Nil,
+            7 => {
+                let element = self.ty()?;
+                let len = if self.bool()? {
+                    Some(self.usize()?)
+                } else {
+                    None
+                };
+                TypeNode::Array(Rc::new(element), len)
+            }
+            8 => TypeNode::Map(Rc::new(self.ty()?), Rc::new(self.ty()?)),
+            9 => {
+                let nparams = self.usize()?;
+                let params = (0..nparams)
+                    .map(|_| self.ty())
+                    .collect::<Result<Vec<Type>, BytecodeError>>()?;
+                TypeNode::Func(params, Rc::new(self.ty()?), None, false)
+            }
+            tag => return Err(BytecodeError::new(format!("invalid type {}", tag))),
+        };
+
+        let mode = if splat {
+            TypeMode::Splat(None)
+        } else {
+            TypeMode::Regular
+        };
+        Ok(Type::new(node, mode))
+    }
+
+    fn pos(&mut self) -> Result<Pos, BytecodeError> {
+        let line = self.usize()?;
+        let text = self.str()?;
+        let start = self.usize()?;
+        let end = self.usize()?;
+        Ok(Pos((line, text), (start, end)))
+    }
+
+    fn lines(&mut self) -> Result<LineTable, BytecodeError> {
+        let mut lines = LineTable::default();
+        lines.file = self.str()?;
+        let len = self.usize()?;
+        for _ in 0..len {
+            let pc = self.usize()?;
+            lines.add(pc, self.pos()?);
+        }
+        Ok(lines)
+    }
+
+    fn constant(&mut self) -> Result<Value, BytecodeError> {
+        match self.u8()? {
+            0 => Ok(Value::Null),
+            1 => Ok(Value::Int(self.u64()? as i64)),
+            2 => Ok(Value::Float(self.u64()?)),
+            3 => Ok(Value::Bool(self.bool()?)),
+            4 => Ok(Value::Str(self.str()?)),
+            tag => Err(BytecodeError::new(format!("invalid constant {}", tag))),
+        }
+    }
+
+    fn code(&mut self) -> Result<Vec<Opcode>, BytecodeError> {
+        let len = self.usize()?;
+        let mut code = Vec::new();
+        for _ in 0..len {
+            code.push(self.op()?);
+        }
+        Ok(code)
+    }
+
+    fn op(&mut self) -> Result<Opcode, BytecodeError> {
+        use self::Opcode::*;
+
+        let op = match self.u8()? {
+            0 => PushInt(self.u64()? as i64),
+            1 => PushFloat(f64::from_bits(self.u64()?)),
+            2 => PushFunc(self.usize()?),
+            3 => MakeClosure(self.usize()?),
+            4 => PushStr(self.str()?),
+            5 => PushNull,
+            6 => PushBool(self.bool()?),
+            7 => Pop,
+            8 => Amake(self.usize()?),
+            9 => Mmake(self.usize()?),
+            10 => Aget,
+            11 => Aset,
+            12 => Slice,
+            13 => Range,
+            14 => {
+                let name = self.str()?;
+                let len = self.usize()?;
+                let mut fields = Vec::new();
+                for _ in 0..len {
+                    fields.push((self.str()?, self.bool()?));
+                }
+                MakeStruct(name, fields)
+            }
+            15 => Construct(self.usize()?),
+            16 => MakeEnum(self.str()?, self.strs()?),
+            17 => CallObj(self.usize()?),
+            18 => Call(self.usize()?),
+            19 => TailCall(self.usize()?),
+            20 => Add,
+            21 => Sub,
+            22 => Div,
+            23 => Mul,
+            24 => Rem,
+            25 => Pow,
+            26 => Concat,
+            27 => Bxor,
+            28 => Bor,
+            29 => Band,
+            30 => And,
+            31 => Nop,
+            32 => Or,
+            33 => Shr,
+            34 => Shl,
+            35 => Lt,
+            36 => Gt,
+            37 => Le,
+            38 => Ge,
+            39 => Eq,
+            40 => Neq,
+            41 => Neg,
+            42 => Not,
+            43 => JmpF(self.usize()?),
+            44 => JmpT(self.usize()?),
+            45 => Jmp(self.usize()?),
+            46 => {
+                let len = self.usize()?;
+                let mut table = FnvHashMap::default();
+                for _ in 0..len {
+                    let value = self.constant()?;
+                    table.insert(value, self.usize()?);
+                }
+                JmpTable(table, self.usize()?)
+            }
+            47 => {
+                let len = self.usize()?;
+                let mut table = FnvHashMap::default();
+                for _ in 0..len {
+                    let variant = self.str()?;
+                    table.insert(variant, self.usize()?);
+                }
+                JmpVariant(table, self.usize()?)
+            }
+            48 => MatchArray(self.usize()?),
+            49 => MatchStruct,
+            50 => Iter,
+            51 => IterNext(self.bool()?),
+            52 => StoreField,
+            53 => LoadField,
+            54 => LoadGlobal,
+            55 => StoreGlobal,
+            56 => LoadSlot(self.u16()?),
+            57 => StoreSlot(self.u16()?),
+            58 => LoadCell(self.u16()?),
+            59 => StoreCell(self.u16()?),
+            60 => LoadUpvalue(self.usize()?),
+            61 => StoreUpvalue(self.usize()?),
+            62 => Ret,
+            tag => return Err(BytecodeError::new(format!("invalid opcode {}", tag))),
+        };
+
+        Ok(op)
+    }
+}
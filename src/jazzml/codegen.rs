@@ -1,24 +1,56 @@
 use super::frame::Frame;
+use super::lexer::Pos;
 use super::opcodes::Opcode;
 use super::parser::*;
 use super::value::*;
 use super::visitor::ty::Type;
 use super::vm::VirtualMachine;
 
-use fnv::FnvHashMap;
+use fnv::{FnvHashMap, FnvHashSet};
+use std::rc::Rc;
 
 pub struct Compiler<'a> {
     pub ins: Vec<UOP>,
     pub func_def: FnvHashMap<String, usize>,
     pub vm: &'a mut VirtualMachine,
     pub labels: FnvHashMap<String, Option<usize>>,
-    end_label: Option<String>,
+    /// Loops being compiled, innermost last
+    loops: Vec<Loop>,
+    /// Slots of variables living in the frame being compiled
+    locals: FnvHashMap<String, u16>,
+    /// Locals captured by closures, accessed through cells
+    captured: FnvHashSet<String>,
+    /// Variables captured from enclosing functions, by upvalue index
+    upvalues: Vec<String>,
+    /// Top level `var`/`let` bindings are globals
+    top_level: bool,
+    /// Global key of the module being compiled, prefixing its globals. None
+    /// for the program
+    pub namespace: Option<String>,
+    /// Top level names of the module, whose globals get the prefix
+    module_globals: FnvHashSet<String>,
+    /// Exported names of the module, stored in the module object so importers
+    /// see their current values
+    module_exports: FnvHashSet<String>,
+    /// Global keys of the imported module objects, by import path
+    pub imports: FnvHashMap<String, String>,
+    /// Source positions of `ins`
+    pub lines: LineTable,
 }
 
 #[derive(Clone, Debug)]
 pub enum UOP {
     Op(Opcode),
     Goto(String),
+    GotoF(String),
+}
+
+/// Jump targets of a loop for `break` and `continue`
+struct Loop {
+    label: Option<String>,
+    /// Where `continue` jumps, before the next iteration's check
+    next: String,
+    end: String,
 }
 
 impl<'a> Compiler<'a> {
@@ -28,12 +60,22 @@ impl<'a> Compiler<'a> {
             func_def: FnvHashMap::default(),
             vm: vm,
             labels: FnvHashMap::default(),
-            end_label: None,
+            loops: vec![],
+            locals: FnvHashMap::default(),
+            captured: FnvHashSet::default(),
+            upvalues: vec![],
+            top_level: true,
+            namespace: None,
+            module_globals: FnvHashSet::default(),
+            module_exports: FnvHashSet::default(),
+            imports: FnvHashMap::default(),
+            lines: LineTable::default(),
         }
     }
 
+    /// Compiles top level code, against a VM whose builtins were registered
+    /// with `VirtualMachine::init_builtins`
     pub fn compile(&mut self, ast: Vec<Statement>) {
-        self.vm.init_builtins();
         let print = self
             .vm
             .globals
@@ -122,7 +164,30 @@ impl<'a> Compiler<'a> {
         if let Value::FuncRef(id) = obj {
             self.func_def.insert("chars".into(), *id);
         }
+        if self.top_level {
+            self.captured = Scope::of_program(&ast).captured();
+        }
+        if self.namespace.is_some() {
+            self.module_globals = ast
+                .iter()
+                .filter_map(|stmt| match stmt.node {
+                    StatementNode::Variable(.., ref name, _)
+                    | StatementNode::Import(_, ref name) => Some(name.clone()),
+                    _ => None,
+                })
+                .collect();
+            self.module_exports = ast
+                .iter()
+                .filter_map(|stmt| match stmt.node {
+                    StatementNode::Variable(.., ref name, _) if !name.starts_with('_') => {
+                        Some(name.clone())
+                    }
+                    _ => None,
+                })
+                .collect();
+        }
         for stmt in ast.iter() {
+            self.mark(&stmt.pos);
             self.stmt(stmt.node.clone());
         }
         self.emit(Opcode::PushNull);
@@ -143,6 +208,22 @@ impl<'a> Compiler<'a> {
         self.labels.insert(s, Some(self.ins.len()));
     }
 
+    /// Attributes the instructions emitted from here on to `pos`
+    pub fn mark(&mut self, pos: &Pos) {
+        self.lines.add(self.ins.len(), pos.clone());
+    }
+
+    /// Position covering both `from` and `to` when they share a line
+    fn span(from: &Pos, to: &Pos) -> Pos {
+        if (from.0).0 != (to.0).0 {
+            return from.clone();
+        }
+
+        let start = (from.1).0.min((to.1).0);
+        let end = (from.1).1.max((to.1).1);
+        Pos(from.0.clone(), (start, end))
+    }
+
     pub fn emit(&mut self, op: Opcode) {
         self.ins.push(UOP::Op(op));
     }
@@ -151,11 +232,20 @@ impl<'a> Compiler<'a> {
         self.ins.push(UOP::Goto(lbl.to_owned()));
     }
 
+    pub fn emit_goto_false(&mut self, lbl: &str) {
+        self.ins.push(UOP::GotoF(lbl.to_owned()));
+    }
+
     pub fn finish(&mut self) -> Vec<Opcode> {
+        if self.top_level {
+            self.vm.lines = self.lines.clone();
+        }
+
         self.ins
             .iter()
             .map(|e| match e {
                 &UOP::Goto(ref lbl) => Opcode::Jmp(self.labels.get(lbl).unwrap().unwrap()),
+                &UOP::GotoF(ref lbl) => Opcode::JmpF(self.labels.get(lbl).unwrap().unwrap()),
                 &UOP::Op(ref op) => op.clone(),
             })
             .collect::<Vec<Opcode>>()
@@ -166,8 +256,7 @@ impl<'a> Compiler<'a> {
             StatementNode::Expression(expr) => self.expr(expr.node),
             StatementNode::Return(r) => {
                 if r.is_some() {
-                    self.expr(r.unwrap().node.clone());
-                    self.emit(Opcode::Ret);
+                    self.tail(&r.unwrap());
                 } else {
                     self.emit(Opcode::PushNull);
                     self.emit(Opcode::Ret);
@@ -177,70 +266,611 @@ impl<'a> Compiler<'a> {
                 if init.is_some() {
                     let init = init.unwrap().clone();
                     if let ExpressionNode::Function(args, _, block, _) = init.node.clone() {
-                        let mut func = Function {
-                            args: args
-                                .iter()
-                                .map(|(name, _)| name.clone())
-                                .collect::<Vec<String>>(),
-                            nargs: args.len() as i32,
-                            kind: FuncKind::Interpret(vec![]),
-                        };
-
-                        let mut cmpl = Compiler::new(&mut self.vm);
-                        cmpl.func_def = self.func_def.clone();
-                        cmpl.compile(vec![Statement::new(
-                            StatementNode::Expression(block.as_ref().clone()),
-                            block.pos.clone(),
-                        )]);
-                        let ins = cmpl.finish();
-                        func.kind = FuncKind::Interpret(ins);
-
-                        self.func_def = cmpl.func_def;
-                        let id = self.vm.register_predefiend_func(func);
-                        self.func_def.insert(name.to_owned(), id);
-
-                        self.emit(Opcode::PushFunc(id));
+                        self.compile_function(Some(&name), &args, &block);
+                    } else if let ExpressionNode::Struct(fields, _) = init.node.clone() {
+                        self.compile_struct(&name, &fields);
+                    } else if let ExpressionNode::Enum(_, variants) = init.node.clone() {
+                        self.emit(Opcode::MakeEnum(name.clone(), variants));
                     } else {
                         self.expr(init.node);
                     }
                 } else {
                     self.emit(Opcode::PushNull);
                 }
-                self.emit(Opcode::PushStr(name));
-                self.emit(Opcode::StoreLocal);
+
+                if self.top_level {
+                    self.store_global(&name);
+                } else {
+                    self.store_local(&name);
+                }
+            }
+            StatementNode::Import(path, name) => {
+                let module = self.imports[&path].clone();
+                self.emit(Opcode::PushStr(module));
+                self.emit(Opcode::LoadGlobal);
+                self.store_global(&name);
             }
-            StatementNode::Break => {
-                let end = self.end_label.clone().unwrap().clone();
+            // linked by the engine, calls load the native like any global
+            StatementNode::Extern(..) => (),
+            StatementNode::Break(label) => {
+                let end = self.target_loop(&label).end.clone();
 
                 self.emit_goto(&end);
             }
+            StatementNode::Continue(label) => {
+                let next = self.target_loop(&label).next.clone();
+
+                self.emit_goto(&next);
+            }
             StatementNode::Assignment(to, val) => {
                 if let ExpressionNode::Index(a, b, is_arr) = to.node.clone() {
                     self.expr(val.node);
-                    match b.node.clone() {
-                        ExpressionNode::Identifier(ref name) => {
-                            if !is_arr {
-                                self.emit(Opcode::PushStr(name.to_owned()));
-                            } else {
-                                self.expr(ExpressionNode::Identifier(name.to_owned()));
-                            }
-                        }
-                        ExpressionNode::Index(_, _, _) => self.expr(b.node.clone()),
-                        ExpressionNode::Int(i) => self.emit(Opcode::PushInt(i as i64)),
-                        v => panic!("{:?}", v),
-                    };
+                    if is_arr {
+                        self.expr(b.node.clone());
+                    } else {
+                        self.field_key(b.node.clone());
+                    }
 
                     self.expr(a.node.clone());
-                    self.emit(Opcode::StoreField);
+                    self.mark(&Self::span(&a.pos, &b.pos));
+                    self.emit(if is_arr { Opcode::Aset } else { Opcode::StoreField });
                 } else {
                     self.expr(val.node);
                     if let ExpressionNode::Identifier(ref name) = to.node.clone() {
-                        self.emit(Opcode::PushStr(name.to_owned()));
+                        self.store_var(name);
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn compile_struct(&mut self, name: &str, fields: &[(String, Type, Option<Expression>)]) {
+        // defaults are functions, so every instance gets values of its own
+        for (_, _, default) in fields.iter().rev() {
+            if let Some(default) = default {
+                let ret = StatementNode::Return(Some(Rc::new(default.clone())));
+                let body = Expression::new(
+                    ExpressionNode::Block(vec![Statement::new(ret, default.pos.clone())]),
+                    default.pos.clone(),
+                );
+                self.compile_function(None, &[], &body);
+            }
+        }
+
+        self.emit(Opcode::MakeStruct(
+            name.to_owned(),
+            fields
+                .iter()
+                .map(|(field, _, default)| (field.clone(), default.is_some()))
+                .collect(),
+        ));
+    }
+
+    fn slot(&mut self, name: &str) -> u16 {
+        let next = self.locals.len() as u16;
+        *self.locals.entry(name.to_owned()).or_insert(next)
+    }
+
+    fn load_local(&mut self, name: &str) {
+        let slot = self.slot(name);
+        if self.captured.contains(name) {
+            self.emit(Opcode::LoadCell(slot));
+        } else {
+            self.emit(Opcode::LoadSlot(slot));
+        }
+    }
+
+    fn store_local(&mut self, name: &str) {
+        let slot = self.slot(name);
+        if self.captured.contains(name) {
+            self.emit(Opcode::StoreCell(slot));
+        } else {
+            self.emit(Opcode::StoreSlot(slot));
+        }
+    }
+
+    // variables resolve to locals, then upvalues, then known functions, then globals
+    fn load_var(&mut self, name: &str) {
+        if self.locals.contains_key(name) {
+            self.load_local(name);
+        } else if let Some(i) = self.upvalues.iter().position(|var| var == name) {
+            self.emit(Opcode::LoadUpvalue(i));
+        } else if let Some(&id) = self.func_def.get(name) {
+            self.emit(Opcode::PushFunc(id));
+        } else {
+            self.load_global(name);
+        }
+    }
+
+    fn store_var(&mut self, name: &str) {
+        if self.locals.contains_key(name) {
+            self.store_local(name);
+        } else if let Some(i) = self.upvalues.iter().position(|var| var == name) {
+            self.emit(Opcode::StoreUpvalue(i));
+        } else {
+            self.store_global(name);
+        }
+    }
+
+    fn load_global(&mut self, name: &str) {
+        match self.export_of(name) {
+            Some(module) => {
+                self.emit(Opcode::PushStr(name.to_owned()));
+                self.emit(Opcode::PushStr(module));
+                self.emit(Opcode::LoadGlobal);
+                self.emit(Opcode::LoadField);
+            }
+            None => {
+                let key = self.global_key(name);
+                self.emit(Opcode::PushStr(key));
+                self.emit(Opcode::LoadGlobal);
+            }
+        }
+    }
+
+    // stores the value on top of the stack
+    fn store_global(&mut self, name: &str) {
+        match self.export_of(name) {
+            Some(module) => {
+                self.emit(Opcode::PushStr(name.to_owned()));
+                self.emit(Opcode::PushStr(module));
+                self.emit(Opcode::LoadGlobal);
+                self.emit(Opcode::StoreField);
+            }
+            None => {
+                let key = self.global_key(name);
+                self.emit(Opcode::PushStr(key));
+                self.emit(Opcode::StoreGlobal);
+            }
+        }
+    }
+
+    // a field named by an identifier is that name, any other expression is
+    // the key it evaluates to
+    fn field_key(&mut self, field: ExpressionNode) {
+        match field {
+            ExpressionNode::Identifier(name) => self.emit(Opcode::PushStr(name)),
+            field => self.expr(field),
+        }
+    }
+
+    // globals of a module live under its prefix, builtins and host globals don't
+    fn global_key(&self, name: &str) -> String {
+        match self.namespace {
+            Some(ref module) if self.module_globals.contains(name) => {
+                format!("{}::{}", module, name)
+            }
+            _ => name.to_owned(),
+        }
+    }
+
+    // the global key of the module object holding `name`, if it's exported
+    fn export_of(&self, name: &str) -> Option<String> {
+        match self.namespace {
+            Some(ref module) if self.module_exports.contains(name) => Some(module.clone()),
+            _ => None,
+        }
+    }
+
+    /// Compiles a function literal, leaving it on the stack. Functions using
+    /// variables of enclosing functions become closures over those variables.
+    pub fn compile_function(
+        &mut self,
+        name: Option<&str>,
+        args: &[(String, Type)],
+        block: &Expression,
+    ) {
+        let scope = Scope::of_function(args, block);
+
+        let mut captured = vec![];
+        let mut captures = vec![];
+        let mut recursive = false;
+        for var in scope.free() {
+            if Some(var.as_str()) == name {
+                recursive = true;
+                continue;
+            }
+
+            if let Some(&slot) = self.locals.get(&var) {
+                captures.push(Capture::Local(slot));
+            } else if let Some(i) = self.upvalues.iter().position(|up| *up == var) {
+                captures.push(Capture::Upvalue(i));
+            } else {
+                continue;
+            }
+            captured.push(var);
+        }
+
+        let id = self.vm.new_func(
+            args.len() as i32,
+            args.iter().map(|(name, _)| name.clone()).collect(),
+        );
+
+        match name {
+            // a closure reaches itself through its own variable
+            Some(name) if recursive && !captures.is_empty() => {
+                if let Some(&slot) = self.locals.get(name) {
+                    captures.push(Capture::Local(slot));
+                    captured.push(name.to_owned());
+                } else if let Some(i) = self.upvalues.iter().position(|up| up == name) {
+                    captures.push(Capture::Upvalue(i));
+                    captured.push(name.to_owned());
+                }
+            }
+            Some(name) if captures.is_empty() => {
+                self.func_def.insert(name.to_owned(), id);
+            }
+            _ => (),
+        }
+
+        let mut cmpl = Compiler::new(&mut self.vm);
+        cmpl.func_def = self.func_def.clone();
+        // parameters take the first slots, in order
+        for name in scope.declared.iter() {
+            cmpl.slot(name);
+        }
+        cmpl.captured = scope.captured();
+        cmpl.upvalues = captured;
+        cmpl.top_level = false;
+        cmpl.lines.file = self.lines.file.clone();
+        cmpl.namespace = self.namespace.clone();
+        cmpl.module_globals = self.module_globals.clone();
+        cmpl.module_exports = self.module_exports.clone();
+        cmpl.tail(block);
+        let ins = cmpl.finish();
+        let locals = cmpl.locals.len();
+        let lines = cmpl.lines;
+        self.func_def = cmpl.func_def;
+
+        let is_closure = !captures.is_empty();
+        {
+            let mut func = self.vm.get_func(&id).borrow_mut();
+            func.name = name.unwrap_or("<lambda>").to_owned();
+            func.kind = FuncKind::Interpret(ins);
+            func.captures = captures;
+            func.locals = locals;
+            func.lines = lines;
+        }
+
+        if is_closure {
+            self.emit(Opcode::MakeClosure(id));
+        } else {
+            self.emit(Opcode::PushFunc(id));
+        }
+    }
+
+    /// Compiles `expr` as the value returned by the function. Calls in tail
+    /// position replace the running frame instead of growing the call stack.
+    fn tail(&mut self, expr: &Expression) {
+        match expr.node {
+            ExpressionNode::Block(ref body) => match body.split_last() {
+                Some((last, init)) => {
+                    for stmt in init.iter() {
+                        self.mark(&stmt.pos);
+                        self.stmt(stmt.node.clone());
+                    }
+
+                    self.mark(&last.pos);
+                    if let StatementNode::Expression(ref e) = last.node {
+                        self.tail(e);
+                    } else {
+                        self.stmt(last.node.clone());
+                        self.emit(Opcode::PushNull);
+                        self.emit(Opcode::Ret);
+                    }
+                }
+                None => {
+                    self.emit(Opcode::PushNull);
+                    self.emit(Opcode::Ret);
+                }
+            },
+            ExpressionNode::If(ref cond, ref then, ref or) => {
+                let next = self.new_empty_label();
+                self.expr(cond.node.clone());
+                self.emit_goto_false(&next);
+                self.tail(then);
+                self.label_here(&next);
+
+                for (cond, body, _) in or.iter().flatten() {
+                    if let Some(cond) = cond {
+                        let next = self.new_empty_label();
+                        self.expr(cond.node.clone());
+                        self.emit_goto_false(&next);
+                        self.tail(body);
+                        self.label_here(&next);
+                    } else {
+                        return self.tail(body);
+                    }
+                }
+
+                self.emit(Opcode::PushNull);
+                self.emit(Opcode::Ret);
+            }
+            ExpressionNode::Call(ref target, ref args) => {
+                for arg in args.iter().rev() {
+                    self.expr(arg.node.clone());
+                }
+                self.expr(target.node.clone());
+                self.mark(&target.pos);
+                self.emit(Opcode::TailCall(args.len()));
+            }
+            // no value left on the stack
+            ExpressionNode::While(..) | ExpressionNode::For(..) | ExpressionNode::EOF => {
+                self.expr(expr.node.clone());
+                self.emit(Opcode::PushNull);
+                self.emit(Opcode::Ret);
+            }
+            ref node => {
+                self.expr(node.clone());
+                self.emit(Opcode::Ret);
+            }
+        }
+    }
+
+    // bound of a range, null when left out
+    fn bound(&mut self, bound: Option<Rc<Expression>>) {
+        match bound {
+            Some(e) => self.expr(e.node.clone()),
+            None => self.emit(Opcode::PushNull),
+        }
+    }
+
+    fn literal_key(expr: &Expression) -> Option<Value> {
+        match expr.node {
+            ExpressionNode::Int(i) => Some(Value::Int(i as i64)),
+            ExpressionNode::Float(f) => Some(Value::Float(f.to_bits())),
+            ExpressionNode::Str(ref s) => Some(Value::Str(s.clone())),
+            ExpressionNode::Char(c) => Some(Value::Str(c.to_string())),
+            ExpressionNode::Bool(b) => Some(Value::Bool(b)),
+            _ => None,
+        }
+    }
+
+    // the enum and the name of a variant pattern
+    fn variant_key(expr: &Expression) -> Option<(Expression, String)> {
+        if let ExpressionNode::Index(ref path, ref variant, false) = expr.node {
+            if let ExpressionNode::Identifier(ref name) = variant.node {
+                return Some(((**path).clone(), name.clone()));
+            }
+        }
+
+        None
+    }
+
+    pub fn compile_switch(&mut self, subject: &Expression, arms: &[(Pattern, Expression)]) {
+        let value = format!("__switch{}__", self.new_empty_label());
+        let end = self.new_empty_label();
+
+        self.expr(subject.node.clone());
+        self.store_local(&value);
+
+        // arms up to the first catch-all, the rest is unreachable
+        let arms = match arms.iter().position(|arm| arm.0.is_catch_all()) {
+            Some(i) => &arms[..i + 1],
+            None => arms,
+        };
+
+        let tested = arms.iter().filter(|arm| !arm.0.is_catch_all());
+
+        let literals = tested
+            .clone()
+            .map(|arm| match arm.0.node {
+                PatternNode::Literal(ref e) => Self::literal_key(e),
+                _ => None,
+            })
+            .collect::<Option<Vec<Value>>>();
+        let variants = tested
+            .map(|arm| match arm.0.node {
+                PatternNode::Variant(ref e) => Self::variant_key(e),
+                _ => None,
+            })
+            .collect::<Option<Vec<(Expression, String)>>>();
+
+        if literals.is_some() || variants.is_some() {
+            // constant patterns: arms first, then dispatch through a jump table
+            let dispatch = self.new_empty_label();
+            let no_match = self.new_empty_label();
+            self.emit_goto(&dispatch);
+
+            let mut targets = vec![];
+            for (pattern, body) in arms.iter() {
+                let arm = self.new_empty_label();
+                self.label_here(&arm);
+                targets.push(arm);
+
+                if let PatternNode::Binding(ref name) = pattern.node {
+                    self.load_local(&value);
+                    self.store_local(name);
+                }
+                self.expr(body.node.clone());
+                self.emit_goto(&end);
+            }
+
+            self.label_here(&no_match);
+            self.emit(Opcode::PushNull);
+            self.emit_goto(&end);
+
+            self.label_here(&dispatch);
+            self.load_local(&value);
+
+            let l = self.labels.clone();
+            let pc = |label: &String| l.get(label).unwrap().unwrap();
+
+            let default = match arms.last() {
+                Some(arm) if arm.0.is_catch_all() => pc(targets.last().unwrap()),
+                _ => pc(&no_match),
+            };
+
+            if let Some(keys) = literals {
+                let mut table = FnvHashMap::default();
+                for (key, target) in keys.into_iter().zip(targets.iter()) {
+                    table.entry(key).or_insert(pc(target));
+                }
+                self.emit(Opcode::JmpTable(table, default));
+            } else if let Some(keys) = variants {
+                // every arm is of the same enum, the type checker made sure
+                if let Some((path, _)) = keys.first() {
+                    self.expr(path.node.clone());
+                }
+
+                let mut table = FnvHashMap::default();
+                for ((_, key), target) in keys.into_iter().zip(targets.iter()) {
+                    table.entry(key).or_insert(pc(target));
+                }
+                self.emit(Opcode::JmpVariant(table, default));
+            }
+        } else {
+            // destructuring patterns are tested one arm after another
+            for (pattern, body) in arms.iter() {
+                let next = self.new_empty_label();
+
+                self.compile_pattern(&value, pattern, &next);
+                self.expr(body.node.clone());
+                self.emit_goto(&end);
+
+                self.label_here(&next);
+            }
+
+            self.emit(Opcode::PushNull);
+        }
+
+        self.label_here(&end);
+    }
+
+    /// Loops over arrays, strings and objects through `Iter`, and over ranges
+    /// without building their array
+    fn compile_for(
+        &mut self,
+        names: &[String],
+        iterable: &Expression,
+        body: &Expression,
+        label: Option<String>,
+    ) {
+        let index = format!("__for{}__", self.new_empty_label());
+        let start = self.new_empty_label();
+        let next = self.new_empty_label();
+        let end = self.new_empty_label();
+
+        match (names, &iterable.node) {
+            ([name], ExpressionNode::Range(Some(from), Some(to))) => {
+                let to_value = format!("__for{}__", self.new_empty_label());
+
+                self.expr(to.node.clone());
+                self.store_local(&to_value);
+                self.expr(from.node.clone());
+                self.store_local(&index);
+
+                self.label_here(&start);
+                self.load_local(&to_value);
+                self.load_local(&index);
+                self.emit(Opcode::Lt);
+                self.emit_goto_false(&end);
+                self.load_local(&index);
+                self.store_local(name);
+            }
+            _ => {
+                let items = format!("__for{}__", self.new_empty_label());
+                let keys = format!("__for{}__", self.new_empty_label());
+
+                self.expr(iterable.node.clone());
+                self.mark(&iterable.pos);
+                self.emit(Opcode::Iter);
+                self.store_local(&keys);
+                self.store_local(&items);
+                self.emit(Opcode::PushInt(0));
+                self.store_local(&index);
+
+                self.label_here(&start);
+                self.load_local(&index);
+                self.load_local(&items);
+                self.load_local(&keys);
+                self.emit(Opcode::IterNext(names.len() == 2));
+                self.emit_goto_false(&end);
+                for name in names.iter() {
+                    self.store_local(name);
+                }
+            }
+        }
+
+        self.loops.push(Loop {
+            label,
+            next: next.clone(),
+            end: end.clone(),
+        });
+        self.expr(body.node.clone());
+        self.loops.pop();
+
+        self.label_here(&next);
+        self.emit(Opcode::PushInt(1));
+        self.load_local(&index);
+        self.emit(Opcode::Add);
+        self.store_local(&index);
+        self.emit_goto(&start);
+        self.label_here(&end);
+    }
+
+    // innermost loop, or the innermost one labeled `label`
+    fn target_loop(&self, label: &Option<String>) -> &Loop {
+        self.loops
+            .iter()
+            .rev()
+            .find(|l| label.is_none() || l.label == *label)
+            .expect("`break` or `continue` outside loop")
+    }
+
+    // tests the local `value` against `pattern`, jumping to `fail` if it doesn't match
+    fn compile_pattern(&mut self, value: &str, pattern: &Pattern, fail: &str) {
+        match pattern.node {
+            PatternNode::Wildcard => (),
+            PatternNode::Binding(ref name) => {
+                self.load_local(value);
+                self.store_local(name);
+            }
+            PatternNode::Literal(ref e) | PatternNode::Variant(ref e) => {
+                self.expr(e.node.clone());
+                self.load_local(value);
+                self.emit(Opcode::Eq);
+                self.emit_goto_false(fail);
+            }
+            PatternNode::Array(ref elements) => {
+                self.load_local(value);
+                self.emit(Opcode::MatchArray(elements.len()));
+                self.emit_goto_false(fail);
+
+                for (i, element) in elements.iter().enumerate() {
+                    if let PatternNode::Wildcard = element.node {
+                        continue;
                     }
-                    self.emit(Opcode::StoreLocal);
+
+                    let element_value = format!("__switch{}__", self.new_empty_label());
+                    self.emit(Opcode::PushInt(i as i64));
+                    self.load_local(value);
+                    self.emit(Opcode::Aget);
+                    self.store_local(&element_value);
+
+                    self.compile_pattern(&element_value, element, fail);
+                }
+            }
+            PatternNode::Struct(ref descriptor, ref fields) => {
+                self.expr(descriptor.node.clone());
+                self.load_local(value);
+                self.emit(Opcode::MatchStruct);
+                self.emit_goto_false(fail);
+
+                for (field, field_pattern) in fields.iter() {
+                    if let PatternNode::Wildcard = field_pattern.node {
+                        continue;
+                    }
+
+                    let field_value = format!("__switch{}__", self.new_empty_label());
+                    self.emit(Opcode::PushStr(field.clone()));
+                    self.load_local(value);
+                    self.emit(Opcode::LoadField);
+                    self.store_local(&field_value);
+
+                    self.compile_pattern(&field_value, field_pattern, fail);
                 }
             }
-            _ => unimplemented!(),
         }
     }
 
@@ -248,23 +878,52 @@ impl<'a> Compiler<'a> {
         match expr {
             ExpressionNode::Bool(b) => self.emit(Opcode::PushBool(b)),
             ExpressionNode::Int(i) => self.emit(Opcode::PushInt(i as i64)),
+            ExpressionNode::Neg(ref e) => match e.node {
+                ExpressionNode::Int(i) => self.emit(Opcode::PushInt(-(i as i64))),
+                ExpressionNode::Float(f) => self.emit(Opcode::PushFloat(-f)),
+                _ => {
+                    self.expr(e.node.clone());
+                    self.emit(Opcode::Neg)
+                }
+            },
+            ExpressionNode::Not(e) => {
+                self.expr(e.node.clone());
+                self.emit(Opcode::Not)
+            }
             ExpressionNode::Float(f) => self.emit(Opcode::PushFloat(f as f64)),
             ExpressionNode::Str(s) => self.emit(Opcode::PushStr(s)),
             ExpressionNode::Char(c) => self.emit(Opcode::PushStr(c.to_string())),
-            ExpressionNode::Identifier(name) => {
-                if self.func_def.contains_key(&name) {
-                    let id = self.func_def.get(&name).expect("not found");
+            ExpressionNode::Identifier(name) => self.load_var(&name),
 
-                    self.emit(Opcode::PushFunc(*id));
-                    return;
-                }
-                self.emit(Opcode::PushStr(name));
-                self.emit(Opcode::LoadLocal);
+            ExpressionNode::Binary(lhs, Operator::And, rhs) => {
+                let short = self.new_empty_label();
+                let end = self.new_empty_label();
+
+                self.expr(lhs.node.clone());
+                self.emit_goto_false(&short);
+                self.expr(rhs.node.clone());
+                self.emit_goto(&end);
+                self.label_here(&short);
+                self.emit(Opcode::PushBool(false));
+                self.label_here(&end);
+            }
+            ExpressionNode::Binary(lhs, Operator::Or, rhs) => {
+                let rest = self.new_empty_label();
+                let end = self.new_empty_label();
+
+                self.expr(lhs.node.clone());
+                self.emit_goto_false(&rest);
+                self.emit(Opcode::PushBool(true));
+                self.emit_goto(&end);
+                self.label_here(&rest);
+                self.expr(rhs.node.clone());
+                self.label_here(&end);
             }
 
             ExpressionNode::Binary(lhs, op, rhs) => {
                 self.expr(rhs.node.clone());
                 self.expr(lhs.node.clone());
+                self.mark(&Self::span(&lhs.pos, &rhs.pos));
 
                 match op {
                     Operator::Add => self.emit(Opcode::Add),
@@ -272,18 +931,22 @@ impl<'a> Compiler<'a> {
                     Operator::Mul => self.emit(Opcode::Mul),
                     Operator::Mod => self.emit(Opcode::Rem),
                     Operator::Div => self.emit(Opcode::Div),
+                    Operator::Pow => self.emit(Opcode::Pow),
+                    Operator::Concat => self.emit(Opcode::Concat),
                     Operator::Lt => self.emit(Opcode::Lt),
                     Operator::Gt => self.emit(Opcode::Gt),
+                    Operator::LtEq => self.emit(Opcode::Le),
+                    Operator::GtEq => self.emit(Opcode::Ge),
                     Operator::Eq => self.emit(Opcode::Eq),
                     Operator::NEq => self.emit(Opcode::Neq),
-                    Operator::Or => self.emit(Opcode::Or),
-                    _ => unimplemented!(),
+                    Operator::And | Operator::Or => unreachable!("short-circuited above"),
                 }
             }
             ExpressionNode::Empty => self.emit(Opcode::PushNull),
             ExpressionNode::EOF => {}
             ExpressionNode::Block(stmt) => {
                 for stmt in stmt.iter() {
+                    self.mark(&stmt.pos);
                     self.stmt(stmt.node.clone());
                 }
             }
@@ -295,25 +958,40 @@ impl<'a> Compiler<'a> {
                 self.emit(Opcode::Amake(arr.len()));
             }
 
+            ExpressionNode::Map(entries) => {
+                for (key, value) in entries.iter().rev() {
+                    self.expr(value.node.clone());
+                    self.expr(key.node.clone());
+                }
+                self.emit(Opcode::Mmake(entries.len()));
+            }
+
+            ExpressionNode::Range(start, end) => {
+                self.bound(end);
+                self.bound(start);
+                self.emit(Opcode::Range);
+            }
+
             ExpressionNode::Index(a, b, is_arr) => {
-                
-                match b.node.clone() {
-                    ExpressionNode::Identifier(ref name) => {
-                        if is_arr {
-                            self.expr(b.node.clone());
-                        } else {
-                            self.emit(Opcode::PushStr(name.to_owned()));
-                        }
+                let op = match b.node.clone() {
+                    ExpressionNode::Range(start, end) if is_arr => {
+                        self.bound(end);
+                        self.bound(start);
+                        Opcode::Slice
+                    }
+                    _ if is_arr => {
+                        self.expr(b.node.clone());
+                        Opcode::Aget
                     }
-                    ExpressionNode::Index(_, _, _) => self.expr(b.node.clone()),
-                    ExpressionNode::Int(idx) => {
-                        self.emit(Opcode::PushInt(idx as i64));
+                    field => {
+                        self.field_key(field);
+                        Opcode::LoadField
                     }
-                    _ => unimplemented!(),
                 };
-                
+
                 self.expr(a.node.clone());
-                self.emit(Opcode::LoadField);
+                self.mark(&Self::span(&a.pos, &b.pos));
+                self.emit(op);
             }
 
             ExpressionNode::Call(target, args) => {
@@ -321,17 +999,23 @@ impl<'a> Compiler<'a> {
                     self.expr(arg.node.clone());
                 }
                 self.expr(target.node.clone());
+                self.mark(&target.pos);
                 self.emit(Opcode::Call(args.len()));
             }
-            ExpressionNode::While(cond, block) => {
+            ExpressionNode::While(cond, block, label) => {
                 let while_block = self.new_empty_label();
                 let while_end = self.new_empty_label();
                 let while_start = self.new_empty_label();
-                self.end_label = Some(while_end.clone());
                 self.emit_goto(&while_start);
 
                 self.label_here(&while_block);
+                self.loops.push(Loop {
+                    label,
+                    next: while_start.clone(),
+                    end: while_end.clone(),
+                });
                 self.expr(block.node.clone());
+                self.loops.pop();
                 self.label_here(&while_start);
                 self.expr(cond.node.clone());
 
@@ -340,6 +1024,10 @@ impl<'a> Compiler<'a> {
                 self.label_here(&while_end);
             }
 
+            ExpressionNode::For(names, iterable, body, label) => {
+                self.compile_for(&names, &iterable, &body, label)
+            }
+
             ExpressionNode::If(cond, then, or) => {
                 self.expr(cond.node.clone());
                 let if_true = self.new_empty_label();
@@ -387,31 +1075,196 @@ impl<'a> Compiler<'a> {
             }
 
             ExpressionNode::Function(args, _, block, _) => {
-                let mut func = Function {
-                    args: args
-                        .iter()
-                        .map(|(name, _)| name.clone())
-                        .collect::<Vec<String>>(),
-                    nargs: args.len() as i32,
-                    kind: FuncKind::Interpret(vec![]),
-                };
+                self.compile_function(None, &args, &block)
+            }
+            ExpressionNode::Cast(e, _) => self.expr(e.node.clone()),
 
-                let mut cmpl = Compiler::new(&mut self.vm);
-                cmpl.func_def = self.func_def.clone();
-                cmpl.compile(vec![Statement::new(
-                    StatementNode::Expression(block.as_ref().clone()),
-                    block.pos.clone(),
-                )]);
-                let ins = cmpl.finish();
-                func.kind = FuncKind::Interpret(ins);
+            ExpressionNode::Switch(subject, arms) => self.compile_switch(&subject, &arms),
 
-                self.func_def = cmpl.func_def;
-                let id = self.vm.register_predefiend_func(func);
-                self.emit(Opcode::PushFunc(id));
+            ExpressionNode::Struct(fields, _) => self.compile_struct("struct", &fields),
+            ExpressionNode::Enum(_, variants) => {
+                self.emit(Opcode::MakeEnum("enum".to_owned(), variants))
             }
-            ExpressionNode::Cast(e, _) => self.expr(e.node.clone()),
+            ExpressionNode::Initialization(target, args) => {
+                for (name, value) in args.iter().rev() {
+                    self.expr(value.node.clone());
+                    self.emit(Opcode::PushStr(name.to_owned()));
+                }
+                self.expr(target.node.clone());
+                self.mark(&target.pos);
+                self.emit(Opcode::Construct(args.len()));
+            }
+        }
+    }
+}
+
+/// Variables declared and referenced by a function body, nested functions
+/// referencing whatever they don't declare themselves.
+#[derive(Default)]
+struct Scope {
+    /// In order of declaration, parameters first
+    declared: Vec<String>,
+    referenced: Vec<String>,
+    /// Free variables of nested functions
+    enclosed: Vec<String>,
+}
+
+impl Scope {
+    fn of_function(params: &[(String, Type)], body: &Expression) -> Scope {
+        let mut scope = Scope::default();
+
+        for (name, _) in params.iter() {
+            scope.declare(name);
+        }
+        scope.expr(body);
+        scope
+    }
+
+    fn of_program(ast: &[Statement]) -> Scope {
+        let mut scope = Scope::default();
+
+        ast.iter().for_each(|s| scope.stmt(s));
+        scope
+    }
+
+    /// Declared variables that nested functions capture
+    fn captured(&self) -> FnvHashSet<String> {
+        self.enclosed
+            .iter()
+            .filter(|name| self.declared.contains(*name))
+            .cloned()
+            .collect()
+    }
+
+    fn declare(&mut self, name: &str) {
+        if !self.declared.iter().any(|var| var == name) {
+            self.declared.push(name.to_owned());
+        }
+    }
+
+    /// Variables used but not declared, in order of first use
+    fn free(&self) -> Vec<String> {
+        self.referenced
+            .iter()
+            .filter(|name| !self.declared.contains(*name))
+            .cloned()
+            .collect()
+    }
 
-            v => panic!("{:?}", v),
+    fn reference(&mut self, name: &str) {
+        if !self.referenced.iter().any(|var| var == name) {
+            self.referenced.push(name.to_owned());
+        }
+    }
+
+    fn stmt(&mut self, stmt: &Statement) {
+        match stmt.node {
+            StatementNode::Expression(ref e) => self.expr(e),
+            StatementNode::Variable(_, _, ref name, ref init) => {
+                self.declare(name);
+                if let Some(init) = init {
+                    self.expr(init);
+                }
+            }
+            StatementNode::Assignment(ref to, ref val) => {
+                self.expr(to);
+                self.expr(val);
+            }
+            StatementNode::Return(Some(ref e)) => self.expr(e),
+            _ => (),
+        }
+    }
+
+    fn expr(&mut self, expr: &Expression) {
+        use self::ExpressionNode::*;
+
+        match expr.node {
+            Identifier(ref name) => self.reference(name),
+            Neg(ref e) | Not(ref e) | Cast(ref e, _) => self.expr(e),
+            Binary(ref lhs, _, ref rhs) => {
+                self.expr(lhs);
+                self.expr(rhs);
+            }
+            Array(ref elements) => elements.iter().for_each(|e| self.expr(e)),
+            Map(ref entries) => {
+                for (key, value) in entries.iter() {
+                    self.expr(key);
+                    self.expr(value);
+                }
+            }
+            Range(ref start, ref end) => {
+                start.iter().chain(end.iter()).for_each(|e| self.expr(e));
+            }
+            Call(ref target, ref args) => {
+                self.expr(target);
+                args.iter().for_each(|e| self.expr(e));
+            }
+            Index(ref a, ref b, is_arr) => {
+                self.expr(a);
+                match b.node {
+                    // field name
+                    Identifier(_) if !is_arr => (),
+                    _ => self.expr(b),
+                }
+            }
+            Block(ref body) => body.iter().for_each(|s| self.stmt(s)),
+            Function(ref params, _, ref body, _) => {
+                for name in Scope::of_function(params, body).free() {
+                    self.reference(&name);
+                    self.enclosed.push(name);
+                }
+            }
+            If(ref cond, ref then, ref or) => {
+                self.expr(cond);
+                self.expr(then);
+                for (cond, body, _) in or.iter().flatten() {
+                    if let Some(cond) = cond {
+                        self.expr(cond);
+                    }
+                    self.expr(body);
+                }
+            }
+            While(ref cond, ref body, _) => {
+                self.expr(cond);
+                self.expr(body);
+            }
+            For(ref names, ref iterable, ref body, _) => {
+                self.expr(iterable);
+                names.iter().for_each(|name| self.declare(name));
+                self.expr(body);
+            }
+            Switch(ref subject, ref arms) => {
+                self.expr(subject);
+                for (pattern, body) in arms.iter() {
+                    self.pattern(pattern);
+                    self.expr(body);
+                }
+            }
+            Struct(ref fields, _) => {
+                for (_, _, default) in fields.iter() {
+                    if let Some(default) = default {
+                        self.expr(default);
+                    }
+                }
+            }
+            Initialization(ref target, ref fields) => {
+                self.expr(target);
+                fields.iter().for_each(|(_, e)| self.expr(e));
+            }
+            _ => (),
+        }
+    }
+
+    fn pattern(&mut self, pattern: &Pattern) {
+        match pattern.node {
+            PatternNode::Binding(ref name) => self.declare(name),
+            PatternNode::Literal(ref e) | PatternNode::Variant(ref e) => self.expr(e),
+            PatternNode::Array(ref elements) => elements.iter().for_each(|p| self.pattern(p)),
+            PatternNode::Struct(ref descriptor, ref fields) => {
+                self.expr(descriptor);
+                fields.iter().for_each(|(_, p)| self.pattern(p));
+            }
+            PatternNode::Wildcard => (),
         }
     }
 }
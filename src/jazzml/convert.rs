@@ -0,0 +1,390 @@
+use super::error::RuntimeError;
+use super::value::{NativeFn, Object, Value};
+use super::visitor::{Type, TypeNode};
+use super::vm::VirtualMachine;
+
+use std::collections::HashMap;
+use std::hash::BuildHasher;
+use std::rc::Rc;
+
+/// Rust types with a counterpart in the type checker.
+pub trait ValueType {
+    fn value_type() -> Type;
+}
+
+/// Conversion of JazzML values into Rust, used for native arguments.
+pub trait FromValue: ValueType + Sized {
+    fn from_value(value: Value, vm: &VirtualMachine) -> Result<Self, RuntimeError>;
+}
+
+/// Conversion of Rust values into JazzML, used for native results.
+pub trait IntoValue: ValueType {
+    fn into_value(self, vm: &mut VirtualMachine) -> Value;
+}
+
+/// Result of a native function, either a value or a fallible one.
+pub trait IntoResult: ValueType {
+    fn into_result(self, vm: &mut VirtualMachine) -> Result<Value, RuntimeError>;
+}
+
+impl<T: IntoValue> IntoResult for T {
+    fn into_result(self, vm: &mut VirtualMachine) -> Result<Value, RuntimeError> {
+        Ok(self.into_value(vm))
+    }
+}
+
+impl<T: ValueType> ValueType for Result<T, RuntimeError> {
+    fn value_type() -> Type {
+        T::value_type()
+    }
+}
+
+impl<T: IntoValue> IntoResult for Result<T, RuntimeError> {
+    fn into_result(self, vm: &mut VirtualMachine) -> Result<Value, RuntimeError> {
+        self.map(|value| value.into_value(vm))
+    }
+}
+
+fn expected(what: &str, value: &Value, vm: &VirtualMachine) -> RuntimeError {
+    RuntimeError::new(format!("Expected {}, found `{}`", what, value.as_str(vm)))
+}
+
+fn elements(value: Value, vm: &VirtualMachine) -> Result<Vec<Value>, RuntimeError> {
+    match value {
+        Value::Array(id) => Ok(vm.get_array(&id)?.vec.borrow().clone()),
+        ref v => Err(expected("array", v, vm)),
+    }
+}
+
+fn array(values: Vec<Value>, vm: &mut VirtualMachine) -> Value {
+    Value::Array(vm.new_array(values))
+}
+
+impl ValueType for Value {
+    fn value_type() -> Type {
+        Type::from(TypeNode::Any)
+    }
+}
+
+impl FromValue for Value {
+    fn from_value(value: Value, _: &VirtualMachine) -> Result<Self, RuntimeError> {
+        Ok(value)
+    }
+}
+
+impl IntoValue for Value {
+    fn into_value(self, _: &mut VirtualMachine) -> Value {
+        self
+    }
+}
+
+impl ValueType for () {
+    fn value_type() -> Type {
+        Type::from(TypeNode::Any)
+    }
+}
+
+impl IntoValue for () {
+    fn into_value(self, _: &mut VirtualMachine) -> Value {
+        Value::Null
+    }
+}
+
+macro_rules! int_conversions {
+    ($($t: ty),+) => {
+        $(
+        impl ValueType for $t {
+            fn value_type() -> Type {
+                Type::from(TypeNode::Int)
+            }
+        }
+
+        impl FromValue for $t {
+            fn from_value(value: Value, vm: &VirtualMachine) -> Result<Self, RuntimeError> {
+                match value {
+                    Value::Int(i) if i as $t as i64 == i => Ok(i as $t),
+                    Value::Int(i) => Err(RuntimeError::new(format!(
+                        "{} is out of range for {}",
+                        i,
+                        stringify!($t)
+                    ))),
+                    ref v => Err(expected("int", v, vm)),
+                }
+            }
+        }
+
+        impl IntoValue for $t {
+            fn into_value(self, _: &mut VirtualMachine) -> Value {
+                Value::Int(self as i64)
+            }
+        }
+        )+
+    };
+}
+
+int_conversions!(i8, i16, i32, i64, isize, u8, u16, u32, usize);
+
+macro_rules! float_conversions {
+    ($($t: ty),+) => {
+        $(
+        impl ValueType for $t {
+            fn value_type() -> Type {
+                Type::from(TypeNode::Float)
+            }
+        }
+
+        impl FromValue for $t {
+            fn from_value(value: Value, vm: &VirtualMachine) -> Result<Self, RuntimeError> {
+                match value {
+                    Value::Float(bits) => Ok(f64::from_bits(bits) as $t),
+                    Value::Int(i) => Ok(i as $t),
+                    ref v => Err(expected("float", v, vm)),
+                }
+            }
+        }
+
+        impl IntoValue for $t {
+            fn into_value(self, _: &mut VirtualMachine) -> Value {
+                Value::Float((self as f64).to_bits())
+            }
+        }
+        )+
+    };
+}
+
+float_conversions!(f32, f64);
+
+impl ValueType for bool {
+    fn value_type() -> Type {
+        Type::from(TypeNode::Bool)
+    }
+}
+
+impl FromValue for bool {
+    fn from_value(value: Value, vm: &VirtualMachine) -> Result<Self, RuntimeError> {
+        match value {
+            Value::Bool(b) => Ok(b),
+            ref v => Err(expected("bool", v, vm)),
+        }
+    }
+}
+
+impl IntoValue for bool {
+    fn into_value(self, _: &mut VirtualMachine) -> Value {
+        Value::Bool(self)
+    }
+}
+
+impl ValueType for String {
+    fn value_type() -> Type {
+        Type::from(TypeNode::Str)
+    }
+}
+
+impl FromValue for String {
+    fn from_value(value: Value, vm: &VirtualMachine) -> Result<Self, RuntimeError> {
+        match value {
+            Value::Str(s) => Ok(s),
+            ref v => Err(expected("str", v, vm)),
+        }
+    }
+}
+
+impl IntoValue for String {
+    fn into_value(self, _: &mut VirtualMachine) -> Value {
+        Value::Str(self)
+    }
+}
+
+impl<'a> ValueType for &'a str {
+    fn value_type() -> Type {
+        Type::from(TypeNode::Str)
+    }
+}
+
+impl<'a> IntoValue for &'a str {
+    fn into_value(self, _: &mut VirtualMachine) -> Value {
+        Value::Str(self.to_owned())
+    }
+}
+
+impl<T: ValueType> ValueType for Vec<T> {
+    fn value_type() -> Type {
+        Type::array(T::value_type(), None)
+    }
+}
+
+impl<T: FromValue> FromValue for Vec<T> {
+    fn from_value(value: Value, vm: &VirtualMachine) -> Result<Self, RuntimeError> {
+        elements(value, vm)?
+            .into_iter()
+            .map(|value| T::from_value(value, vm))
+            .collect()
+    }
+}
+
+impl<T: IntoValue> IntoValue for Vec<T> {
+    fn into_value(self, vm: &mut VirtualMachine) -> Value {
+        let values = self.into_iter().map(|value| value.into_value(vm)).collect();
+        array(values, vm)
+    }
+}
+
+impl<T: ValueType, S> ValueType for HashMap<String, T, S> {
+    fn value_type() -> Type {
+        Type::map(Type::from(TypeNode::Str), T::value_type())
+    }
+}
+
+impl<T: FromValue, S: BuildHasher + Default> FromValue for HashMap<String, T, S> {
+    fn from_value(value: Value, vm: &VirtualMachine) -> Result<Self, RuntimeError> {
+        let id = match value {
+            Value::ObjectRef(id) => id,
+            ref v => return Err(expected("object", v, vm)),
+        };
+
+        let entries = vm.get_object(&id)?.borrow().map.clone();
+        entries
+            .into_iter()
+            .map(|(key, value)| Ok((key.as_str(vm), T::from_value(value, vm)?)))
+            .collect()
+    }
+}
+
+impl<T: IntoValue, S> IntoValue for HashMap<String, T, S> {
+    fn into_value(self, vm: &mut VirtualMachine) -> Value {
+        let mut obj = Object::new();
+        for (key, value) in self.into_iter() {
+            let value = value.into_value(vm);
+            obj.store(Value::Str(key), value);
+        }
+
+        Value::ObjectRef(vm.register_object(obj))
+    }
+}
+
+/// `null` stands for `None`
+impl<T: ValueType> ValueType for Option<T> {
+    fn value_type() -> Type {
+        Type::from(TypeNode::Any)
+    }
+}
+
+impl<T: FromValue> FromValue for Option<T> {
+    fn from_value(value: Value, vm: &VirtualMachine) -> Result<Self, RuntimeError> {
+        match value {
+            Value::Null => Ok(None),
+            value => T::from_value(value, vm).map(Some),
+        }
+    }
+}
+
+impl<T: IntoValue> IntoValue for Option<T> {
+    fn into_value(self, vm: &mut VirtualMachine) -> Value {
+        match self {
+            Some(value) => value.into_value(vm),
+            None => Value::Null,
+        }
+    }
+}
+
+macro_rules! count {
+    () => { 0 };
+    ($head: ident $($tail: ident)*) => { 1 + count!($($tail)*) };
+}
+
+/// Tuples are arrays of fixed length
+macro_rules! tuple_conversions {
+    ($($t: ident $v: ident),+) => {
+        impl<$($t: ValueType),+> ValueType for ($($t,)+) {
+            fn value_type() -> Type {
+                Type::array(Type::from(TypeNode::Any), Some(count!($($t)+)))
+            }
+        }
+
+        impl<$($t: FromValue),+> FromValue for ($($t,)+) {
+            fn from_value(value: Value, vm: &VirtualMachine) -> Result<Self, RuntimeError> {
+                let values = elements(value, vm)?;
+                if values.len() != count!($($t)+) {
+                    return Err(RuntimeError::new(format!(
+                        "Expected {} elements, found {}",
+                        count!($($t)+),
+                        values.len()
+                    )));
+                }
+
+                let mut values = values.into_iter();
+                $(let $v = $t::from_value(values.next().unwrap(), vm)?;)+
+                Ok(($($v,)+))
+            }
+        }
+
+        impl<$($t: IntoValue),+> IntoValue for ($($t,)+) {
+            fn into_value(self, vm: &mut VirtualMachine) -> Value {
+                let ($($v,)+) = self;
+                let values = vec![$($v.into_value(vm)),+];
+                array(values, vm)
+            }
+        }
+    };
+}
+
+tuple_conversions!(A a);
+tuple_conversions!(A a, B b);
+tuple_conversions!(A a, B b, C c);
+tuple_conversions!(A a, B b, C c, D d);
+
+/// Plain Rust functions callable from JazzML. `Args` is the tuple of
+/// parameter types, it only tells the implementations apart.
+pub trait IntoNative<Args> {
+    fn arity() -> usize;
+
+    /// Signature seen by the type checker
+    fn signature() -> Type;
+
+    fn into_native(self) -> NativeFn;
+}
+
+macro_rules! native_fns {
+    ($($t: ident $v: ident),*) => {
+        impl<F, R, $($t),*> IntoNative<($($t,)*)> for F
+        where
+            F: Fn($($t),*) -> R + 'static,
+            R: IntoResult,
+            $($t: FromValue,)*
+        {
+            fn arity() -> usize {
+                count!($($t)*)
+            }
+
+            fn signature() -> Type {
+                Type::function(vec![$($t::value_type()),*], R::value_type(), false)
+            }
+
+            #[allow(unused_mut, unused_variables)]
+            fn into_native(self) -> NativeFn {
+                Rc::new(move |vm: &mut VirtualMachine, args: Vec<Value>| {
+                    if args.len() != count!($($t)*) {
+                        return Err(RuntimeError::new(format!(
+                            "expected {} argument(s) found {}",
+                            count!($($t)*),
+                            args.len()
+                        )));
+                    }
+
+                    let mut args = args.into_iter();
+                    $(let $v = $t::from_value(args.next().unwrap(), vm)?;)*
+                    self($($v),*).into_result(vm)
+                })
+            }
+        }
+    };
+}
+
+native_fns!();
+native_fns!(A a);
+native_fns!(A a, B b);
+native_fns!(A a, B b, C c);
+native_fns!(A a, B b, C c, D d);
+native_fns!(A a, B b, C c, D d, E e);
+native_fns!(A a, B b, C c, D d, E e, G g);
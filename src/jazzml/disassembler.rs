@@ -0,0 +1,187 @@
+//! Listings of compiled code, one instruction per line with its pc, jump
+//! targets and function names resolved, and the source line it came from.
+
+use super::bytecode::Unit;
+use super::opcodes::Opcode;
+use super::value::{FuncKind, LineTable, Value};
+use super::vm::VirtualMachine;
+
+use fnv::{FnvHashMap, FnvHashSet};
+use std::fmt::Write;
+
+pub struct Disassembler<'a> {
+    vm: &'a VirtualMachine,
+    /// Function names by id, as the compiler knew them. Functions missing
+    /// here go by the name they were compiled with.
+    names: &'a FnvHashMap<usize, String>,
+}
+
+impl<'a> Disassembler<'a> {
+    pub fn new(vm: &'a VirtualMachine, names: &'a FnvHashMap<usize, String>) -> Self {
+        Disassembler { vm, names }
+    }
+
+    /// Lists `units`, then every script function they reach, each once
+    pub fn units(&self, units: &[Unit]) -> String {
+        let mut out = String::new();
+        let mut pending = Vec::new();
+
+        for unit in units.iter() {
+            writeln!(out, "{}", unit.file).unwrap();
+            self.code(&mut out, &unit.code, &unit.lines);
+            out.push('\n');
+            pending.extend(Self::functions(&unit.code));
+        }
+
+        let mut listed = FnvHashSet::default();
+        let mut i = 0;
+        while i < pending.len() {
+            let id = pending[i];
+            i += 1;
+            if !listed.insert(id) {
+                continue;
+            }
+
+            let func = self.vm.get_func(&id).borrow();
+            if let FuncKind::Interpret(ref code) = func.kind {
+                out.push_str(&self.function(id));
+                out.push('\n');
+                pending.extend(Self::functions(code));
+            }
+        }
+
+        out
+    }
+
+    /// Lists the function `id`, natives only get a header
+    pub fn function(&self, id: usize) -> String {
+        let func = self.vm.get_func(&id).borrow();
+        let mut out = format!("func {}({})", self.name(id), func.args.join(", "));
+
+        match func.kind {
+            FuncKind::Native(_) => out.push_str(" native\n"),
+            FuncKind::Interpret(ref code) => {
+                write!(out, " locals: {}", func.locals).unwrap();
+                if !func.captures.is_empty() {
+                    write!(out, " captures: {:?}", func.captures).unwrap();
+                }
+                out.push('\n');
+                self.code(&mut out, code, &func.lines);
+            }
+        }
+
+        out
+    }
+
+    /// One instruction, with its operands
+    pub fn op(&self, op: &Opcode) -> String {
+        use self::Opcode::*;
+
+        match op {
+            PushInt(n) => format!("PushInt {}", n),
+            PushFloat(f) => format!("PushFloat {:?}", f),
+            PushObject(id) => format!("PushObject {}", id),
+            PushFunc(id) => format!("PushFunc {}", self.name(*id)),
+            MakeClosure(id) => format!("MakeClosure {}", self.name(*id)),
+            PushStr(s) => format!("PushStr {:?}", s),
+            PushBool(b) => format!("PushBool {}", b),
+            Amake(n) => format!("Amake {}", n),
+            Mmake(n) => format!("Mmake {}", n),
+            MakeStruct(name, fields) => {
+                let fields = fields
+                    .iter()
+                    .map(|(field, has_default)| {
+                        if *has_default {
+                            format!("{} = ..", field)
+                        } else {
+                            field.clone()
+                        }
+                    })
+                    .collect::<Vec<String>>();
+                format!("MakeStruct {} {{ {} }}", name, fields.join(", "))
+            }
+            Construct(n) => format!("Construct {}", n),
+            MakeEnum(name, variants) => format!("MakeEnum {} {{ {} }}", name, variants.join(", ")),
+            CallObj(n) => format!("CallObj {}", n),
+            Call(n) => format!("Call {}", n),
+            TailCall(n) => format!("TailCall {}", n),
+            JmpF(pc) => format!("JmpF -> {}", pc),
+            JmpT(pc) => format!("JmpT -> {}", pc),
+            Jmp(pc) => format!("Jmp -> {}", pc),
+            JmpTable(table, default) => {
+                let arms = table.iter().map(|(value, pc)| (Self::constant(value), *pc));
+                format!("JmpTable {}", Self::arms(arms, *default))
+            }
+            JmpVariant(table, default) => {
+                let arms = table.iter().map(|(variant, pc)| (variant.clone(), *pc));
+                format!("JmpVariant {}", Self::arms(arms, *default))
+            }
+            MatchArray(n) => format!("MatchArray {}", n),
+            IterNext(pairs) => format!("IterNext {}", pairs),
+            LoadSlot(slot) => format!("LoadSlot {}", slot),
+            StoreSlot(slot) => format!("StoreSlot {}", slot),
+            LoadCell(slot) => format!("LoadCell {}", slot),
+            StoreCell(slot) => format!("StoreCell {}", slot),
+            LoadUpvalue(i) => format!("LoadUpvalue {}", i),
+            StoreUpvalue(i) => format!("StoreUpvalue {}", i),
+            op => format!("{:?}", op),
+        }
+    }
+
+    // the source line is shown above the first instruction compiled from it
+    fn code(&self, out: &mut String, code: &[Opcode], lines: &LineTable) {
+        let mut line = None;
+
+        for (pc, op) in code.iter().enumerate() {
+            if let Some(pos) = lines.get(pc) {
+                if line != Some((pos.0).0) {
+                    line = Some((pos.0).0);
+                    writeln!(out, "  {:>5} | {}", (pos.0).0, (pos.0).1.trim()).unwrap();
+                }
+            }
+
+            writeln!(out, "  {:>5}   {}", pc, self.op(op)).unwrap();
+        }
+    }
+
+    fn name(&self, id: usize) -> String {
+        match self.names.get(&id) {
+            Some(name) => format!("{}#{}", name, id),
+            None => format!("{}#{}", self.vm.get_func(&id).borrow().name, id),
+        }
+    }
+
+    // functions used by `code`, in order
+    fn functions(code: &[Opcode]) -> Vec<usize> {
+        code.iter()
+            .filter_map(|op| match op {
+                Opcode::PushFunc(id) | Opcode::MakeClosure(id) => Some(*id),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn constant(value: &Value) -> String {
+        match value {
+            Value::Str(s) => format!("{:?}", s),
+            Value::Int(n) => n.to_string(),
+            Value::Float(bits) => format!("{:?}", f64::from_bits(*bits)),
+            Value::Bool(b) => b.to_string(),
+            value => format!("{:?}", value),
+        }
+    }
+
+    // jump table arms by target, then the default
+    fn arms<I: Iterator<Item = (String, usize)>>(arms: I, default: usize) -> String {
+        let mut arms = arms.collect::<Vec<(String, usize)>>();
+        arms.sort_by(|a, b| (a.1, &a.0).cmp(&(b.1, &b.0)));
+
+        let mut arms = arms
+            .into_iter()
+            .map(|(key, pc)| format!("{} -> {}", key, pc))
+            .collect::<Vec<String>>();
+        arms.push(format!("_ -> {}", default));
+
+        arms.join(", ")
+    }
+}
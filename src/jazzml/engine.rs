@@ -0,0 +1,731 @@
+use super::bytecode::{self, BytecodeError, Unit};
+use super::codegen::Compiler;
+use super::convert::{IntoNative, IntoValue};
+use super::disassembler::Disassembler;
+use super::error::Response::Wrong;
+use super::error::RuntimeError;
+use super::gc::Handle;
+use super::lexer::{Lexer, Pos, Token};
+use super::parser::{Parser, Statement, StatementNode};
+use super::source::{FilePath, Source};
+use super::stdlib::{string, NativeModule};
+use super::value::{Object, Value, VAR_ARGS};
+use super::visitor::{SymTab, Type, TypeNode, Visitor};
+use super::vm::VirtualMachine;
+
+use fnv::FnvHashMap;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::iter;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+/// Stage of the pipeline that rejected a program. Lexer, parser and type
+/// checker print their diagnostics as they go.
+#[derive(Debug)]
+pub enum EvalError {
+    Io(io::Error),
+    Lex,
+    Parse,
+    Check,
+    /// A module couldn't be found or imports itself
+    Import,
+    /// An `extern` has no matching native
+    Link,
+    /// Compiled code couldn't be written or loaded
+    Bytecode(BytecodeError),
+    Runtime(RuntimeError),
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EvalError::Io(err) => write!(f, "{}", err),
+            EvalError::Lex => write!(f, "lexing failed"),
+            EvalError::Parse => write!(f, "parsing failed"),
+            EvalError::Check => write!(f, "type checking failed"),
+            EvalError::Import => write!(f, "import failed"),
+            EvalError::Link => write!(f, "linking failed"),
+            EvalError::Bytecode(err) => write!(f, "{}", Wrong(err)),
+            EvalError::Runtime(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl From<io::Error> for EvalError {
+    fn from(err: io::Error) -> Self {
+        EvalError::Io(err)
+    }
+}
+
+impl From<BytecodeError> for EvalError {
+    fn from(err: BytecodeError) -> Self {
+        EvalError::Bytecode(err)
+    }
+}
+
+impl From<RuntimeError> for EvalError {
+    fn from(err: RuntimeError) -> Self {
+        EvalError::Runtime(err)
+    }
+}
+
+/// What `Engine::dump_file` shows of a program
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Dump {
+    Tokens,
+    Ast,
+    /// Types of the top level bindings
+    Types,
+    /// Disassembly of the program, the modules it imports and their functions
+    Bytecode,
+}
+
+/// A script module, loaded once however many files import it
+#[derive(Clone)]
+struct Module {
+    /// Global holding the module object, also prefixing the module's globals
+    key: String,
+    /// Exported bindings, as seen by importers
+    t: Type,
+    /// Code of the module, after the modules it imports
+    units: Vec<Unit>,
+}
+
+/// Runs JazzML programs inside a host application. Globals and registered
+/// functions stay around between evaluations.
+pub struct Engine {
+    pub vm: VirtualMachine,
+    /// Types of the globals, as seen by the type checker
+    symtab: SymTab,
+    /// Types of what the host declared, all a module starts with
+    host: SymTab,
+    /// Directories searched for imports not found next to the importing file
+    search_path: Vec<PathBuf>,
+    /// Loaded modules by canonical path
+    modules: HashMap<PathBuf, Module>,
+    /// Modules being loaded, innermost last
+    loading: Vec<PathBuf>,
+    /// Names of the compiled functions by id, for disassembly
+    names: FnvHashMap<usize, String>,
+}
+
+impl Engine {
+    pub fn new() -> Engine {
+        let mut engine = Engine {
+            vm: VirtualMachine::new(),
+            symtab: SymTab::new(),
+            host: SymTab::new(),
+            search_path: Vec::new(),
+            modules: HashMap::new(),
+            loading: Vec::new(),
+            names: FnvHashMap::default(),
+        };
+        engine.vm.init_builtins();
+
+        let any = Type::from(TypeNode::Any);
+        let int = Type::from(TypeNode::Int);
+
+        for name in ["print", "println", "array_pop", "chars", "putc"].iter() {
+            engine.declare(name, Type::function(vec![any.clone()], any.clone(), false));
+        }
+        engine.declare("new_obj", Type::function(vec![], any.clone(), false));
+        engine.declare("getc", Type::function(vec![], any.clone(), false));
+        engine.declare(
+            "array_push",
+            Type::function(vec![any.clone(), any.clone()], any.clone(), false),
+        );
+        engine.declare(
+            "array_len",
+            Type::function(vec![any.clone()], int.clone(), false),
+        );
+        engine.declare("len", Type::function(vec![any.clone()], int, false));
+
+        let map = Type::map(any.clone(), any.clone());
+        for name in ["keys", "values"].iter() {
+            engine.declare(
+                name,
+                Type::function(vec![map.clone()], Type::array(any.clone(), None), false),
+            );
+        }
+        engine.declare(
+            "has",
+            Type::function(
+                vec![map.clone(), any.clone()],
+                Type::from(TypeNode::Bool),
+                false,
+            ),
+        );
+        engine.declare(
+            "remove",
+            Type::function(vec![map, any.clone()], any.clone(), false),
+        );
+        engine.declare("string", string::module().module_type());
+
+        engine
+    }
+
+    /// Runs `code`, returning the value of its top level `return` if any.
+    /// The value is pinned while the handle lives, see `VirtualMachine::pin`.
+    pub fn eval_str(&mut self, code: &str) -> Result<Handle, EvalError> {
+        self.eval("<eval>", code)
+    }
+
+    pub fn eval_file(&mut self, path: &str) -> Result<Handle, EvalError> {
+        let units = self.from_file(path, Self::compile)?;
+        self.run_units(units)
+    }
+
+    /// Runs `code` typed at a prompt. Unlike `eval_str`, a trailing
+    /// expression gives its value as if it was returned.
+    pub fn eval_line(&mut self, code: &str) -> Result<Handle, EvalError> {
+        let source = Source::from("<repl>", code.lines().map(|x| x.into()).collect());
+        let mut ast = Self::parse(&source, code)?;
+
+        if let Some(last) = ast.pop() {
+            ast.push(match last.node {
+                StatementNode::Expression(expr) => {
+                    Statement::new(StatementNode::Return(Some(Rc::new(expr))), last.pos)
+                }
+                _ => last,
+            });
+        }
+
+        let units = self.compile_ast(&source, ast)?;
+        self.run_units(units)
+    }
+
+    /// Type checks `code` without running it. Its globals are declared as
+    /// if it had run.
+    pub fn check_str(&mut self, code: &str) -> Result<(), EvalError> {
+        let source = Source::from("<eval>", code.lines().map(|x| x.into()).collect());
+        let ast = Self::parse(&source, code)?;
+        self.check(&source, ast).map(|_| ())
+    }
+
+    /// Type checks the program in `path` and the modules it imports
+    pub fn check_file(&mut self, path: &str) -> Result<(), EvalError> {
+        self.from_file(path, |engine, file, code| {
+            let source = Source::from(file, code.lines().map(|x| x.into()).collect());
+            let ast = Self::parse(&source, code)?;
+            engine.check(&source, ast).map(|_| ())
+        })
+    }
+
+    /// Compiles `code` without running it, see `eval_bytecode`
+    pub fn compile_str(&mut self, code: &str) -> Result<Vec<u8>, EvalError> {
+        let units = self.compile("<eval>", code)?;
+        Ok(bytecode::write(&self.vm, &units)?)
+    }
+
+    /// Compiles the program in `path` and the modules it imports without
+    /// running them, see `eval_bytecode`
+    pub fn compile_file(&mut self, path: &str) -> Result<Vec<u8>, EvalError> {
+        let units = self.from_file(path, Self::compile)?;
+        Ok(bytecode::write(&self.vm, &units)?)
+    }
+
+    /// Runs a program compiled by `compile_file` or `compile_str`, possibly
+    /// by another process. Natives are looked up by name in this engine.
+    pub fn eval_bytecode(&mut self, bytes: &[u8]) -> Result<Handle, EvalError> {
+        let units = bytecode::read(&mut self.vm, bytes)?;
+        self.run_units(units)
+    }
+
+    fn eval(&mut self, file: &str, code: &str) -> Result<Handle, EvalError> {
+        let units = self.compile(file, code)?;
+        self.run_units(units)
+    }
+
+    /// Shows what a stage of the pipeline makes of the program in `path`.
+    /// Nothing is run, but imported modules are loaded.
+    pub fn dump_file(&mut self, path: &str, dump: Dump) -> Result<String, EvalError> {
+        self.from_file(path, |engine, file, code| engine.dump(file, code, dump))
+    }
+
+    /// Shows what a stage of the pipeline makes of `code`, see `dump_file`
+    pub fn dump_str(&mut self, code: &str, dump: Dump) -> Result<String, EvalError> {
+        self.dump("<eval>", code, dump)
+    }
+
+    fn dump(&mut self, file: &str, code: &str, dump: Dump) -> Result<String, EvalError> {
+        let source = Source::from(file, code.lines().map(|x| x.into()).collect());
+        let mut out = String::new();
+
+        match dump {
+            Dump::Tokens => {
+                for token in Self::lex(&source, code)? {
+                    out.push_str(&format!(
+                        "{:>4}:{:<4} {:<10} {:?}\n",
+                        token.line.0,
+                        token.slice.0,
+                        token.token_type.to_string(),
+                        token.lexeme
+                    ));
+                }
+            }
+            Dump::Ast => {
+                for statement in Self::parse(&source, code)? {
+                    out.push_str(&format!("{:#?}\n", statement));
+                }
+            }
+            Dump::Types => {
+                let (ast, _) = self.check(&source, Self::parse(&source, code)?)?;
+
+                for statement in ast.iter() {
+                    let name = match statement.node {
+                        StatementNode::Variable(_, _, ref name, _)
+                        | StatementNode::Extern(ref name, _)
+                        | StatementNode::Import(_, ref name) => name,
+                        _ => continue,
+                    };
+                    if let Some(t) = self.symtab.fetch(name) {
+                        out.push_str(&format!("{}: {}\n", name, t));
+                    }
+                }
+            }
+            Dump::Bytecode => {
+                let units = self.compile(file, code)?;
+                out = Disassembler::new(&self.vm, &self.names).units(&units);
+            }
+        }
+
+        Ok(out)
+    }
+
+    // runs `f` on the file name and code of the program in `path`
+    fn from_file<T, F>(&mut self, path: &str, f: F) -> Result<T, EvalError>
+    where
+        F: FnOnce(&mut Self, &str, &str) -> Result<T, EvalError>,
+    {
+        let code = fs::read_to_string(path)?;
+
+        // the program itself may be imported back
+        self.loading.push(fs::canonicalize(path)?);
+        let result = f(self, path, &code);
+        self.loading.pop();
+
+        result
+    }
+
+    // checks `ast` against the globals, loading what it imports
+    fn check(
+        &mut self,
+        source: &Source,
+        ast: Vec<Statement>,
+    ) -> Result<(Vec<Statement>, HashMap<String, Module>), EvalError> {
+        let imports = self.load_imports(&source.file.0, &ast, source)?;
+
+        let mut visitor = Visitor::new(&ast, source);
+        visitor.symtab = self.symtab.clone();
+        visitor.modules = Self::module_types(&imports);
+        visitor.visit().map_err(|_| EvalError::Check)?;
+        self.symtab = visitor.symtab;
+
+        Ok((ast, imports))
+    }
+
+    // checks and compiles `code`, after the modules it imports
+    fn compile(&mut self, file: &str, code: &str) -> Result<Vec<Unit>, EvalError> {
+        let source = Source::from(file, code.lines().map(|x| x.into()).collect());
+        let ast = Self::parse(&source, code)?;
+        self.compile_ast(&source, ast)
+    }
+
+    fn compile_ast(
+        &mut self,
+        source: &Source,
+        ast: Vec<Statement>,
+    ) -> Result<Vec<Unit>, EvalError> {
+        let (ast, imports) = self.check(source, ast)?;
+
+        let mut compiler = Compiler::new(&mut self.vm);
+        compiler.lines.file = source.file.0.clone();
+        compiler.imports = Self::module_keys(&imports);
+        compiler.compile(ast.clone());
+        Self::record_names(&mut self.names, &compiler);
+
+        let mut units = Self::imported_units(&ast, &imports);
+        units.push(Unit {
+            file: source.file.0.clone(),
+            exports: None,
+            externs: Self::externs(&ast),
+            code: compiler.finish(),
+            lines: compiler.lines,
+        });
+
+        Ok(units)
+    }
+
+    /// Runs `units` in order, returning what the last one returns. Modules
+    /// already run are skipped.
+    fn run_units(&mut self, units: Vec<Unit>) -> Result<Handle, EvalError> {
+        let mut ret = Value::Null;
+
+        for unit in units {
+            if unit.exports.is_some() && self.global(&unit.file).is_some() {
+                continue;
+            }
+
+            self.link(&unit)?;
+
+            // the module stores its exports in the object as it runs, so
+            // importers see their current values
+            let file = unit.file;
+            let module = unit.exports.map(|exports| {
+                let mut obj = Object::new();
+                for name in exports {
+                    obj.store(Value::Str(name), Value::Null);
+                }
+
+                let key = Value::Str(file);
+                let module_obj = Value::ObjectRef(self.vm.register_object(obj));
+                self.vm.globals.insert(key.clone(), module_obj);
+                key
+            });
+
+            self.vm.lines = unit.lines;
+            ret = match self.vm.run_instructions(unit.code) {
+                Ok(ret) => ret,
+                Err(err) => {
+                    // a module that failed isn't loaded, importing it runs it again
+                    if let Some(key) = module {
+                        self.vm.globals.remove(&key);
+                    }
+                    return Err(err.into());
+                }
+            };
+        }
+
+        Ok(self.vm.pin(ret))
+    }
+
+    fn lex(source: &Source, code: &str) -> Result<Vec<Token>, EvalError> {
+        let mut tokens = Vec::new();
+        for token in Lexer::default(code.chars().collect(), source) {
+            tokens.push(token.map_err(|_| EvalError::Lex)?);
+        }
+
+        Ok(tokens)
+    }
+
+    fn parse(source: &Source, code: &str) -> Result<Vec<Statement>, EvalError> {
+        Parser::new(Self::lex(source, code)?, source)
+            .parse()
+            .map_err(|_| EvalError::Parse)
+    }
+
+    // the `extern` declarations of `ast`, with their signatures
+    fn externs(ast: &[Statement]) -> Vec<(String, Type, Pos)> {
+        ast.iter()
+            .filter_map(|statement| match statement.node {
+                StatementNode::Extern(ref name, ref t) => {
+                    Some((name.clone(), t.clone(), statement.pos.clone()))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    // every `extern` must name a native the host registered, with the
+    // signature the host registered it with
+    fn link(&self, unit: &Unit) -> Result<(), EvalError> {
+        for (name, t, pos) in unit.externs.iter() {
+            let nargs = match t.node {
+                TypeNode::Func(ref params, ..) if !params.iter().any(Type::is_splat) => {
+                    params.len() as i32
+                }
+                _ => VAR_ARGS,
+            };
+
+            let linked = self.vm.link_native(name, nargs).and_then(|_| {
+                match self.host.fetch_str(name) {
+                    Some(ref host) if !Self::same_signature(t, host) => Err(format!(
+                        "extern `{}` is declared `{}`, the host's native is `{}`",
+                        name, t, host
+                    )),
+                    _ => Ok(()),
+                }
+            });
+
+            if let Err(message) = linked {
+                response!(Wrong(message), FilePath(unit.file.clone()), pos);
+                return Err(EvalError::Link);
+            }
+        }
+
+        Ok(())
+    }
+
+    // natives registered without a function type take anything
+    fn same_signature(declared: &Type, host: &Type) -> bool {
+        match (&declared.node, &host.node) {
+            (TypeNode::Func(params, retty, ..), TypeNode::Func(host_params, host_retty, ..)) => {
+                params == host_params && retty == host_retty
+            }
+            (_, TypeNode::Func(..)) => false,
+            _ => true,
+        }
+    }
+
+    /// Adds a directory to look for imports in, after the importing file's own
+    pub fn add_search_path<P: Into<PathBuf>>(&mut self, dir: P) {
+        self.search_path.push(dir.into())
+    }
+
+    // loads the modules imported at the top level of `ast`, by import path
+    fn load_imports(
+        &mut self,
+        file: &str,
+        ast: &[Statement],
+        source: &Source,
+    ) -> Result<HashMap<String, Module>, EvalError> {
+        let mut imports = HashMap::new();
+
+        for statement in ast.iter() {
+            if let StatementNode::Import(ref import, _) = statement.node {
+                let path = match self.resolve(file, import) {
+                    Some(path) => path,
+                    None => {
+                        response!(
+                            Wrong(format!("can't find module `{}`", import)),
+                            source.file,
+                            statement.pos
+                        );
+                        return Err(EvalError::Import);
+                    }
+                };
+
+                let module = self.load_module(path, source, &statement.pos)?;
+                imports.insert(import.clone(), module);
+            }
+        }
+
+        Ok(imports)
+    }
+
+    fn resolve(&self, file: &str, import: &str) -> Option<PathBuf> {
+        let here = Path::new(file).parent().unwrap_or_else(|| Path::new(""));
+
+        iter::once(here)
+            .chain(self.search_path.iter().map(PathBuf::as_path))
+            .map(|dir| dir.join(import))
+            .find(|path| path.is_file())
+            .and_then(|path| fs::canonicalize(path).ok())
+    }
+
+    fn load_module(
+        &mut self,
+        path: PathBuf,
+        source: &Source,
+        pos: &Pos,
+    ) -> Result<Module, EvalError> {
+        if let Some(module) = self.modules.get(&path) {
+            return Ok(module.clone());
+        }
+
+        if let Some(start) = self.loading.iter().position(|loading| *loading == path) {
+            let cycle = self.loading[start..]
+                .iter()
+                .chain(iter::once(&path))
+                .map(|path| path.display().to_string())
+                .collect::<Vec<String>>();
+
+            response!(
+                Wrong(format!("import cycle: {}", cycle.join(" -> "))),
+                source.file,
+                pos
+            );
+            return Err(EvalError::Import);
+        }
+
+        self.loading.push(path.clone());
+        let module = self.compile_module(&path);
+        self.loading.pop();
+
+        let module = module?;
+        self.modules.insert(path, module.clone());
+
+        Ok(module)
+    }
+
+    // checks and compiles a module on its own
+    fn compile_module(&mut self, path: &Path) -> Result<Module, EvalError> {
+        let file = path.display().to_string();
+        let code = fs::read_to_string(path)?;
+
+        let source = Source::from(&file, code.lines().map(|x| x.into()).collect());
+        let ast = Self::parse(&source, &code)?;
+        let imports = self.load_imports(&file, &ast, &source)?;
+
+        // top level bindings are exported, unless they start with `_`
+        let exports = ast
+            .iter()
+            .filter_map(|statement| match statement.node {
+                StatementNode::Variable(.., ref name, _) if !name.starts_with('_') => {
+                    Some(name.clone())
+                }
+                _ => None,
+            })
+            .collect::<Vec<String>>();
+
+        let mut visitor = Visitor::new(&ast, &source);
+        visitor.symtab = self.host.clone();
+        visitor.modules = Self::module_types(&imports);
+        visitor.visit().map_err(|_| EvalError::Check)?;
+
+        let members = exports
+            .iter()
+            .map(|name| {
+                let t = match visitor.symtab.fetch(name) {
+                    // the body was checked here, importers only see the signature
+                    Some(Type {
+                        node: TypeNode::Func(params, retty, _, is_method),
+                        mode,
+                    }) => Type::new(TypeNode::Func(params, retty, None, is_method), mode),
+                    Some(t) => t,
+                    None => Type::from(TypeNode::Any),
+                };
+                (name.clone(), t)
+            })
+            .collect::<HashMap<String, Type>>();
+
+        let mut compiler = Compiler::new(&mut self.vm);
+        compiler.namespace = Some(file.clone());
+        compiler.lines.file = file.clone();
+        compiler.imports = Self::module_keys(&imports);
+        compiler.compile(ast.clone());
+        Self::record_names(&mut self.names, &compiler);
+
+        let mut units = Self::imported_units(&ast, &imports);
+        units.push(Unit {
+            file: file.clone(),
+            exports: Some(exports),
+            externs: Self::externs(&ast),
+            code: compiler.finish(),
+            lines: compiler.lines,
+        });
+
+        Ok(Module {
+            key: file,
+            t: Type::from(TypeNode::Module(members)),
+            units,
+        })
+    }
+
+    // code of the modules imported by `ast`, each once, in import order
+    fn imported_units(ast: &[Statement], imports: &HashMap<String, Module>) -> Vec<Unit> {
+        let mut units: Vec<Unit> = Vec::new();
+
+        for statement in ast.iter() {
+            if let StatementNode::Import(ref import, _) = statement.node {
+                for unit in imports[import].units.iter() {
+                    if !units.iter().any(|loaded| loaded.file == unit.file) {
+                        units.push(unit.clone());
+                    }
+                }
+            }
+        }
+
+        units
+    }
+
+    // the compiler knows functions bound at the top level by name
+    fn record_names(names: &mut FnvHashMap<usize, String>, compiler: &Compiler) {
+        for (name, id) in compiler.func_def.iter() {
+            names.insert(*id, name.clone());
+        }
+    }
+
+    fn module_types(imports: &HashMap<String, Module>) -> HashMap<String, Type> {
+        imports
+            .iter()
+            .map(|(import, module)| (import.clone(), module.t.clone()))
+            .collect()
+    }
+
+    fn module_keys(imports: &HashMap<String, Module>) -> FnvHashMap<String, String> {
+        imports
+            .iter()
+            .map(|(import, module)| (import.clone(), module.key.clone()))
+            .collect()
+    }
+
+    pub fn global(&self, name: &str) -> Option<&Value> {
+        self.vm.globals.get(&Value::Str(name.to_owned()))
+    }
+
+    /// Sets the global `name` to a Rust value, typed after its conversion
+    pub fn bind<T: IntoValue>(&mut self, name: &str, value: T) {
+        let value = value.into_value(&mut self.vm);
+
+        self.declare(name, T::value_type());
+        self.vm.globals.insert(Value::Str(name.to_owned()), value);
+    }
+
+    pub fn set_global(&mut self, name: &str, value: Value) {
+        let t = match value {
+            Value::Int(_) => TypeNode::Int,
+            Value::Float(_) => TypeNode::Float,
+            Value::Bool(_) => TypeNode::Bool,
+            Value::Str(_) => TypeNode::Str,
+            _ => TypeNode::Any,
+        };
+
+        self.declare(name, Type::from(t));
+        self.vm.globals.insert(Value::Str(name.to_owned()), value);
+    }
+
+    pub fn call_global(&mut self, name: &str, args: Vec<Value>) -> Result<Handle, RuntimeError> {
+        match self.global(name).cloned() {
+            Some(callee) => self.vm.call_value(&callee, args).map(|ret| self.vm.pin(ret)),
+            None => Err(RuntimeError::new(format!(
+                "Global `{}` doesn't exists",
+                name
+            ))),
+        }
+    }
+
+    /// Registers a native function under `name`. The arity is taken from the
+    /// parameters of `t`, non-function types accept any number of arguments.
+    pub fn register_fn<T>(&mut self, name: &str, t: Type, f: T)
+    where
+        T: Fn(&mut VirtualMachine, Vec<Value>) -> Result<Value, RuntimeError> + 'static,
+    {
+        let nargs = match t.node {
+            TypeNode::Func(ref params, ..) => params.len() as i32,
+            _ => VAR_ARGS,
+        };
+
+        self.vm.register_native_func(name.to_owned(), f, nargs);
+        self.declare(name, t);
+    }
+
+    /// Registers a plain Rust function under `name`. Arguments and result
+    /// are converted with `FromValue`/`IntoValue`, which also give the
+    /// signature seen by the type checker.
+    pub fn register_native<Args, F: IntoNative<Args>>(&mut self, name: &str, f: F) {
+        let native = f.into_native();
+
+        self.vm.register_native_func(
+            name.to_owned(),
+            move |vm, args| native(vm, args),
+            F::arity() as i32,
+        );
+        self.declare(name, F::signature());
+    }
+
+    /// Registers the functions of `module` under a global named after it
+    pub fn register_module(&mut self, module: NativeModule) {
+        self.vm.register_module(&module);
+        self.declare(&module.name, module.module_type());
+    }
+
+    /// Makes `name` known to the type checker, in every module too
+    pub fn declare(&mut self, name: &str, t: Type) {
+        self.symtab.assign_str(name, t.clone());
+        self.host.assign_str(name, t)
+    }
+}
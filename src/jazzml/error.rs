@@ -35,3 +35,76 @@ impl<T: fmt::Display> fmt::Display for Response<T> {
         write!(f, "{}", message)
     }
 }
+
+use super::lexer::Pos;
+use super::opcodes::Opcode;
+
+/// Fault raised while running bytecode.
+#[derive(Debug, Clone)]
+pub struct RuntimeError {
+    pub message: String,
+    /// Instruction that failed
+    pub opcode: Option<Opcode>,
+    /// Functions on the call stack and their positions, innermost first
+    pub trace: Vec<(String, Option<Location>)>,
+    pub pos: Option<Location>,
+}
+
+/// Position in a source file of the code that ran.
+#[derive(Debug, Clone)]
+pub struct Location {
+    pub file: String,
+    pub pos: Pos,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (line, col) = ((self.pos.0).0, (self.pos.1).0);
+        write!(
+            f,
+            "\n{:>8} {}:{}:{}{}",
+            "-->".blue().bold(),
+            self.file,
+            line,
+            col,
+            self.pos
+        )
+    }
+}
+
+impl RuntimeError {
+    pub fn new<T: Into<String>>(message: T) -> Self {
+        RuntimeError {
+            message: message.into(),
+            opcode: None,
+            trace: vec![],
+            pos: None,
+        }
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", Wrong(&self.message))?;
+
+        if let Some(ref pos) = self.pos {
+            write!(f, "{}", pos)?;
+        }
+
+        if let Some(ref opcode) = self.opcode {
+            write!(f, "{}", Note(format!("while executing `{:?}`", opcode)))?;
+        }
+
+        for (i, (name, pos)) in self.trace.iter().enumerate() {
+            write!(f, "\n{:>8} {}", "in".blue().bold(), name)?;
+
+            // the innermost position is the one shown above
+            match pos {
+                Some(pos) if i > 0 => write!(f, "{}", pos)?,
+                _ => (),
+            }
+        }
+
+        Ok(())
+    }
+}
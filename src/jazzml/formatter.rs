@@ -0,0 +1,126 @@
+use super::engine::EvalError;
+use super::lexer::Lexer;
+use super::source::Source;
+
+/// Brackets of one source line
+#[derive(Clone, Default)]
+struct Line {
+    opens: usize,
+    closes: usize,
+    /// Whether a closing bracket comes before anything else
+    dedents: bool,
+    /// Whether the line has anything but whitespace yet
+    started: bool,
+    /// Whether the line starts inside a string, which is kept as is
+    verbatim: bool,
+}
+
+/// Reindents `code`, two spaces for every bracket open at the start of a
+/// line, one less when the line starts by closing one. Trailing whitespace,
+/// blank lines at either end and runs of blank lines are dropped, everything
+/// else is kept as written.
+pub fn format(file: &str, code: &str) -> Result<String, EvalError> {
+    let source = Source::from(file, code.lines().map(|x| x.into()).collect());
+
+    // only code that lexes is formatted
+    for token in Lexer::default(code.chars().collect(), &source) {
+        token.map_err(|_| EvalError::Lex)?;
+    }
+
+    let mut out = String::new();
+    let mut depth: usize = 0;
+    let mut blank = false;
+
+    for (text, line) in source.lines.iter().zip(scan(code).iter()) {
+        if line.verbatim {
+            out.push_str(text);
+            out.push('\n');
+            continue;
+        }
+
+        let text = text.trim();
+        if text.is_empty() {
+            blank = !out.is_empty();
+            continue;
+        }
+        if blank {
+            out.push('\n');
+            blank = false;
+        }
+
+        let indent = if line.dedents {
+            depth.saturating_sub(1)
+        } else {
+            depth
+        };
+        for _ in 0..indent {
+            out.push_str("  ");
+        }
+        out.push_str(text);
+        out.push('\n');
+
+        depth = depth.saturating_sub(line.closes) + line.opens;
+    }
+
+    Ok(out)
+}
+
+// brackets of every line, outside of strings and comments
+fn scan(code: &str) -> Vec<Line> {
+    let mut lines = vec![Line::default()];
+    let mut in_string = false;
+    let mut in_comment = false;
+    let mut chars = code.chars();
+
+    while let Some(ch) = chars.next() {
+        if ch == '\n' {
+            in_comment = false;
+            lines.push(Line {
+                verbatim: in_string,
+                ..Line::default()
+            });
+            continue;
+        }
+
+        let line = lines.last_mut().unwrap();
+        if in_comment || ch.is_whitespace() {
+            continue;
+        }
+        let first = !line.started;
+        line.started = true;
+
+        if in_string {
+            match ch {
+                '\\' => {
+                    if chars.next() == Some('\n') {
+                        lines.push(Line {
+                            verbatim: true,
+                            ..Line::default()
+                        });
+                    }
+                }
+                '"' => in_string = false,
+                _ => (),
+            }
+            continue;
+        }
+
+        match ch {
+            '"' => in_string = true,
+            '#' => in_comment = true,
+            '(' | '[' | '{' => line.opens += 1,
+            ')' | ']' | '}' => {
+                // closing what the line opened doesn't change the indentation
+                if line.opens > 0 {
+                    line.opens -= 1;
+                } else {
+                    line.dedents |= first;
+                    line.closes += 1;
+                }
+            }
+            _ => (),
+        }
+    }
+
+    lines
+}
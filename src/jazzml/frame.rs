@@ -1,4 +1,5 @@
 use super::builtins;
+use super::error::{Location, RuntimeError};
 use super::opcodes::Opcode;
 use super::value::*;
 use super::vm::VirtualMachine;
@@ -6,486 +7,1011 @@ use super::vm::VirtualMachine;
 use fnv::FnvHashMap;
 use std::cell::RefCell;
 
-pub struct Frame<'a> {
+/// Activation record of a single call, kept on `VirtualMachine::frames`.
+pub struct Frame {
     pub code: Vec<Opcode>,
-    pub locals: &'a mut FnvHashMap<String, Value>,
+    /// Local slots, parameters first
+    pub locals: Vec<Value>,
     pub pc: usize,
     pub stack: Vec<Value>,
-    pub vm: &'a mut VirtualMachine,
+    /// Captured locals, moved out of `locals` once a closure captures them
+    pub cells: FnvHashMap<u16, Ref<Value>>,
+    /// Cells captured by the running closure
+    pub upvalues: Vec<Ref<Value>>,
+    /// Function running in this frame, `None` for top level code
+    pub func: Option<usize>,
 }
 
-impl<'a> Frame<'a> {
-    pub fn new(vm: &'a mut VirtualMachine, locals: &'a mut FnvHashMap<String, Value>) -> Frame<'a> {
+impl Frame {
+    pub fn new(code: Vec<Opcode>, func: Option<usize>, nlocals: usize) -> Frame {
         Frame {
-            code: vec![],
-            locals,
+            code,
+            locals: vec![Value::Null; nlocals],
             pc: 0,
             stack: vec![],
-            vm,
+            cells: FnvHashMap::default(),
+            upvalues: vec![],
+            func,
         }
     }
 
-    pub fn new_with_ins(
-        vm: &'a mut VirtualMachine,
-        locals: &'a mut FnvHashMap<String, Value>,
-        ins: Vec<Opcode>,
-    ) -> Frame<'a> {
-        Frame {
-            code: ins,
-            locals,
-            pc: 0,
-            stack: vec![],
-            vm,
-        }
-    }
-
-    pub fn fetch_opcode(&mut self) -> Opcode {
-        let ins = self
-            .code
-            .get(self.pc)
-            .expect(&format!(
-                "No opcodes left (pc = {})\n all opcodes: {:?}",
-                self.pc, self.code
-            ))
-            .clone();
+    pub fn fetch_opcode(&mut self) -> Result<Opcode, RuntimeError> {
+        let ins = match self.code.get(self.pc) {
+            Some(ins) => ins.clone(),
+            None => {
+                return Err(RuntimeError::new(format!(
+                    "No opcodes left (pc = {})",
+                    self.pc
+                )));
+            }
+        };
         self.pc += 1;
-        ins
+        Ok(ins)
     }
 
-    pub fn pop(&mut self) -> Value {
-        self.stack.pop().expect(&format!(
-            "No value to pop.\nCurrent opcode: {:?}",
-            self.code[self.pc - 1],
-        ))
+    pub fn pop(&mut self) -> Result<Value, RuntimeError> {
+        match self.stack.pop() {
+            Some(value) => Ok(value),
+            None => Err(RuntimeError::new("No value to pop")),
+        }
     }
 
     pub fn push(&mut self, v: Value) {
         self.stack.push(v);
     }
 
-    pub fn execute_field_call(&mut self, method_key: Value, base: Value, argc: usize) -> Value {
-        let v: &Value = &base;
-        let key: &Value = &method_key;
-        match v {
-            &Value::ObjectRef(id) => {
-                let obj = self.vm.get_object(&id).clone();
-                let obj: &Object = &obj.borrow();
-                let func: &Value = &obj.load(key);
-
-                let func: Function = if let Value::FuncRef(id) = func {
-                    self.vm.get_func(&id).borrow().clone()
-                } else {
-                    panic!("Method not found");
+    /// Cell of local `slot`, created on first use
+    fn cell(&mut self, slot: u16) -> Ref<Value> {
+        if let Some(cell) = self.cells.get(&slot) {
+            return cell.clone();
+        }
+
+        let value = std::mem::replace(&mut self.locals[slot as usize], Value::Null);
+        let cell = Ref::new(RefCell::new(value));
+        self.cells.insert(slot, cell.clone());
+        cell
+    }
+
+    /// Number of local slots `code` uses, for code compiled outside a function
+    pub fn slots_used(code: &[Opcode]) -> usize {
+        code.iter()
+            .map(|op| match op {
+                Opcode::LoadSlot(slot)
+                | Opcode::StoreSlot(slot)
+                | Opcode::LoadCell(slot)
+                | Opcode::StoreCell(slot) => *slot as usize + 1,
+                _ => 0,
+            })
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+// The interpreter runs on the topmost frame of the call stack. Calls push a
+// frame and returns pop it, so script recursion doesn't recurse in Rust.
+impl VirtualMachine {
+    fn frame(&mut self) -> &mut Frame {
+        self.frames.last_mut().expect("No frame on call stack")
+    }
+
+    fn pop(&mut self) -> Result<Value, RuntimeError> {
+        self.frame().pop()
+    }
+
+    fn push(&mut self, v: Value) {
+        self.frame().push(v)
+    }
+
+    /// Runs the topmost frame and whatever it calls until it returns. On error
+    /// the frames it ran are unwound.
+    pub fn run_frames(&mut self) -> Result<Value, RuntimeError> {
+        self.gc.running += 1;
+        let result = self.run_loop();
+        self.gc.running -= 1;
+        result
+    }
+
+    fn run_loop(&mut self) -> Result<Value, RuntimeError> {
+        let depth = self.frames.len();
+
+        loop {
+            self.maybe_collect();
+
+            match self.execute_op() {
+                Ok(None) => (),
+                Ok(Some(ret)) => {
+                    self.frames.pop();
+
+                    if self.frames.len() < depth {
+                        return Ok(ret);
+                    }
+                    self.push(ret);
+                }
+                Err(mut err) => {
+                    // errors from nested runs already know where they happened
+                    if err.opcode.is_none() {
+                        if let Some(frame) = self.frames.last() {
+                            err.opcode = frame.code.get(frame.pc.wrapping_sub(1)).cloned();
+                            err.pos = self.position(frame);
+                        }
+                        err.trace = self.stack_trace();
+                    }
+
+                    self.frames.truncate(depth - 1);
+                    return Err(err);
+                }
+            }
+        }
+    }
+
+    /// Functions on the call stack and where they are at, innermost first
+    pub fn stack_trace(&self) -> Vec<(String, Option<Location>)> {
+        self.frames
+            .iter()
+            .rev()
+            .map(|frame| {
+                let name = match frame.func {
+                    Some(id) => self.get_func(&id).borrow().name.clone(),
+                    None => "<top level>".to_owned(),
+                };
+
+                (name, self.position(frame))
+            })
+            .collect()
+    }
+
+    /// Source position of the last instruction executed in `frame`
+    pub fn position(&self, frame: &Frame) -> Option<Location> {
+        let pc = frame.pc.checked_sub(1)?;
+
+        match frame.func {
+            Some(id) => self.get_func(&id).borrow().lines.location(pc),
+            None => self.lines.location(pc),
+        }
+    }
+
+    pub fn execute_field_call(
+        &mut self,
+        method_key: Value,
+        base: Value,
+        argc: usize,
+    ) -> Result<(), RuntimeError> {
+        match base {
+            Value::ObjectRef(id) => {
+                let func = self.get_object(&id)?.borrow().load(&method_key).clone();
+
+                let (func_id, upvalues) = match func {
+                    Value::FuncRef(id) => (id, vec![]),
+                    Value::Closure(id) => {
+                        let closure = self.get_closure(&id)?.borrow().clone();
+                        (closure.func, closure.upvalues)
+                    }
+                    _ => {
+                        return Err(RuntimeError::new(format!(
+                            "Method `{}` not found",
+                            method_key.as_str(self)
+                        )));
+                    }
                 };
+                let func = self.get_func(&func_id).borrow().clone();
+                if func.nargs != VAR_ARGS && func.nargs != argc as i32 {
+                    return Err(RuntimeError::new(format!(
+                        "`{}` expected {} argument(s) found {}",
+                        func.name, func.nargs, argc
+                    )));
+                }
 
                 let mut stack = vec![];
                 for _ in 0..argc {
-                    stack.push(self.pop());
+                    stack.push(self.pop()?);
                 }
 
                 match func.kind {
                     FuncKind::Interpret(code) => {
-                        let mut frame = Frame::new(self.vm, self.locals);
-                        frame.locals.insert("__this__".into(), Value::ObjectRef(id));
-                        for (arg, arg_name) in stack.iter().zip(&func.args) {
-                            frame.locals.insert(arg_name.to_string(), arg.clone());
+                        let mut frame = Frame::new(code, Some(func_id), func.locals);
+                        frame.upvalues = upvalues;
+                        for (slot, arg) in stack.into_iter().take(func.args.len()).enumerate() {
+                            frame.locals[slot] = arg;
                         }
-                        frame.code = code.clone();
-                        frame.run_frame()
+                        self.frames.push(frame);
+                    }
+                    FuncKind::Native(f) => {
+                        let ret = f(self, stack)?;
+                        self.push(ret);
                     }
-                    FuncKind::Native(f) => f(&mut self.vm, stack),
                 }
+                Ok(())
             }
-            _ => panic!(""),
+            _ => Err(RuntimeError::new(format!(
+                "Can't call method on `{}`",
+                base.as_str(self)
+            ))),
         }
     }
 
-    pub fn execute_call(&mut self, v: Value, argc: usize, obj_call: bool) -> Value {
-        let v: &Value = &v;
-
+    /// Calls `v` with `argc` arguments from the stack. Interpreted functions
+    /// get a new frame, which replaces the running one for tail calls; native
+    /// results are returned.
+    pub fn execute_call(
+        &mut self,
+        v: Value,
+        argc: usize,
+        obj_call: bool,
+        tail: bool,
+    ) -> Result<Option<Value>, RuntimeError> {
         match v {
-            &Value::ObjectRef(id) => {
-                let obj = self.vm.get_object(&id).clone();
-                let obj: &Object = &obj.borrow();
-                let reference = obj.load(&Value::Str("__call__".into()));
-                self.stack.push(Value::ObjectRef(id));
-                self.execute_call(reference.clone(), argc, true)
-            }
-            &Value::FuncRef(id) => {
-                let func: &Function = &self.vm.get_func(&id).borrow().clone();
-                match func.nargs {
-                    -1 /* VAR ARGS */ => {
-                        let mut temp = vec![];
-                        for _ in 0..argc {
-                            temp.push(self.pop());
-                        }
-                        let ret = match func.kind.clone() {
-                            FuncKind::Native(f) => f(self.vm,temp),
-                            FuncKind::Interpret(v) => {
-                                let mut frame = Frame::new(self.vm,self.locals);
-                                frame.code = v;
-                                frame.stack = temp;
-
-                                frame.run_frame()
-                            }
-                        };
+            Value::ObjectRef(id) => {
+                let reference = self
+                    .get_object(&id)?
+                    .borrow()
+                    .load(&Value::Str("__call__".into()))
+                    .clone();
+                self.push(Value::ObjectRef(id));
+                self.execute_call(reference, argc, true, tail)
+            }
+            Value::FuncRef(id) => self.call_function(id, vec![], argc, obj_call, tail),
+            Value::Closure(id) => {
+                let closure = self.get_closure(&id)?.borrow().clone();
+                self.call_function(closure.func, closure.upvalues, argc, obj_call, tail)
+            }
+            _ => Err(RuntimeError::new(format!(
+                "Can't call value `{}`",
+                v.as_str(self)
+            ))),
+        }
+    }
 
-                        return ret;
+    fn call_function(
+        &mut self,
+        id: usize,
+        upvalues: Vec<Ref<Value>>,
+        argc: usize,
+        obj_call: bool,
+        tail: bool,
+    ) -> Result<Option<Value>, RuntimeError> {
+        let func: Function = self.get_func(&id).borrow().clone();
+        match func.nargs {
+            -1 /* VAR ARGS */ => {
+                let mut temp = vec![];
+                for _ in 0..argc {
+                    temp.push(self.pop()?);
+                }
+                match func.kind {
+                    FuncKind::Native(f) => Ok(Some(f(self, temp)?)),
+                    FuncKind::Interpret(v) => {
+                        let mut frame = Frame::new(v, Some(id), func.locals);
+                        frame.upvalues = upvalues;
+                        frame.stack = temp;
+
+                        Ok(self.enter(frame, tail))
                     }
-                    nargs => {
-                        if nargs != argc as i32 {
-                            panic!("Expected {} argument(s) found {}",nargs,argc);
-                        }
+                }
+            }
+            nargs => {
+                if nargs != argc as i32 {
+                    return Err(RuntimeError::new(format!(
+                        "`{}` expected {} argument(s) found {}",
+                        func.name, nargs, argc
+                    )));
+                }
 
-                        let mut temp = vec![];
-                        if obj_call {
-                            temp.push(self.pop());
-                        }
-                        for _ in 0..nargs {
-                            temp.push(self.pop());
-                        }
+                let mut temp = vec![];
+                if obj_call {
+                    temp.push(self.pop()?);
+                }
+                for _ in 0..nargs {
+                    temp.push(self.pop()?);
+                }
 
-                        let ret = match &func.kind {
-                            FuncKind::Native(f) => f(self.vm,temp),
-                            FuncKind::Interpret(v) => {
-                                let mut frame = Frame::new(self.vm,self.locals);
-                                frame.code = v.clone();
+                match func.kind {
+                    FuncKind::Native(f) => Ok(Some(f(self, temp)?)),
+                    FuncKind::Interpret(v) => {
+                        let mut frame = Frame::new(v, Some(id), func.locals);
+                        frame.upvalues = upvalues;
 
-                                for (arg,arg_name) in temp.iter().zip(&func.args) {
-                                    frame.locals.insert(arg_name.clone(), arg.clone());
-                                }
+                        for (slot, arg) in temp.into_iter().take(func.args.len()).enumerate() {
+                            frame.locals[slot] = arg;
+                        }
 
-                                frame.run_frame()
-                            }
-                        };
-                        return ret;
+                        Ok(self.enter(frame, tail))
                     }
                 }
             }
-            _ => panic!("Can't call value `{:?}`", v),
         }
     }
 
-    pub fn run_frame(&mut self) -> Value {
-        let value = loop {
-            let result = self.execute_op();
-            match result {
-                None => (),
-                Some(res) => break Some(res),
-            }
-        };
-
-        value.unwrap().clone()
+    fn enter(&mut self, frame: Frame, tail: bool) -> Option<Value> {
+        if tail {
+            self.frames.pop();
+        }
+        self.frames.push(frame);
+        None
     }
 
-    pub fn execute_op(&mut self) -> Option<Value> {
-        let ins = self.fetch_opcode();
+    pub fn execute_op(&mut self) -> Result<Option<Value>, RuntimeError> {
+        let ins = self.frame().fetch_opcode()?;
 
         match ins {
             Opcode::PushObject(id) => {
                 self.push(Value::ObjectRef(id));
-                None
+                Ok(None)
             }
             Opcode::PushFunc(id) => {
                 self.push(Value::FuncRef(id));
-                None
+                Ok(None)
+            }
+            Opcode::MakeClosure(func) => {
+                let captures = self.get_func(&func).borrow().captures.clone();
+                let frame = self.frame();
+                let upvalues = captures
+                    .iter()
+                    .map(|capture| match capture {
+                        Capture::Local(slot) => frame.cell(*slot),
+                        Capture::Upvalue(i) => frame.upvalues[*i].clone(),
+                    })
+                    .collect();
+
+                let id = self.register_closure(Closure { func, upvalues });
+                self.push(Value::Closure(id));
+                Ok(None)
             }
 
-            Opcode::Nop => None,
+            Opcode::Nop => Ok(None),
 
             Opcode::PushNull => {
                 self.push(Value::Null);
-                None
+                Ok(None)
             }
             Opcode::PushBool(b) => {
                 self.push(Value::Bool(b));
-                None
+                Ok(None)
             }
             Opcode::PushInt(int) => {
-                self.stack.push(Value::Int(int));
-                None
+                self.push(Value::Int(int));
+                Ok(None)
             }
             Opcode::PushFloat(float) => {
-                self.stack.push(Value::Float(float.to_bits()));
-                None
+                self.push(Value::Float(float.to_bits()));
+                Ok(None)
             }
 
             Opcode::PushStr(str) => {
-                self.stack.push(Value::Str(str));
-                None
+                self.push(Value::Str(str));
+                Ok(None)
             }
             Opcode::Amake(arr_len) => {
-                let obj_id = self.vm.new_object();
+                // the length may come from a loaded file, the stack bounds it
+                let capacity = arr_len.min(self.frame().stack.len());
+                let mut elements = Vec::with_capacity(capacity);
+                for _ in 0..arr_len {
+                    elements.push(self.pop()?);
+                }
+
+                let id = self.new_array(elements);
+                self.push(Value::Array(id));
+                Ok(None)
+            }
+            Opcode::Mmake(nentries) => {
+                let mut map = Object::new();
+                for _ in 0..nentries {
+                    let key = self.pop()?;
+                    let value = self.pop()?;
+                    map.store(key, value);
+                }
+
+                let id = self.register_object(map);
+                self.push(Value::ObjectRef(id));
+                Ok(None)
+            }
+            Opcode::Aget => {
+                let target = self.pop()?;
+                let index = self.pop()?;
+
+                let result = match target {
+                    Value::Array(id) => {
+                        let vec = self.get_array(&id)?.vec.borrow();
+                        vec[array_index(index.as_int(self)?, vec.len())?].clone()
+                    }
+                    Value::Str(ref s) => {
+                        let chars = s.chars().collect::<Vec<char>>();
+                        let i = array_index(index.as_int(self)?, chars.len())?;
+                        Value::Str(chars[i].to_string())
+                    }
+                    Value::ObjectRef(id) => self.get_object(&id)?.borrow().load(&index).clone(),
+                    _ => {
+                        return Err(RuntimeError::new(format!(
+                            "Can't index `{}`",
+                            target.as_str(self)
+                        )));
+                    }
+                };
+                self.push(result);
+                Ok(None)
+            }
+            Opcode::Aset => {
+                let target = self.pop()?;
+                let index = self.pop()?;
+                let val = self.pop()?;
 
-                let mut map = FnvHashMap::default();
-                for i in 0..arr_len {
-                    let value = self.pop();
-                    map.insert(Value::Int(i as i64), value);
+                match target {
+                    Value::Array(id) => {
+                        let i = index.as_int(self)?;
+                        let mut vec = self.get_array(&id)?.vec.borrow_mut();
+                        let i = array_index(i, vec.len())?;
+                        vec[i] = val;
+                    }
+                    Value::ObjectRef(id) => self.get_object(&id)?.borrow_mut().store(index, val),
+                    _ => {
+                        return Err(RuntimeError::new(format!(
+                            "Can't store index on `{}`",
+                            target.as_str(self)
+                        )));
+                    }
                 }
-                {
-                    let mut obj = self.vm.get_object(&obj_id).borrow_mut();
-                    obj.map = map;
+                Ok(None)
+            }
+            Opcode::Slice => {
+                let target = self.pop()?;
+                let start = self.pop()?;
+                let end = self.pop()?;
+
+                let start = match start {
+                    Value::Null => None,
+                    v => Some(v.as_int(self)?),
+                };
+                let end = match end {
+                    Value::Null => None,
+                    v => Some(v.as_int(self)?),
+                };
+
+                let result = match target {
+                    Value::Array(id) => {
+                        let elements = {
+                            let vec = self.get_array(&id)?.vec.borrow();
+                            let (start, end) = slice_range(start, end, vec.len())?;
+                            vec[start..end].to_vec()
+                        };
+                        Value::Array(self.new_array(elements))
+                    }
+                    Value::Str(ref s) => {
+                        let chars = s.chars().collect::<Vec<char>>();
+                        let (start, end) = slice_range(start, end, chars.len())?;
+                        Value::Str(chars[start..end].iter().collect())
+                    }
+                    _ => {
+                        return Err(RuntimeError::new(format!(
+                            "Can't slice `{}`",
+                            target.as_str(self)
+                        )));
+                    }
+                };
+                self.push(result);
+                Ok(None)
+            }
+            Opcode::Range => {
+                let start = self.pop()?.as_int(self)?;
+                let end = self.pop()?.as_int(self)?;
+
+                let elements = (start..end).map(Value::Int).collect();
+                let id = self.new_array(elements);
+                self.push(Value::Array(id));
+                Ok(None)
+            }
+            Opcode::MakeStruct(name, fields) => {
+                let mut descriptor = Struct::new(name);
+
+                for (field, has_default) in fields {
+                    if has_default {
+                        let value = self.pop()?;
+                        descriptor.defaults.insert(field.clone(), value);
+                    }
+                    descriptor.fields.push(field);
                 }
 
-                self.push(Value::ObjectRef(obj_id));
-                None
+                let id = self.register_struct(descriptor);
+                self.push(Value::StructRef(id));
+                Ok(None)
+            }
+            Opcode::MakeEnum(name, variants) => {
+                let id = self.register_enum(Enum::new(name, variants));
+                self.push(Value::EnumRef(id));
+                Ok(None)
+            }
+            Opcode::Construct(nfields) => {
+                let target = self.pop()?;
+
+                let mut init = FnvHashMap::default();
+                for _ in 0..nfields {
+                    let key = self.pop()?;
+                    let value = self.pop()?;
+                    init.insert(key, value);
+                }
+
+                let obj = match target {
+                    Value::StructRef(id) => {
+                        let descriptor = self.get_struct(&id)?.borrow().clone();
+                        descriptor.instantiate(self, init)?
+                    }
+                    _ => {
+                        return Err(RuntimeError::new(format!(
+                            "Can't initialize non-struct `{}`",
+                            target.as_str(self)
+                        )));
+                    }
+                };
+
+                let id = self.register_object(obj);
+                self.push(Value::ObjectRef(id));
+                Ok(None)
             }
 
             Opcode::Add => {
-                let x = self.pop();
-                let y = self.pop();
+                let x = self.pop()?;
+                let y = self.pop()?;
 
-                let z = builtins::add(self.vm, vec![x, y]);
+                let z = builtins::add(self, vec![x, y])?;
                 self.push(z);
-                None
+                Ok(None)
             }
 
             Opcode::Sub => {
-                let x = self.pop();
-                let y = self.pop();
+                let x = self.pop()?;
+                let y = self.pop()?;
 
-                let z = builtins::sub(self.vm, vec![x, y]);
+                let z = builtins::sub(self, vec![x, y])?;
                 self.push(z);
-                None
+                Ok(None)
             }
             Opcode::Mul => {
-                let x = self.pop();
-                let y = self.pop();
+                let x = self.pop()?;
+                let y = self.pop()?;
 
-                let z = builtins::mul(self.vm, vec![x, y]);
+                let z = builtins::mul(self, vec![x, y])?;
                 self.push(z);
-                None
+                Ok(None)
             }
 
             Opcode::Div => {
-                let x = self.pop();
-                let y = self.pop();
+                let x = self.pop()?;
+                let y = self.pop()?;
 
-                let z = builtins::div(self.vm, vec![x, y]);
+                let z = builtins::div(self, vec![x, y])?;
                 self.push(z);
-                None
+                Ok(None)
             }
 
             Opcode::Rem => {
-                let x = self.pop();
-                let y = self.pop();
+                let x = self.pop()?;
+                let y = self.pop()?;
 
-                let z = builtins::rem(self.vm, vec![x, y]);
+                let z = builtins::rem(self, vec![x, y])?;
                 self.push(z);
-                None
+                Ok(None)
             }
 
-            Opcode::LoadLocal => {
-                let name: Value = self.pop();
-                let name_str = name.as_str(self.vm);
+            Opcode::Pow => {
+                let x = self.pop()?;
+                let y = self.pop()?;
+                let z = builtins::pow(self, vec![x, y])?;
+                self.push(z);
+                Ok(None)
+            }
 
-                let val = self
-                    .locals
-                    .get(&name_str)
-                    .expect(&format!("Local `{}` doesn't exists", name_str));
+            Opcode::Concat => {
+                let x = self.pop()?;
+                let y = self.pop()?;
 
-                self.push(val.clone());
-                None
+                let z = builtins::concat(self, vec![x, y])?;
+                self.push(z);
+                Ok(None)
             }
 
-            Opcode::StoreLocal => {
-                let name: Value = self.pop();
-                let name_str = name.as_str(self.vm);
-                let val = self.pop();
-
-                self.locals.insert(name_str, val);
-                None
+            Opcode::LoadSlot(slot) => {
+                let val = self.frame().locals[slot as usize].clone();
+                self.push(val);
+                Ok(None)
+            }
+            Opcode::StoreSlot(slot) => {
+                let val = self.pop()?;
+                self.frame().locals[slot as usize] = val;
+                Ok(None)
+            }
+            Opcode::LoadCell(slot) => {
+                let val = self.frame().cell(slot).borrow().clone();
+                self.push(val);
+                Ok(None)
+            }
+            Opcode::StoreCell(slot) => {
+                let val = self.pop()?;
+                *self.frame().cell(slot).borrow_mut() = val;
+                Ok(None)
+            }
+            Opcode::LoadUpvalue(i) => {
+                let val = self.frame().upvalues[i].borrow().clone();
+                self.push(val);
+                Ok(None)
+            }
+            Opcode::StoreUpvalue(i) => {
+                let val = self.pop()?;
+                *self.frame().upvalues[i].borrow_mut() = val;
+                Ok(None)
             }
 
             Opcode::StoreField => {
-                let target = self.pop();
-                let key = self.pop();
-                let val = self.pop();
+                let target = self.pop()?;
+                let key = self.pop()?;
+                let val = self.pop()?;
                 let key: &Value = &key;
                 let target: &Value = &target;
                 match target {
                     Value::ObjectRef(id) => {
-                        let object: &mut Object = &mut self.vm.get_object(id).borrow_mut();
+                        let object: &mut Object = &mut self.get_object(id)?.borrow_mut();
                         object.store(key.clone(), val);
                     }
 
-                    _ => panic!("Can't load field on `{:?}`", target),
+                    _ => {
+                        return Err(RuntimeError::new(format!(
+                            "Can't store field on `{}`",
+                            target.as_str(self)
+                        )));
+                    }
                 }
-                None
+                Ok(None)
             }
             Opcode::LoadField => {
-                let target = self.pop();
-                let key = self.pop();
+                let target = self.pop()?;
+                let key = self.pop()?;
 
                 let target: &Value = &target;
                 let key: &Value = &key;
                 let result = match target {
                     Value::ObjectRef(id) => {
-                        let object = self.vm.get_object(id).borrow();
+                        let object = self.get_object(id)?.borrow();
                         object.load(key).clone()
                     }
-                    _ => panic!("Can't load field on `{:?}`", target),
+                    Value::EnumRef(id) => {
+                        let descriptor = self.get_enum(id)?.borrow();
+                        let name = key.as_str(self);
+                        match descriptor.variant(&name) {
+                            Some(variant) => Value::Variant(*id, variant),
+                            None => {
+                                return Err(RuntimeError::new(format!(
+                                    "No variant `{}` in enum `{}`",
+                                    name, descriptor.name
+                                )));
+                            }
+                        }
+                    }
+                    _ => {
+                        return Err(RuntimeError::new(format!(
+                            "Can't load field on `{}`",
+                            target.as_str(self)
+                        )));
+                    }
                 };
                 self.push(result);
-                None
+                Ok(None)
             }
 
             Opcode::LoadGlobal => {
-                let key = self.pop();
-                let val = self.vm.globals.get(&key).unwrap();
+                let key = self.pop()?;
+                let val = match self.globals.get(&key) {
+                    Some(val) => val.clone(),
+                    None => {
+                        return Err(RuntimeError::new(format!(
+                            "Global `{}` doesn't exists",
+                            key.as_str(self)
+                        )));
+                    }
+                };
 
-                self.stack.push(val.clone());
-                None
+                self.push(val);
+                Ok(None)
             }
 
             Opcode::Call(nargs) => {
-                let target = self.pop();
-                let result = self.execute_call(target, nargs, false);
-                self.push(result);
-                None
+                let target = self.pop()?;
+                if let Some(ret) = self.execute_call(target, nargs, false, false)? {
+                    self.push(ret);
+                }
+                Ok(None)
             }
             Opcode::CallObj(nargs) => {
-                let target = self.pop();
-                let obj = self.pop();
-                let result = self.execute_field_call(target, obj, nargs);
-                self.push(result);
-                None
+                let target = self.pop()?;
+                let obj = self.pop()?;
+                self.execute_field_call(target, obj, nargs)?;
+                Ok(None)
             }
 
             Opcode::Ret => {
-                let ret = self.pop();
-                return Some(ret);
+                let ret = self.pop()?;
+                Ok(Some(ret))
             }
 
             Opcode::Eq => {
-                let x = self.pop();
-                let y = self.pop();
-                let z = builtins::eq(self.vm, vec![x, y]);
+                let x = self.pop()?;
+                let y = self.pop()?;
+                let z = builtins::eq(self, vec![x, y])?;
                 self.push(z);
-                None
+                Ok(None)
             }
             Opcode::Neq => {
-                let x = self.pop();
-                let y = self.pop();
-                let z = builtins::neq(self.vm, vec![x, y]);
+                let x = self.pop()?;
+                let y = self.pop()?;
+                let z = builtins::neq(self, vec![x, y])?;
                 self.push(z);
-                None
+                Ok(None)
             }
             Opcode::Gt => {
-                let x = self.pop();
-                let y = self.pop();
-                let z = builtins::gt(self.vm, vec![x, y]);
+                let x = self.pop()?;
+                let y = self.pop()?;
+                let z = builtins::gt(self, vec![x, y])?;
                 self.push(z);
-                None
+                Ok(None)
             }
 
             Opcode::Lt => {
-                let x = self.pop();
-                let y = self.pop();
-                let z = builtins::lt(self.vm, vec![x, y]);
+                let x = self.pop()?;
+                let y = self.pop()?;
+                let z = builtins::lt(self, vec![x, y])?;
+                self.push(z);
+                Ok(None)
+            }
+            Opcode::Le => {
+                let x = self.pop()?;
+                let y = self.pop()?;
+                let z = builtins::le(self, vec![x, y])?;
+                self.push(z);
+                Ok(None)
+            }
+            Opcode::Ge => {
+                let x = self.pop()?;
+                let y = self.pop()?;
+                let z = builtins::ge(self, vec![x, y])?;
+                self.push(z);
+                Ok(None)
+            }
+            Opcode::Neg => {
+                let x = self.pop()?;
+                let z = builtins::neg(self, vec![x])?;
+                self.push(z);
+                Ok(None)
+            }
+            Opcode::Not => {
+                let x = self.pop()?;
+                let z = builtins::not(self, vec![x])?;
                 self.push(z);
-                None
+                Ok(None)
             }
             Opcode::Shr => {
-                let x = self.pop();
-                let y = self.pop();
-                let z = builtins::shr(self.vm, vec![x, y]);
+                let x = self.pop()?;
+                let y = self.pop()?;
+                let z = builtins::shr(self, vec![x, y])?;
                 self.push(z);
-                None
+                Ok(None)
             }
 
             Opcode::Shl => {
-                let x = self.pop();
-                let y = self.pop();
-                let z = builtins::shl(self.vm, vec![x, y]);
+                let x = self.pop()?;
+                let y = self.pop()?;
+                let z = builtins::shl(self, vec![x, y])?;
                 self.push(z);
-                None
+                Ok(None)
             }
             Opcode::And => {
-                let x = self.pop();
-                let y = self.pop();
-                let z = builtins::and(self.vm, vec![x, y]);
+                let x = self.pop()?;
+                let y = self.pop()?;
+                let z = builtins::and(self, vec![x, y])?;
                 self.push(z);
-                None
+                Ok(None)
             }
 
             Opcode::Or => {
-                let x = self.pop();
-                let y = self.pop();
-                let z = builtins::and(self.vm, vec![x, y]);
+                let x = self.pop()?;
+                let y = self.pop()?;
+                let z = builtins::or(self, vec![x, y])?;
                 self.push(z);
-                None
+                Ok(None)
             }
 
             Opcode::Band => {
-                let x = self.pop();
-                let y = self.pop();
-                let z = builtins::band(self.vm, vec![x, y]);
+                let x = self.pop()?;
+                let y = self.pop()?;
+                let z = builtins::band(self, vec![x, y])?;
                 self.push(z);
-                None
+                Ok(None)
             }
             Opcode::Bor => {
-                let x = self.pop();
-                let y = self.pop();
-                let z = builtins::bor(self.vm, vec![x, y]);
+                let x = self.pop()?;
+                let y = self.pop()?;
+                let z = builtins::bor(self, vec![x, y])?;
                 self.push(z);
-                None
+                Ok(None)
             }
 
             Opcode::Bxor => {
-                let x = self.pop();
-                let y = self.pop();
-                let z = builtins::bxor(self.vm, vec![x, y]);
+                let x = self.pop()?;
+                let y = self.pop()?;
+                let z = builtins::bxor(self, vec![x, y])?;
                 self.push(z);
-                None
+                Ok(None)
             }
 
             Opcode::Pop => {
-                self.pop();
-                None
+                self.pop()?;
+                Ok(None)
             }
             Opcode::Jmp(pc) => {
-                self.pc = pc;
-                None
+                self.frame().pc = pc;
+                Ok(None)
             }
             Opcode::JmpF(pc) => {
-                let value = self.pop();
+                let value = self.pop()?;
                 let value: &Value = &value;
 
                 match value {
                     Value::Bool(b) => {
                         if !*b {
-                            self.pc = pc
+                            self.frame().pc = pc
                         }
                     }
                     _ => {}
                 }
-                None
+                Ok(None)
             }
 
             Opcode::JmpT(pc) => {
-                let value = self.pop();
+                let value = self.pop()?;
                 let value: &Value = &value;
 
                 match value {
                     Value::Bool(b) => {
                         if *b {
-                            self.pc = pc
+                            self.frame().pc = pc
                         }
                     }
                     _ => {}
                 }
-                None
+                Ok(None)
+            }
+            Opcode::JmpTable(table, default) => {
+                let value = self.pop()?;
+
+                // ints and floats of the same value match, as they're `==`
+                let other = match value {
+                    Value::Int(i) => Some(Value::Float((i as f64).to_bits())),
+                    Value::Float(bits) => {
+                        let f = f64::from_bits(bits);
+                        if (f as i64) as f64 == f {
+                            Some(Value::Int(f as i64))
+                        } else {
+                            None
+                        }
+                    }
+                    _ => None,
+                };
+
+                self.frame().pc = *table
+                    .get(&value)
+                    .or_else(|| other.and_then(|other| table.get(&other)))
+                    .unwrap_or(&default);
+                Ok(None)
+            }
+            Opcode::JmpVariant(table, default) => {
+                let descriptor = self.pop()?;
+                let value = self.pop()?;
+
+                self.frame().pc = match (value, descriptor) {
+                    (Value::Variant(id, variant), Value::EnumRef(enum_id)) if id == enum_id => {
+                        let descriptor = self.get_enum(&id)?.borrow();
+                        *table.get(&descriptor.variants[variant]).unwrap_or(&default)
+                    }
+                    _ => default,
+                };
+                Ok(None)
+            }
+            Opcode::MatchArray(len) => {
+                let value = self.pop()?;
+
+                let matched = match value {
+                    Value::Array(id) => self.get_array(&id)?.vec.borrow().len() == len,
+                    _ => false,
+                };
+
+                self.push(Value::Bool(matched));
+                Ok(None)
+            }
+            Opcode::MatchStruct => {
+                let value = self.pop()?;
+                let descriptor = self.pop()?;
+
+                let matched = match (value, descriptor) {
+                    (Value::ObjectRef(id), Value::StructRef(sid)) => {
+                        let obj = self.get_object(&id)?.borrow();
+                        obj.name.as_ref() == Some(&self.get_struct(&sid)?.borrow().name)
+                    }
+                    _ => false,
+                };
+
+                self.push(Value::Bool(matched));
+                Ok(None)
+            }
+            Opcode::Iter => {
+                let value = self.pop()?;
+
+                let (items, keys) = match value {
+                    Value::Array(_) => (value, Value::Null),
+                    Value::Str(ref s) => {
+                        let chars = s.chars().map(|c| Value::Str(c.to_string())).collect();
+                        (Value::Array(self.new_array(chars)), Value::Null)
+                    }
+                    Value::ObjectRef(id) => {
+                        let keys = self.get_object(&id)?.borrow().map.keys().cloned().collect();
+                        (value, Value::Array(self.new_array(keys)))
+                    }
+                    _ => {
+                        return Err(RuntimeError::new(format!(
+                            "Can't iterate over `{}`",
+                            value.as_str(self)
+                        )));
+                    }
+                };
+
+                self.push(items);
+                self.push(keys);
+                Ok(None)
+            }
+            Opcode::IterNext(pairs) => {
+                let keys = self.pop()?;
+                let items = self.pop()?;
+                let index = self.pop()?.as_int(self)? as usize;
+
+                // (key, value, whether a single name binds the key)
+                let entry = match (keys, items) {
+                    (Value::Null, Value::Array(id)) => {
+                        let array = self.get_array(&id)?.vec.borrow();
+                        array
+                            .get(index)
+                            .map(|value| (Value::Int(index as i64), value.clone(), false))
+                    }
+                    (Value::Array(keys), Value::ObjectRef(id)) => {
+                        let key = self.get_array(&keys)?.vec.borrow().get(index).cloned();
+                        match key {
+                            Some(key) => {
+                                let value = self.get_object(&id)?.borrow().load(&key).clone();
+                                Some((key, value, true))
+                            }
+                            None => None,
+                        }
+                    }
+                    _ => return Err(RuntimeError::new("Invalid iterator")),
+                };
+
+                match entry {
+                    Some((key, value, _)) if pairs => {
+                        self.push(value);
+                        self.push(key);
+                        self.push(Value::Bool(true));
+                    }
+                    Some((key, value, by_key)) => {
+                        self.push(if by_key { key } else { value });
+                        self.push(Value::Bool(true));
+                    }
+                    None => self.push(Value::Bool(false)),
+                }
+                Ok(None)
             }
             Opcode::StoreGlobal => {
-                let key = self.pop();
-                let val = self.pop();
+                let key = self.pop()?;
+                let val = self.pop()?;
                 let key: &Value = &key;
-                self.vm.globals.insert(key.clone(), val);
-                None
+                self.globals.insert(key.clone(), val);
+                Ok(None)
+            }
+            Opcode::TailCall(nargs) => {
+                let target = self.pop()?;
+                self.execute_call(target, nargs, false, true)
             }
-            Opcode::Aget => unimplemented!(),
-            Opcode::TailCall(_) => panic!("Taill call not implemented"),
         }
     }
 }
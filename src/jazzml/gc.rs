@@ -0,0 +1,223 @@
+use super::value::Value;
+use super::vm::VirtualMachine;
+
+use fnv::{FnvHashMap, FnvHashSet};
+use std::cell::RefCell;
+use std::fmt;
+use std::ops::Deref;
+use std::rc::Rc;
+
+pub const DEFAULT_THRESHOLD: usize = 4096;
+
+/// Collector state, see `VirtualMachine::collect`.
+#[derive(Clone, Debug)]
+pub struct Gc {
+    /// Allocations between automatic collections
+    pub threshold: usize,
+    /// Allocations since the last collection
+    pub allocated: usize,
+    /// Nested `run_frames` calls, collections only happen in the outermost
+    pub(crate) running: usize,
+    pub stats: GcStats,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GcStats {
+    pub collections: usize,
+    /// Heap values freed over all collections
+    pub freed: usize,
+    /// Heap values alive after the last collection
+    pub live: usize,
+}
+
+impl Gc {
+    pub fn new() -> Gc {
+        Gc {
+            threshold: DEFAULT_THRESHOLD,
+            allocated: 0,
+            running: 0,
+            stats: GcStats::default(),
+        }
+    }
+}
+
+/// Values pinned by handles, with how many handles pin them
+pub(crate) type Pins = Rc<RefCell<FnvHashMap<Value, usize>>>;
+
+/// A value held by the host. It is a root for as long as the handle lives,
+/// so runs after the one that made it don't collect it.
+pub struct Handle {
+    value: Value,
+    pins: Pins,
+}
+
+impl Handle {
+    pub fn value(&self) -> &Value {
+        &self.value
+    }
+}
+
+impl Deref for Handle {
+    type Target = Value;
+
+    fn deref(&self) -> &Value {
+        &self.value
+    }
+}
+
+impl Clone for Handle {
+    fn clone(&self) -> Handle {
+        *self.pins.borrow_mut().entry(self.value.clone()).or_insert(0) += 1;
+        Handle {
+            value: self.value.clone(),
+            pins: self.pins.clone(),
+        }
+    }
+}
+
+impl Drop for Handle {
+    fn drop(&mut self) {
+        let mut pins = self.pins.borrow_mut();
+        let unpinned = match pins.get_mut(&self.value) {
+            Some(count) => {
+                *count -= 1;
+                *count == 0
+            }
+            None => false,
+        };
+        if unpinned {
+            pins.remove(&self.value);
+        }
+    }
+}
+
+impl PartialEq<Value> for Handle {
+    fn eq(&self, other: &Value) -> bool {
+        self.value == *other
+    }
+}
+
+impl fmt::Debug for Handle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self.value)
+    }
+}
+
+/// Heap values reached while marking
+#[derive(Default)]
+struct Marked {
+    objects: FnvHashSet<usize>,
+    arrays: FnvHashSet<usize>,
+    closures: FnvHashSet<usize>,
+}
+
+impl VirtualMachine {
+    pub fn set_gc_threshold(&mut self, threshold: usize) {
+        self.gc.threshold = threshold;
+    }
+
+    pub fn gc_stats(&self) -> GcStats {
+        self.gc.stats.clone()
+    }
+
+    /// Keeps `value` from being collected until the handle is dropped
+    pub fn pin(&self, value: Value) -> Handle {
+        *self.pins.borrow_mut().entry(value.clone()).or_insert(0) += 1;
+        Handle {
+            value,
+            pins: self.pins.clone(),
+        }
+    }
+
+    /// Counts an allocation towards the threshold
+    pub(crate) fn allocated(&mut self) {
+        self.gc.allocated += 1;
+    }
+
+    /// Collects when enough was allocated. Only called between instructions
+    /// of the outermost run, where every live value is reachable from a root;
+    /// natives calling back into the VM may hold values the roots don't see.
+    pub(crate) fn maybe_collect(&mut self) {
+        if self.gc.allocated >= self.gc.threshold && self.gc.running == 1 {
+            self.collect();
+        }
+    }
+
+    /// Frees the objects, arrays and closures unreachable from the globals,
+    /// struct defaults, the call stack and pinned values, returning how many
+    /// were freed. Values held only by the host are not roots, pin them to use
+    /// them across collections.
+    pub fn collect(&mut self) -> usize {
+        let mut marked = Marked::default();
+        let mut pending = self.roots();
+
+        while let Some(value) = pending.pop() {
+            match value {
+                Value::ObjectRef(id) => {
+                    if marked.objects.insert(id) {
+                        if let Some(obj) = self.pool.get(&id) {
+                            for (key, value) in obj.borrow().map.iter() {
+                                pending.push(key.clone());
+                                pending.push(value.clone());
+                            }
+                        }
+                    }
+                }
+                Value::Array(id) => {
+                    if marked.arrays.insert(id) {
+                        if let Some(arr) = self.arrays.get(&id) {
+                            pending.extend(arr.vec.borrow().iter().cloned());
+                        }
+                    }
+                }
+                Value::Closure(id) => {
+                    if marked.closures.insert(id) {
+                        if let Some(closure) = self.closures.get(&id) {
+                            for cell in closure.borrow().upvalues.iter() {
+                                pending.push(cell.borrow().clone());
+                            }
+                        }
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        let before = self.pool.len() + self.arrays.len() + self.closures.len();
+        self.pool.retain(|id, _| marked.objects.contains(id));
+        self.arrays.retain(|id, _| marked.arrays.contains(id));
+        self.closures.retain(|id, _| marked.closures.contains(id));
+        let live = self.pool.len() + self.arrays.len() + self.closures.len();
+
+        self.gc.allocated = 0;
+        self.gc.stats.collections += 1;
+        self.gc.stats.freed += before - live;
+        self.gc.stats.live = live;
+        before - live
+    }
+
+    fn roots(&self) -> Vec<Value> {
+        let mut roots = vec![];
+
+        for (key, value) in self.globals.iter() {
+            roots.push(key.clone());
+            roots.push(value.clone());
+        }
+
+        roots.extend(self.pins.borrow().keys().cloned());
+
+        for s in self.structs.values() {
+            roots.extend(s.borrow().defaults.values().cloned());
+        }
+
+        for frame in self.frames.iter() {
+            roots.extend(frame.locals.iter().cloned());
+            roots.extend(frame.stack.iter().cloned());
+
+            let cells = frame.cells.values().chain(frame.upvalues.iter());
+            roots.extend(cells.map(|cell| cell.borrow().clone()));
+        }
+
+        roots
+    }
+}
@@ -33,7 +33,8 @@ impl<'l> Lexer<'l> {
             Keyword,
             &[
                 "enum", "struct", "let", "var", "func", "=>", "return", "if", "elif", "else",
-                "switch", "continue", "break", "while", "new", "as", "->",
+                "switch", "continue", "break", "while", "for", "in", "new", "as", "->", "import",
+                "extern",
             ],
         )));
 
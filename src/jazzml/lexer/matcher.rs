@@ -341,6 +341,11 @@ impl<'t> Matcher<'t> for NumberLiteralMatcher {
 
         while !tokenizer.end() {
             let current = tokenizer.peek().unwrap();
+            // `1..2` is a range
+            if current == '.' && tokenizer.peek_n(1) == Some('.') {
+                break;
+            }
+
             if !current.is_whitespace() && current.is_digit(10) || current == '.' {
                 if current == '.' && accum.contains('.') {
                     let pos = tokenizer.pos;
@@ -6,8 +6,15 @@ pub mod source;
 pub mod visitor;
 
 pub mod builtins;
+pub mod bytecode;
 pub mod codegen;
+pub mod convert;
+pub mod disassembler;
+pub mod engine;
+pub mod formatter;
 pub mod frame;
+pub mod gc;
 pub mod opcodes;
+pub mod stdlib;
 pub mod value;
 pub mod vm;
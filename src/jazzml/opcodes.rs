@@ -1,27 +1,51 @@
+use super::value::Value;
+use fnv::FnvHashMap;
+
 #[derive(Clone, Debug)]
 pub enum Opcode {
     PushInt(i64),
     PushFloat(f64),
     PushObject(usize),
     PushFunc(usize),
+    /// Create closure of function, capturing the cells listed in its `captures`
+    MakeClosure(usize),
     PushStr(String),
     PushNull,
     PushBool(bool),
     Pop,
 
+    /// Create array of N popped elements, the first popped first
     Amake(usize),
+    /// Create map of N popped key/value pairs, the key popped first
+    Mmake(usize),
+    /// Push element of popped array, string or object at popped index
     Aget,
+    /// Store popped value at popped index of popped array or object
+    Aset,
+    /// Push part of popped array or string between popped bounds, null for open ends
+    Slice,
+    /// Create array of the integers between popped bounds
+    Range,
+    /// Create struct descriptor: name and fields, each flagged if its default is on the stack
+    MakeStruct(String, Vec<(String, bool)>),
+    /// Initialize struct instance from N key/value pairs
+    Construct(usize),
+    /// Create enum descriptor: name and variants
+    MakeEnum(String, Vec<String>),
     /// Call field of object
     CallObj(usize),
     /// Call function
     Call(usize),
-    /// Tail call
+    /// Call function, replacing the running frame
     TailCall(usize),
     Add,
     Sub,
     Div,
     Mul,
     Rem,
+    Pow,
+    /// Push popped values joined as strings
+    Concat,
     Bxor,
     Bor,
     Band,
@@ -32,19 +56,49 @@ pub enum Opcode {
     Shl,
     Lt,
     Gt,
+    Le,
+    Ge,
     Eq,
     Neq,
+    Neg,
+    Not,
 
     JmpF(usize),
     JmpT(usize),
     Jmp(usize),
+    /// Jump by value of popped constant, or to default
+    JmpTable(FnvHashMap<Value, usize>, usize),
+    /// Jump by variant name of popped value if it belongs to popped enum, or
+    /// to default
+    JmpVariant(FnvHashMap<String, usize>, usize),
+    /// Push whether popped value is array of N elements
+    MatchArray(usize),
+    /// Push whether popped value is instance of popped struct descriptor
+    MatchStruct,
+    /// Push what popped array, string or object is iterated through, then its
+    /// keys, see `IterNext`
+    Iter,
+    /// Push entry at popped index of what `Iter` pushed and true, or false past
+    /// the end. Entries are key and value if flagged, else the element, or the
+    /// key for objects
+    IterNext(bool),
 
     StoreField,
     LoadField,
 
     LoadGlobal,
     StoreGlobal,
-    StoreLocal,
-    LoadLocal,
+    /// Load local from slot
+    LoadSlot(u16),
+    /// Store popped value into local slot
+    StoreSlot(u16),
+    /// Load captured local through its cell
+    LoadCell(u16),
+    /// Store popped value into captured local through its cell
+    StoreCell(u16),
+    /// Load captured variable of the running closure
+    LoadUpvalue(usize),
+    /// Store popped value into captured variable of the running closure
+    StoreUpvalue(usize),
     Ret,
 }
@@ -9,11 +9,15 @@ pub enum StatementNode {
     Variable(bool, Type, String, Option<Expression>),
     Assignment(Expression, Expression),
     Return(Option<Rc<Expression>>),
-    Extern(Type, Option<String>),
-    Module(Rc<Expression>),
-
-    Continue,
-    Break,
+    /// `extern name: func(...) -> T`, a function the host provides
+    Extern(String, Type),
+    /// `import "path" as name`
+    Import(String, String),
+
+    /// `continue`, or `continue label` for an enclosing labeled loop
+    Continue(Option<String>),
+    /// `break`, or `break label` for an enclosing labeled loop
+    Break(Option<String>),
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -42,6 +46,10 @@ pub enum ExpressionNode {
     Identifier(String),
     Binary(Rc<Expression>, Operator, Rc<Expression>),
     Array(Vec<Expression>),
+    /// `start..end`, end exclusive
+    Range(Option<Rc<Expression>>, Option<Rc<Expression>>),
+    /// `[key: value, ...]`, `[:]` when empty
+    Map(Vec<(Expression, Expression)>),
 
     Call(Rc<Expression>, Vec<Expression>),
     Index(
@@ -60,9 +68,13 @@ pub enum ExpressionNode {
         Option<Vec<(Option<Expression>, Expression, Pos)>>,
     ),
 
-    While(Rc<Expression>, Rc<Expression>),
+    /// Condition, body and label
+    While(Rc<Expression>, Rc<Expression>, Option<String>),
+    /// `for x in xs`, or `for key, value in xs`, with the loop's label
+    For(Vec<String>, Rc<Expression>, Rc<Expression>, Option<String>),
+    Switch(Rc<Expression>, Vec<(Pattern, Expression)>),
 
-    Struct(Vec<(String, Type)>, String),
+    Struct(Vec<(String, Type, Option<Expression>)>, String), // fields with optional defaults
     Enum(String, Vec<String>),
     Initialization(Rc<Expression>, Vec<(String, Expression)>),
 
@@ -82,6 +94,36 @@ impl Expression {
     }
 }
 
+#[derive(Debug, Clone, PartialEq)]
+pub enum PatternNode {
+    Wildcard,
+    Binding(String),
+    Literal(Expression),
+    Variant(Expression), // enum path, like `TokenType.String`
+    Array(Vec<Pattern>),
+    Struct(Rc<Expression>, Vec<(String, Pattern)>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pattern {
+    pub node: PatternNode,
+    pub pos: Pos,
+}
+
+impl Pattern {
+    pub fn new(node: PatternNode, pos: Pos) -> Self {
+        Pattern { node, pos }
+    }
+
+    // matches anything without looking at the value
+    pub fn is_catch_all(&self) -> bool {
+        match self.node {
+            PatternNode::Wildcard | PatternNode::Binding(_) => true,
+            _ => false,
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum Operator {
     Add,
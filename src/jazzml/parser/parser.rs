@@ -44,6 +44,29 @@ impl<'p> Parser<'p> {
                 let name = self.eat_type(&Identifier)?;
 
                 match self.current_lexeme().as_str() {
+                    // `label: while ...`
+                    ":" => {
+                        self.next()?;
+                        self.next_newline()?;
+
+                        let mut expression = self.parse_expression()?;
+
+                        match expression.node {
+                            ExpressionNode::While(.., ref mut label)
+                            | ExpressionNode::For(.., ref mut label) => *label = Some(name),
+
+                            _ => {
+                                return Err(response!(
+                                    Wrong(format!("label `{}` must be followed by a loop", name)),
+                                    self.source.file,
+                                    position
+                                ));
+                            }
+                        }
+
+                        Statement::new(StatementNode::Expression(expression), position)
+                    }
+
                     "=" => {
                         self.next()?;
 
@@ -128,13 +151,37 @@ impl<'p> Parser<'p> {
                 "break" => {
                     self.next()?;
 
-                    Statement::new(StatementNode::Break, position)
+                    Statement::new(StatementNode::Break(self.parse_jump_label()?), position)
                 }
 
                 "continue" => {
                     self.next()?;
 
-                    Statement::new(StatementNode::Continue, position)
+                    Statement::new(StatementNode::Continue(self.parse_jump_label()?), position)
+                }
+
+                "extern" => {
+                    self.next()?;
+
+                    let name = self.eat_type(&Identifier)?;
+
+                    self.eat_lexeme(":")?;
+
+                    let t = self.parse_type()?;
+
+                    Statement::new(StatementNode::Extern(name, t), self.span_from(position))
+                }
+
+                "import" => {
+                    self.next()?;
+
+                    let path = self.eat_type(&Str)?;
+
+                    self.eat_lexeme("as")?;
+
+                    let name = self.eat_type(&Identifier)?;
+
+                    Statement::new(StatementNode::Import(path, name), self.span_from(position))
                 }
 
                 _ => {
@@ -168,6 +215,15 @@ impl<'p> Parser<'p> {
         Ok(statement)
     }
 
+    // label after `break` or `continue`
+    fn parse_jump_label(&mut self) -> Result<Option<String>, ()> {
+        if self.remaining() > 0 && self.current_type() == TokenType::Identifier {
+            Ok(Some(self.eat()?))
+        } else {
+            Ok(None)
+        }
+    }
+
     fn parse_function(&mut self) -> Result<Expression, ()> {
         let mut position = self.current_position();
 
@@ -213,13 +269,53 @@ impl<'p> Parser<'p> {
     fn parse_expression(&mut self) -> Result<Expression, ()> {
         let atom = self.parse_atom()?;
 
-        if self.current_type() == TokenType::Operator {
-            self.parse_binary(atom)
+        let expression = if self.current_type() == TokenType::Operator {
+            self.parse_binary(atom)?
         } else {
-            Ok(atom)
+            atom
+        };
+
+        if self.remaining() > 0 && self.is_range() {
+            let position = expression.pos.clone();
+            self.parse_range(Some(expression), position)
+        } else {
+            Ok(expression)
         }
     }
 
+    // `[:]`
+    fn is_empty_map(&self) -> bool {
+        let lexeme = |offset: usize| {
+            self.tokens
+                .get(self.index + offset)
+                .map(|token| token.lexeme.as_str())
+        };
+
+        lexeme(1) == Some(":") && lexeme(2) == Some("]")
+    }
+
+    fn is_range(&self) -> bool {
+        self.current_type() == TokenType::Symbol && self.current_lexeme() == ".."
+    }
+
+    // `start..end`, either bound may be left out inside brackets
+    fn parse_range(&mut self, start: Option<Expression>, position: Pos) -> Result<Expression, ()> {
+        self.eat_lexeme("..")?;
+
+        let closed = self.current_type() == TokenType::Symbol && self.current_lexeme() == "]";
+
+        let end = if self.remaining() == 0 || closed {
+            None
+        } else {
+            Some(Rc::new(self.parse_expression()?))
+        };
+
+        Ok(Expression::new(
+            ExpressionNode::Range(start.map(Rc::new), end),
+            self.span_from(position),
+        ))
+    }
+
     fn parse_atom(&mut self) -> Result<Expression, ()> {
         use self::TokenType::*;
 
@@ -233,10 +329,24 @@ impl<'p> Parser<'p> {
             let position = self.current_position();
 
             let expression = match token_type {
-                Int => Expression::new(
-                    ExpressionNode::Int(self.eat()?.parse::<u64>().unwrap()),
-                    position,
-                ),
+                Int => {
+                    let literal = self.eat()?;
+
+                    // the lexer reads `-1` as a single literal
+                    if literal.starts_with('-') {
+                        let int = Expression::new(
+                            ExpressionNode::Int(literal[1..].parse::<u64>().unwrap()),
+                            position.clone(),
+                        );
+
+                        Expression::new(ExpressionNode::Neg(Rc::new(int)), position)
+                    } else {
+                        Expression::new(
+                            ExpressionNode::Int(literal.parse::<u64>().unwrap()),
+                            position,
+                        )
+                    }
+                }
 
                 Float => Expression::new(
                     ExpressionNode::Float(self.eat()?.parse::<f64>().unwrap()),
@@ -290,12 +400,41 @@ impl<'p> Parser<'p> {
                         position,
                     ),
 
-                    "[" => Expression::new(
-                        ExpressionNode::Array(
-                            self.parse_block_of(("[", "]"), &Self::_parse_expression_comma)?,
-                        ),
-                        self.span_from(position),
-                    ),
+                    "[" if self.is_empty_map() => {
+                        self.eat_lexeme("[")?;
+                        self.eat_lexeme(":")?;
+                        self.eat_lexeme("]")?;
+
+                        Expression::new(ExpressionNode::Map(Vec::new()), self.span_from(position))
+                    }
+
+                    "[" => {
+                        let entries = self.parse_block_of(("[", "]"), &Self::_parse_entry_comma)?;
+
+                        let node = if entries.iter().all(|entry| entry.1.is_none()) {
+                            let elements = entries.into_iter().map(|entry| entry.0).collect();
+
+                            ExpressionNode::Array(elements)
+                        } else {
+                            let mut pairs = Vec::new();
+
+                            for (key, value) in entries {
+                                if let Some(value) = value {
+                                    pairs.push((key, value))
+                                } else {
+                                    return Err(response!(
+                                        Wrong("expected `key: value` in map literal"),
+                                        self.source.file,
+                                        key.pos
+                                    ));
+                                }
+                            }
+
+                            ExpressionNode::Map(pairs)
+                        };
+
+                        Expression::new(node, self.span_from(position))
+                    }
 
                     "(" => {
                         self.next()?;
@@ -349,6 +488,28 @@ impl<'p> Parser<'p> {
                             )
                         }
 
+                        "enum" => {
+                            let mut position = self.current_position();
+
+                            self.next()?;
+                            self.next_newline()?;
+
+                            position = self.span_from(position);
+
+                            self.expect_lexeme("{")?;
+
+                            let variants =
+                                self.parse_block_of(("{", "}"), &Self::_parse_variant_comma)?;
+
+                            Expression::new(
+                                ExpressionNode::Enum(
+                                    format!("{}{}", self.source.file, position),
+                                    variants,
+                                ),
+                                position,
+                            )
+                        }
+
                         "if" => {
                             self.next()?;
 
@@ -430,11 +591,66 @@ impl<'p> Parser<'p> {
                                 ExpressionNode::While(
                                     Rc::new(condition),
                                     Rc::new(self.parse_expression()?),
+                                    None,
                                 ),
                                 position,
                             )
                         }
 
+                        "for" => {
+                            self.next()?;
+
+                            self.next_newline()?;
+
+                            let mut names = vec![self.eat_type(&TokenType::Identifier)?];
+
+                            if self.current_lexeme() == "," {
+                                self.next()?;
+                                self.next_newline()?;
+
+                                names.push(self.eat_type(&TokenType::Identifier)?);
+                            }
+
+                            self.next_newline()?;
+                            self.eat_lexeme("in")?;
+                            self.next_newline()?;
+
+                            let iterable = self.parse_expression()?;
+
+                            self.next_newline()?;
+
+                            self.expect_lexeme("{")?;
+
+                            let position = self.span_from(position);
+
+                            Expression::new(
+                                ExpressionNode::For(
+                                    names,
+                                    Rc::new(iterable),
+                                    Rc::new(self.parse_expression()?),
+                                    None,
+                                ),
+                                position,
+                            )
+                        }
+
+                        "switch" => {
+                            self.next()?;
+                            self.next_newline()?;
+
+                            let subject = self.parse_expression()?;
+
+                            let position = self.span_from(position);
+
+                            self.next_newline()?;
+
+                            self.expect_lexeme("{")?;
+
+                            let arms = self.parse_block_of(("{", "}"), &Self::_parse_arm_comma)?;
+
+                            Expression::new(ExpressionNode::Switch(Rc::new(subject), arms), position)
+                        }
+
                         "new" => {
                             self.next()?;
                             self.next_newline()?;
@@ -504,7 +720,12 @@ impl<'p> Parser<'p> {
                 "[" => {
                     self.next()?;
 
-                    let expr = self.parse_expression()?;
+                    let expr = if self.is_range() {
+                        let position = self.current_position();
+                        self.parse_range(None, position)?
+                    } else {
+                        self.parse_expression()?
+                    };
 
                     self.eat_lexeme("]")?;
 
@@ -620,6 +841,60 @@ impl<'p> Parser<'p> {
         ))
     }
 
+    fn parse_pattern(&mut self) -> Result<Pattern, ()> {
+        use self::TokenType::*;
+
+        let position = self.current_position();
+
+        let node = match self.current_type() {
+            Int | Float | Str | Char | Bool => PatternNode::Literal(self.parse_atom()?),
+
+            Operator if self.current_lexeme() == "-" => PatternNode::Literal(self.parse_atom()?),
+
+            Symbol if self.current_lexeme() == "[" => PatternNode::Array(
+                self.parse_block_of(("[", "]"), &Self::_parse_pattern_comma)?,
+            ),
+
+            Identifier => {
+                let next = self.tokens.get(self.index + 1).map(|t| t.lexeme.clone());
+
+                match next.as_ref().map(|lexeme| lexeme.as_str()) {
+                    _ if self.current_lexeme() == "_" => {
+                        self.next()?;
+
+                        PatternNode::Wildcard
+                    }
+
+                    Some(".") => PatternNode::Variant(self.parse_atom()?),
+
+                    Some("{") => {
+                        let name = Expression::new(
+                            ExpressionNode::Identifier(self.eat()?),
+                            position.clone(),
+                        );
+
+                        let fields =
+                            self.parse_block_of(("{", "}"), &Self::_parse_field_pattern_comma)?;
+
+                        PatternNode::Struct(Rc::new(name), fields)
+                    }
+
+                    _ => PatternNode::Binding(self.eat()?),
+                }
+            }
+
+            _ => {
+                return Err(response!(
+                    Wrong(format!("expected pattern found `{}`", self.current_lexeme())),
+                    self.source.file,
+                    self.current_position()
+                ));
+            }
+        };
+
+        Ok(Pattern::new(node, self.span_from(position)))
+    }
+
     fn parse_type(&mut self) -> Result<Type, ()> {
         use self::TokenType::*;
 
@@ -695,6 +970,18 @@ impl<'p> Parser<'p> {
 
                     self.next_newline()?;
 
+                    if self.current_lexeme() == ":" {
+                        self.next()?;
+                        self.next_newline()?;
+
+                        let value = self.parse_type()?;
+
+                        self.next_newline()?;
+                        self.eat_lexeme("]")?;
+
+                        return Ok(Type::map(t, value));
+                    }
+
                     let mut len = None;
 
                     if self.current_lexeme() == ";" {
@@ -998,6 +1285,121 @@ impl<'p> Parser<'p> {
         }
     }
 
+    // Static method for parsing `pattern => expr` arms of a switch, separated by `,` or newlines
+    fn _parse_arm_comma(self: &mut Self) -> Result<Option<(Pattern, Expression)>, ()> {
+        self.next_newline()?;
+
+        if self.remaining() == 0 {
+            return Ok(None);
+        }
+
+        let pattern = self.parse_pattern()?;
+
+        self.eat_lexeme("=>")?;
+        self.next_newline()?;
+
+        let body = self.parse_expression()?;
+
+        if self.remaining() > 0 {
+            if ![",", "\n"].contains(&self.current_lexeme().as_str()) {
+                return Err(response!(
+                    Wrong(format!(
+                        "expected `,` or newline, found `{}`",
+                        self.current_lexeme()
+                    )),
+                    self.source.file,
+                    self.current_position()
+                ));
+            } else {
+                self.next()?;
+            }
+
+            self.next_newline()?
+        }
+
+        Ok(Some((pattern, body)))
+    }
+
+    fn _parse_pattern_comma(self: &mut Self) -> Result<Option<Pattern>, ()> {
+        self.next_newline()?;
+
+        if self.remaining() == 0 {
+            return Ok(None);
+        }
+
+        let pattern = self.parse_pattern()?;
+
+        self.next_newline()?;
+
+        if self.remaining() > 0 {
+            self.eat_lexeme(",")?;
+        }
+
+        Ok(Some(pattern))
+    }
+
+    // `field: pattern`, or just `field` to bind it under its own name
+    fn _parse_field_pattern_comma(self: &mut Self) -> Result<Option<(String, Pattern)>, ()> {
+        self.next_newline()?;
+
+        if self.remaining() == 0 {
+            return Ok(None);
+        }
+
+        let position = self.current_position();
+        let name = self.eat_type(&TokenType::Identifier)?;
+
+        let pattern = if self.remaining() > 0 && self.current_lexeme() == ":" {
+            self.next()?;
+
+            self.parse_pattern()?
+        } else {
+            Pattern::new(PatternNode::Binding(name.clone()), position)
+        };
+
+        self.next_newline()?;
+
+        if self.remaining() > 0 {
+            self.eat_lexeme(",")?;
+        }
+
+        Ok(Some((name, pattern)))
+    }
+
+    // Static method for parsing enum variants, separated by `,` or newlines
+    fn _parse_variant_comma(self: &mut Self) -> Result<Option<String>, ()> {
+        if self.remaining() > 0 && self.current_lexeme() == "\n" {
+            self.next_newline()?
+        }
+
+        if self.remaining() == 0 {
+            return Ok(None);
+        }
+
+        let variant = self.eat_type(&TokenType::Identifier)?;
+
+        if self.remaining() > 0 {
+            if ![",", "\n"].contains(&self.current_lexeme().as_str()) {
+                return Err(response!(
+                    Wrong(format!(
+                        "expected `,` or newline, found `{}`",
+                        self.current_lexeme()
+                    )),
+                    self.source.file,
+                    self.current_position()
+                ));
+            } else {
+                self.next()?;
+            }
+
+            if self.remaining() > 0 && self.current_lexeme() == "\n" {
+                self.next_newline()?
+            }
+        }
+
+        Ok(Some(variant))
+    }
+
     // Static method for parsing sequence `expr* ,* \n*` - for things like [1, 2, 3, 4,]
     fn _parse_expression_comma(self: &mut Self) -> Result<Option<Expression>, ()> {
         if self.remaining() > 0 && self.current_lexeme() == "\n" {
@@ -1021,6 +1423,34 @@ impl<'p> Parser<'p> {
         expression
     }
 
+    // An array element, or a `key: value` map entry
+    fn _parse_entry_comma(self: &mut Self) -> Result<Option<(Expression, Option<Expression>)>, ()> {
+        self.next_newline()?;
+
+        if self.remaining() == 0 {
+            return Ok(None);
+        }
+
+        let key = self.parse_expression()?;
+
+        let value = if self.remaining() > 0 && self.current_lexeme() == ":" {
+            self.next()?;
+            self.next_newline()?;
+
+            Some(self.parse_expression()?)
+        } else {
+            None
+        };
+
+        self.next_newline()?;
+
+        if self.remaining() > 0 {
+            self.eat_lexeme(",")?;
+        }
+
+        Ok(Some((key, value)))
+    }
+
     fn _parse_param_comma(self: &mut Self) -> Result<Option<(String, Type)>, ()> {
         if self.remaining() > 0 && self.current_lexeme() == "\n" {
             self.next()?
@@ -1107,7 +1537,9 @@ impl<'p> Parser<'p> {
         Ok(param)
     }
 
-    fn _parse_struct_param_comma(self: &mut Self) -> Result<Option<(String, Type)>, ()> {
+    fn _parse_struct_param_comma(
+        self: &mut Self,
+    ) -> Result<Option<(String, Type, Option<Expression>)>, ()> {
         if self.remaining() > 0 && self.current_lexeme() == "\n" {
             self.next()?
         }
@@ -1121,7 +1553,16 @@ impl<'p> Parser<'p> {
         self.eat_lexeme(":")?;
 
         let value = self.parse_type()?;
-        let param = Some((name, value));
+
+        let mut default = None;
+
+        if self.current_lexeme() == "=" {
+            self.next()?;
+
+            default = Some(self.parse_expression()?);
+        }
+
+        let param = Some((name, value, default));
 
         if self.remaining() > 0 {
             if ![",", "\n"].contains(&self.current_lexeme().as_str()) {
@@ -1169,18 +1610,21 @@ impl<'p> Parser<'p> {
 
         let node = match expression.node {
             Binary(ref left, ref op, ref right) => {
+                // what doesn't fit is left for the VM to report
+                let int = |n: Option<u64>| n.map_or(expression.node.clone(), Int);
+
                 let node = match (
                     &Self::fold_expression(&*left)?.node,
                     op,
                     &Self::fold_expression(&*right)?.node,
                 ) {
-                    (&Int(ref a), &Add, &Int(ref b)) => Int(a + b),
+                    (&Int(a), &Add, &Int(b)) => int(a.checked_add(b)),
                     (&Float(ref a), &Add, &Float(ref b)) => Float(a + b),
-                    (&Int(ref a), &Sub, &Int(ref b)) => Int(a - b),
+                    (&Int(a), &Sub, &Int(b)) => int(a.checked_sub(b)),
                     (&Float(ref a), &Sub, &Float(ref b)) => Float(a - b),
-                    (&Int(ref a), &Mul, &Int(ref b)) => Int(a * b),
+                    (&Int(a), &Mul, &Int(b)) => int(a.checked_mul(b)),
                     (&Float(ref a), &Mul, &Float(ref b)) => Float(a * b),
-                    (&Int(ref a), &Div, &Int(ref b)) => Int(a / b),
+                    (&Int(a), &Div, &Int(b)) => int(a.checked_div(b)),
                     (&Float(ref a), &Div, &Float(ref b)) => Float(a / b),
 
                     _ => expression.node.clone(),
@@ -0,0 +1,79 @@
+pub mod string;
+
+use super::convert::IntoNative;
+use super::error::RuntimeError;
+use super::value::{NativeFn, Object, Value, VAR_ARGS};
+use super::visitor::{Type, TypeNode};
+use super::vm::VirtualMachine;
+
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// Native functions reached through a global object, like `string.len`.
+pub struct NativeModule {
+    pub name: String,
+    functions: Vec<(String, NativeFn, i32, Type)>,
+}
+
+impl NativeModule {
+    pub fn new(name: &str) -> NativeModule {
+        NativeModule {
+            name: name.to_owned(),
+            functions: vec![],
+        }
+    }
+
+    /// Adds a plain Rust function, see `Engine::register_native`
+    pub fn native<Args, F: IntoNative<Args>>(&mut self, name: &str, f: F) {
+        self.functions.push((
+            name.to_owned(),
+            f.into_native(),
+            F::arity() as i32,
+            F::signature(),
+        ));
+    }
+
+    /// Adds a function working on raw values, see `Engine::register_fn`
+    pub fn raw<T>(&mut self, name: &str, t: Type, f: T)
+    where
+        T: Fn(&mut VirtualMachine, Vec<Value>) -> Result<Value, RuntimeError> + 'static,
+    {
+        let nargs = match t.node {
+            TypeNode::Func(ref params, ..) if !params.iter().any(Type::is_splat) => {
+                params.len() as i32
+            }
+            _ => VAR_ARGS,
+        };
+
+        self.functions.push((name.to_owned(), Rc::new(f), nargs, t));
+    }
+
+    /// Type of the module object, as seen by the type checker
+    pub fn module_type(&self) -> Type {
+        let members = self
+            .functions
+            .iter()
+            .map(|(name, _, _, t)| (name.clone(), t.clone()))
+            .collect::<HashMap<String, Type>>();
+
+        Type::from(TypeNode::Module(members))
+    }
+}
+
+impl VirtualMachine {
+    /// Binds the functions of `module` to a global object named after it
+    pub fn register_module(&mut self, module: &NativeModule) -> Value {
+        let mut obj = Object::new();
+
+        for (name, f, nargs, _) in module.functions.iter() {
+            let full_name = format!("{}.{}", module.name, name);
+            let id = self.new_native_func(full_name, f.clone(), *nargs);
+            obj.store(Value::Str(name.clone()), Value::FuncRef(id));
+        }
+
+        let module_obj = Value::ObjectRef(self.register_object(obj));
+        self.globals
+            .insert(Value::Str(module.name.clone()), module_obj.clone());
+        module_obj
+    }
+}
@@ -0,0 +1,196 @@
+use super::super::error::RuntimeError;
+use super::super::value::{slice_range, Value};
+use super::super::visitor::{Type, TypeNode};
+use super::super::vm::VirtualMachine;
+use super::NativeModule;
+
+use std::char;
+use std::convert::TryFrom;
+
+/// The `string` module. Positions count characters, not bytes.
+pub fn module() -> NativeModule {
+    let mut module = NativeModule::new("string");
+
+    module.native("len", |s: String| s.chars().count() as i64);
+    module.native("substr", substr);
+    module.native("find", find);
+    module.native("contains", |s: String, pattern: String| {
+        s.contains(&pattern)
+    });
+    module.native("starts_with", |s: String, prefix: String| {
+        s.starts_with(&prefix)
+    });
+    module.native("ends_with", |s: String, suffix: String| {
+        s.ends_with(&suffix)
+    });
+    module.native("split", split);
+    module.native("join", |parts: Vec<String>, sep: String| parts.join(&sep));
+    module.native("trim", |s: String| s.trim().to_owned());
+    module.native("replace", |s: String, from: String, to: String| {
+        s.replace(&from, &to)
+    });
+    module.native("to_upper", |s: String| s.to_uppercase());
+    module.native("to_lower", |s: String| s.to_lowercase());
+    module.native("repeat", repeat);
+
+    module.native("is_digit", |s: String| all(&s, |c| c.is_ascii_digit()));
+    module.native("is_alpha", |s: String| all(&s, char::is_alphabetic));
+    module.native("is_alnum", |s: String| all(&s, char::is_alphanumeric));
+    module.native("is_space", |s: String| all(&s, char::is_whitespace));
+
+    module.native("char_code", char_code);
+    module.native("from_char_code", from_char_code);
+    module.native("parse_int", parse_int);
+    module.native("parse_float", parse_float);
+
+    let str_type = Type::from(TypeNode::Str);
+    module.raw(
+        "format",
+        Type::function(
+            vec![str_type.clone(), Type::splat(Type::from(TypeNode::Any))],
+            str_type,
+            false,
+        ),
+        format,
+    );
+
+    module
+}
+
+// non-empty and every character satisfies `f`
+fn all(s: &str, f: fn(char) -> bool) -> bool {
+    !s.is_empty() && s.chars().all(f)
+}
+
+fn substr(s: String, start: i64, end: i64) -> Result<String, RuntimeError> {
+    let chars = s.chars().collect::<Vec<char>>();
+    let (start, end) = slice_range(Some(start), Some(end), chars.len())?;
+
+    Ok(chars[start..end].iter().collect())
+}
+
+/// Character position of the first occurrence of `pattern`, or -1
+fn find(s: String, pattern: String) -> i64 {
+    match s.find(&pattern) {
+        Some(byte) => s[..byte].chars().count() as i64,
+        None => -1,
+    }
+}
+
+fn split(s: String, sep: String) -> Result<Vec<String>, RuntimeError> {
+    if sep.is_empty() {
+        return Err(RuntimeError::new("Can't split on an empty separator"));
+    }
+
+    Ok(s.split(&sep).map(str::to_owned).collect())
+}
+
+fn repeat(s: String, n: i64) -> Result<String, RuntimeError> {
+    if n < 0 {
+        return Err(RuntimeError::new(format!(
+            "Can't repeat a string {} times",
+            n
+        )));
+    }
+
+    Ok(s.repeat(n as usize))
+}
+
+fn char_code(s: String) -> Result<i64, RuntimeError> {
+    let mut chars = s.chars();
+
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Ok(c as i64),
+        _ => Err(RuntimeError::new(format!(
+            "Expected a single character, found `{}`",
+            s
+        ))),
+    }
+}
+
+fn from_char_code(code: i64) -> Result<String, RuntimeError> {
+    match u32::try_from(code).ok().and_then(char::from_u32) {
+        Some(c) => Ok(c.to_string()),
+        None => Err(RuntimeError::new(format!(
+            "Invalid character code {}",
+            code
+        ))),
+    }
+}
+
+/// The integer `s` spells, or `null` when it doesn't spell one
+fn parse_int(s: String) -> Option<i64> {
+    s.trim().parse().ok()
+}
+
+/// The float `s` spells, or `null` when it doesn't spell one
+fn parse_float(s: String) -> Option<f64> {
+    s.trim().parse().ok()
+}
+
+/// Replaces `{}` with the next argument and `{N}` with argument N, `{{` and
+/// `}}` are literal braces.
+fn format(vm: &mut VirtualMachine, args: Vec<Value>) -> Result<Value, RuntimeError> {
+    let template = match args.first() {
+        Some(Value::Str(s)) => s.clone(),
+        Some(v) => {
+            return Err(RuntimeError::new(format!(
+                "Expected format string, found `{}`",
+                v.as_str(vm)
+            )));
+        }
+        None => return Err(RuntimeError::new("Missing format string")),
+    };
+    let args = &args[1..];
+
+    let mut result = String::new();
+    let mut next = 0;
+    let mut chars = template.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                result.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                result.push('}');
+            }
+            '{' => {
+                let mut index = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => index.push(c),
+                        None => return Err(RuntimeError::new("Unclosed `{` in format string")),
+                    }
+                }
+
+                let i = if index.is_empty() {
+                    next += 1;
+                    next - 1
+                } else {
+                    index.parse::<usize>().map_err(|_| {
+                        RuntimeError::new(format!("Invalid placeholder `{{{}}}`", index))
+                    })?
+                };
+
+                match args.get(i) {
+                    Some(value) => result.push_str(&value.as_str(vm)),
+                    None => {
+                        return Err(RuntimeError::new(format!(
+                            "No argument {} for format string, {} given",
+                            i,
+                            args.len()
+                        )));
+                    }
+                }
+            }
+            '}' => return Err(RuntimeError::new("Unmatched `}` in format string")),
+            c => result.push(c),
+        }
+    }
+
+    Ok(Value::Str(result))
+}
@@ -1,3 +1,5 @@
+use super::error::{Location, RuntimeError};
+use super::lexer::Pos;
 use super::opcodes::Opcode;
 use super::vm::VirtualMachine;
 use std::cell::Ref as SRef;
@@ -8,12 +10,19 @@ use std::rc::Rc;
 pub type Ref<T> = Rc<RefCell<T>>;
 pub type ObjectRef = Ref<Object>;
 pub type FuncRef = Ref<Function>;
+pub type StructRef = Ref<Struct>;
+pub type EnumRef = Ref<Enum>;
+pub type ClosureRef = Ref<Closure>;
 
 pub const VAR_ARGS: i32 = -1;
 
+/// Function implemented by the host. Closures can carry whatever state they
+/// need, errors are raised as JazzML runtime errors.
+pub type NativeFn = Rc<dyn Fn(&mut VirtualMachine, Vec<Value>) -> Result<Value, RuntimeError>>;
+
 #[derive(Clone)]
 pub enum FuncKind {
-    Native(&'static Fn(&mut VirtualMachine, Vec<Value>) -> Value),
+    Native(NativeFn),
     Interpret(Vec<Opcode>),
 }
 
@@ -30,12 +39,85 @@ impl fmt::Debug for FuncKind {
 
 #[derive(Clone)]
 pub struct Function {
+    /// Name shown in stack traces
+    pub name: String,
     pub nargs: i32,
     pub kind: FuncKind,
     pub args: Vec<String>,
+    /// Variables captured from the defining function, see `Opcode::MakeClosure`
+    pub captures: Vec<Capture>,
+    /// Number of local slots in a frame of this function
+    pub locals: usize,
+    pub lines: LineTable,
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+/// Source positions of compiled code. An entry covers the instructions from
+/// its pc up to the next entry.
+#[derive(Clone, Debug, Default)]
+pub struct LineTable {
+    /// Source file the code was compiled from
+    pub file: String,
+    entries: Vec<(usize, Pos)>,
+}
+
+impl LineTable {
+    pub fn add(&mut self, pc: usize, pos: Pos) {
+        match self.entries.last_mut() {
+            Some(last) if last.0 == pc => last.1 = pos,
+            _ => self.entries.push((pc, pos)),
+        }
+    }
+
+    pub fn get(&self, pc: usize) -> Option<&Pos> {
+        match self.entries.binary_search_by_key(&pc, |entry| entry.0) {
+            Ok(i) => Some(&self.entries[i].1),
+            Err(0) => None,
+            Err(i) => Some(&self.entries[i - 1].1),
+        }
+    }
+
+    /// Position of the instruction at `pc` along with the file
+    pub fn location(&self, pc: usize) -> Option<Location> {
+        self.get(pc).map(|pos| Location {
+            file: self.file.clone(),
+            pos: pos.clone(),
+        })
+    }
+
+    /// Pcs where a new position starts, in order
+    pub fn entries(&self) -> &[(usize, Pos)] {
+        &self.entries
+    }
+}
+
+/// Where a closure finds a captured variable when it is created.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Capture {
+    /// Local slot of the defining function
+    Local(u16),
+    /// Upvalue of the defining closure
+    Upvalue(usize),
+}
+
+/// Function together with the cells of its captured variables.
+#[derive(Clone, Debug)]
+pub struct Closure {
+    pub func: usize,
+    pub upvalues: Vec<Ref<Value>>,
+}
+
+/// Values compare and hash as map keys do, which is stricter than the
+/// language's `==`:
+///
+/// - ints, bools and strings are equal when their contents are
+/// - floats are equal when their numbers are, except that `-0.0` equals `0.0`
+///   and every NaN equals every other NaN, so each float can find itself
+/// - an int never equals a float, `1` and `1.0` are different keys
+/// - null equals null
+/// - arrays, objects, functions, closures, structs and enums are equal only
+///   to themselves, by heap id, whatever they contain
+/// - enum variants are equal when both enum and variant are
+#[derive(Clone, Debug)]
 pub enum Value {
     Int(i64),
     Float(u64),
@@ -44,75 +126,198 @@ pub enum Value {
     Array(usize),
     ObjectRef(usize),
     FuncRef(usize),
+    Closure(usize),
+    StructRef(usize),
+    EnumRef(usize),
+    /// Enum id and variant index
+    Variant(usize, usize),
     Null,
 }
 
 use std::hash::{Hash, Hasher};
+use std::mem;
+
+/// Bits of a float as a key: `-0.0` becomes `0.0` and NaNs a single NaN
+fn float_key(bits: u64) -> u64 {
+    let f = f64::from_bits(bits);
+
+    if f.is_nan() {
+        f64::NAN.to_bits()
+    } else if f == 0.0 {
+        0
+    } else {
+        bits
+    }
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Value) -> bool {
+        use self::Value::*;
+        match (self, other) {
+            (Int(a), Int(b)) => a == b,
+            (Float(a), Float(b)) => float_key(*a) == float_key(*b),
+            (Bool(a), Bool(b)) => a == b,
+            (Str(a), Str(b)) => a == b,
+            (Array(a), Array(b))
+            | (ObjectRef(a), ObjectRef(b))
+            | (FuncRef(a), FuncRef(b))
+            | (Closure(a), Closure(b))
+            | (StructRef(a), StructRef(b))
+            | (EnumRef(a), EnumRef(b)) => a == b,
+            (Variant(a, i), Variant(b, j)) => a == b && i == j,
+            (Null, Null) => true,
+            _ => false,
+        }
+    }
+}
+
+impl Eq for Value {}
 
 impl Hash for Value {
     fn hash<H: Hasher>(&self, state: &mut H) {
         use self::Value::*;
+        mem::discriminant(self).hash(state);
         match self {
             Int(i) => i.hash(state),
-            Float(bits) => bits.hash(state),
+            Float(bits) => float_key(*bits).hash(state),
             Str(s) => s.hash(state),
-            ObjectRef(id) => id.hash(state),
-            FuncRef(id) => id.hash(state),
-            Bool(b) => b.hash(state),
-            Null => {
-                let u: u32 = rand::random();
-                u.hash(state);
+            Array(id) | ObjectRef(id) | FuncRef(id) | Closure(id) | StructRef(id) | EnumRef(id) => {
+                id.hash(state)
+            }
+            Variant(id, variant) => {
+                id.hash(state);
+                variant.hash(state);
             }
-            Array(arr) => arr.hash(state),
+            Bool(b) => b.hash(state),
+            Null => (),
         }
     }
 }
 
 impl Value {
-    pub fn as_f64(&self, _vm: &VirtualMachine) -> f64 {
+    pub fn as_f64(&self, _vm: &VirtualMachine) -> Result<f64, RuntimeError> {
         match self {
-            Value::Float(bits) => f64::from_bits(*bits),
-            Value::Int(i) => *i as f64,
-            Value::Str(s) => s.parse().unwrap(),
-            Value::Null => 0.0,
-            _ => unimplemented!(),
+            Value::Float(bits) => Ok(f64::from_bits(*bits)),
+            Value::Int(i) => Ok(*i as f64),
+            Value::Str(s) => s
+                .parse()
+                .map_err(|_| RuntimeError::new(format!("Can't convert `{}` to float", s))),
+            Value::Null => Ok(0.0),
+            v => Err(RuntimeError::new(format!(
+                "Can't convert `{}` to float",
+                v.as_str(_vm)
+            ))),
         }
     }
-    pub fn as_int(&self, _vm: &VirtualMachine) -> i64 {
+    pub fn as_int(&self, _vm: &VirtualMachine) -> Result<i64, RuntimeError> {
         match self {
-            Value::Float(bits) => f64::from_bits(*bits) as i64,
-            Value::Int(i) => *i,
-            Value::Str(s) => s.parse().unwrap(),
-            Value::Null => 0,
-            _ => unimplemented!(),
+            Value::Float(bits) => Ok(f64::from_bits(*bits) as i64),
+            Value::Int(i) => Ok(*i),
+            Value::Str(s) => s
+                .parse()
+                .map_err(|_| RuntimeError::new(format!("Can't convert `{}` to int", s))),
+            Value::Null => Ok(0),
+            v => Err(RuntimeError::new(format!(
+                "Can't convert `{}` to int",
+                v.as_str(_vm)
+            ))),
         }
     }
     pub fn as_str(&self, _vm: &VirtualMachine) -> String {
+        self.as_str_within(_vm, &mut vec![])
+    }
+
+    // `open` holds the arrays and objects being printed around this value, one
+    // of them showing up again is a cycle and prints as `[...]`
+    fn as_str_within(&self, _vm: &VirtualMachine, open: &mut Vec<Value>) -> String {
         match self {
             Value::Str(s) => s.to_string(),
             Value::Int(i) => i.to_string(),
             Value::Float(bits) => f64::from_bits(*bits).to_string(),
-            Value::Array(_arr) => format!("array"),
+            Value::Array(_) | Value::ObjectRef(_) if open.contains(self) => "[...]".into(),
+            Value::Array(id) => {
+                let array = match _vm.get_array(id) {
+                    Ok(array) => array,
+                    Err(_) => return format!("<collected array {}>", id),
+                };
+                open.push(self.clone());
+                let elements = array
+                    .vec
+                    .borrow()
+                    .iter()
+                    .map(|value| value.as_str_within(_vm, open))
+                    .collect::<Vec<String>>();
+                open.pop();
+
+                format!("[{}]", elements.join(", "))
+            }
             Value::Null => "null".into(),
             Value::ObjectRef(id) => {
-                let obj = _vm.get_object(&id).borrow().clone();
-                return format!("{:?}", obj.map);
+                let obj = match _vm.get_object(&id) {
+                    Ok(obj) => obj.borrow().clone(),
+                    Err(_) => return format!("<collected object {}>", id),
+                };
+
+                open.push(self.clone());
+                let mut entries = obj
+                    .map
+                    .iter()
+                    .map(|(key, value)| {
+                        let key = key.as_str_within(_vm, open);
+                        format!("{}: {}", key, value.as_str_within(_vm, open))
+                    })
+                    .collect::<Vec<String>>();
+                entries.sort();
+                open.pop();
+
+                if let Some(ref name) = obj.name {
+                    return format!("{} {{ {} }}", name, entries.join(", "));
+                }
+                if entries.is_empty() {
+                    return "[:]".to_owned();
+                }
+                return format!("[{}]", entries.join(", "));
             }
+            Value::FuncRef(id) => format!("<function {}>", _vm.get_func(&id).borrow().name),
+            Value::Closure(id) => match _vm.get_closure(&id) {
+                Ok(closure) => {
+                    let func = closure.borrow().func;
+                    format!("<closure {}>", _vm.get_func(&func).borrow().name)
+                }
+                Err(_) => format!("<collected closure {}>", id),
+            },
+            Value::StructRef(id) => match _vm.get_struct(&id) {
+                Ok(s) => format!("<struct {}>", s.borrow().name),
+                Err(_) => format!("<struct {}>", id),
+            },
+            Value::EnumRef(id) => match _vm.get_enum(&id) {
+                Ok(e) => format!("<enum {}>", e.borrow().name),
+                Err(_) => format!("<enum {}>", id),
+            },
+            Value::Variant(id, variant) => match _vm.get_enum(&id) {
+                Ok(e) => e.borrow().variants[*variant].clone(),
+                Err(_) => format!("<variant {} of enum {}>", variant, id),
+            },
             Value::Bool(b) => format!("{}", b),
-            _ => unimplemented!(),
         }
     }
-    pub fn as_object_id(&self) -> usize {
+    pub fn as_object_id(&self) -> Result<usize, RuntimeError> {
         match self {
-            Value::ObjectRef(id) => *id,
-            _ => unimplemented!(),
+            Value::ObjectRef(id) => Ok(*id),
+            _ => Err(RuntimeError::new(format!(
+                "Expected object, found `{:?}`",
+                self
+            ))),
         }
     }
 
-    pub fn as_func_id(&mut self) -> usize {
+    pub fn as_func_id(&mut self) -> Result<usize, RuntimeError> {
         match self {
-            Value::FuncRef(id) => *id,
-            _ => unimplemented!(),
+            Value::FuncRef(id) => Ok(*id),
+            _ => Err(RuntimeError::new(format!(
+                "Expected function, found `{:?}`",
+                self
+            ))),
         }
     }
 }
@@ -141,15 +346,137 @@ impl Object {
     }
 }
 
+/// Runtime descriptor of a struct type, created by `Opcode::MakeStruct`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Struct {
+    pub name: String,
+    pub fields: Vec<String>,
+    /// Functions computing the default of a field, called for every instance
+    /// so that instances don't share arrays or objects
+    pub defaults: FnvHashMap<String, Value>,
+}
+
+impl Struct {
+    pub fn new(name: String) -> Struct {
+        Struct {
+            name,
+            fields: vec![],
+            defaults: FnvHashMap::default(),
+        }
+    }
+
+    /// Builds an instance of this struct from the initialized fields, filling
+    /// the rest from the defaults.
+    pub fn instantiate(
+        &self,
+        vm: &mut VirtualMachine,
+        mut init: FnvHashMap<Value, Value>,
+    ) -> Result<Object, RuntimeError> {
+        let mut obj = Object::new();
+        obj.name = Some(self.name.clone());
+
+        for field in self.fields.iter() {
+            let key = Value::Str(field.clone());
+            let value = match init.remove(&key) {
+                Some(value) => value,
+                None => match self.defaults.get(field) {
+                    Some(default) => vm.call_value(default, vec![])?,
+                    None => {
+                        return Err(RuntimeError::new(format!(
+                            "Missing field `{}` in initialization of `{}`",
+                            field, self.name
+                        )));
+                    }
+                },
+            };
+
+            obj.store(key, value);
+        }
+
+        if let Some((key, _)) = init.into_iter().next() {
+            return Err(RuntimeError::new(format!(
+                "No such member `{:?}` in struct `{}`",
+                key, self.name
+            )));
+        }
+
+        Ok(obj)
+    }
+}
+
+/// Runtime descriptor of an enum type, created by `Opcode::MakeEnum`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Enum {
+    pub name: String,
+    pub variants: Vec<String>,
+}
+
+impl Enum {
+    pub fn new(name: String, variants: Vec<String>) -> Enum {
+        Enum { name, variants }
+    }
+
+    pub fn variant(&self, name: &str) -> Option<usize> {
+        self.variants.iter().position(|variant| variant == name)
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct ArrayRef {
     pub vec: Ref<Vec<Value>>,
 }
 
 impl ArrayRef {
-    pub fn new() -> ArrayRef {
+    pub fn new(elements: Vec<Value>) -> ArrayRef {
         Self {
-            vec: Ref::new(RefCell::new(vec![])),
+            vec: Ref::new(RefCell::new(elements)),
+        }
+    }
+}
+
+/// Position of `index` in a sequence of `len` elements, negative indices
+/// counting from the end.
+pub fn array_index(index: i64, len: usize) -> Result<usize, RuntimeError> {
+    let position = if index < 0 { index + len as i64 } else { index };
+
+    if position < 0 || position >= len as i64 {
+        return Err(RuntimeError::new(format!(
+            "Index {} out of bounds for length {}",
+            index, len
+        )));
+    }
+
+    Ok(position as usize)
+}
+
+/// Range of a slice `start..end` of a sequence of `len` elements. Open
+/// bounds are `None`, negative bounds count from the end.
+pub fn slice_range(
+    start: Option<i64>,
+    end: Option<i64>,
+    len: usize,
+) -> Result<(usize, usize), RuntimeError> {
+    let bound = |bound: i64| {
+        let position = if bound < 0 { bound + len as i64 } else { bound };
+
+        if position < 0 || position > len as i64 {
+            return Err(RuntimeError::new(format!(
+                "Slice bound {} out of bounds for length {}",
+                bound, len
+            )));
         }
+        Ok(position as usize)
+    };
+
+    let start = start.map(bound).unwrap_or(Ok(0))?;
+    let end = end.map(bound).unwrap_or(Ok(len))?;
+
+    if start > end {
+        return Err(RuntimeError::new(format!(
+            "Slice starts at {} but ends at {}",
+            start, end
+        )));
     }
+
+    Ok((start, end))
 }
@@ -17,9 +17,12 @@ pub enum TypeNode {
     Nil,
     Id(Rc<Expression>),
     Array(Rc<Type>, Option<usize>),
+    Map(Rc<Type>, Rc<Type>),
     Func(Vec<Type>, Rc<Type>, Option<Rc<ExpressionNode>>, bool),
     Module(HashMap<String, Type>),
     Struct(HashMap<String, Type>, String),
+    /// Variants, the id telling enums apart and the name it was bound to
+    Enum(Vec<String>, String, String),
     This,
 }
 
@@ -72,10 +75,12 @@ impl TypeNode {
             (&This, &This) => true,
             (&Id(ref a), &Id(ref b)) => a == b,
             (&Array(ref a, ref la), &Array(ref b, ref lb)) => a == b && (la == &None || la == lb),
+            (&Map(ref ak, ref av), &Map(ref bk, ref bv)) => ak == bk && av == bv,
             (&Func(ref a_params, ref a_retty, .., a), &Func(ref b_params, ref b_retty, .., b)) => {
                 a_params == b_params && a_retty == b_retty && a == b
             }
             (&Struct(ref content, _), &Struct(ref content_b, _)) => content == content_b,
+            (&Enum(_, ref id, _), &Enum(_, ref id_b, _)) => id == id_b,
             _ => false,
         }
     }
@@ -93,13 +98,18 @@ impl PartialEq for TypeNode {
             (&Bool, &Bool) => true,
             (&Nil, &Nil) => true,
             (&This, &This) => true,
-            (&Array(ref a, ref la), &Array(ref b, ref lb)) => a == b && (la == &None || la == lb),
+            // arrays of unknown length match any length
+            (&Array(ref a, ref la), &Array(ref b, ref lb)) => {
+                a == b && (la.is_none() || lb.is_none() || la == lb)
+            }
+            (&Map(ref ak, ref av), &Map(ref bk, ref bv)) => ak == bk && av == bv,
             (&Id(ref a), &Id(ref b)) => a == b,
             (&Func(ref a_params, ref a_retty, .., a), &Func(ref b_params, ref b_retty, .., b)) => {
                 a_params == b_params && a_retty == b_retty && a == b
             }
 
             (&Struct(ref content, _), &Struct(ref content_b, _)) => content == content_b,
+            (&Enum(_, ref id, _), &Enum(_, ref id_b, _)) => id == id_b,
 
             (&Any, _) => true,
             (_, &Any) => true,
@@ -158,10 +168,13 @@ impl Display for TypeNode {
                 }
             }
 
+            Map(ref key, ref value) => write!(f, "[{}: {}]", key, value),
+
             Id(ref n) => write!(f, "deid({})", n.pos.get_lexeme()),
 
             Module(_) => write!(f, "module"),
             Struct(..) => write!(f, "<struct>"),
+            Enum(_, _, ref name) => write!(f, "{}", name),
 
             Func(ref params, ref return_type, ..) => {
                 write!(f, "fun(")?;
@@ -236,6 +249,14 @@ impl Type {
         false
     }
 
+    pub fn is_splat(&self) -> bool {
+        if let TypeMode::Splat(_) = self.mode {
+            return true;
+        }
+
+        false
+    }
+
     pub fn id(id: Rc<Expression>) -> Self {
         Type::new(TypeNode::Id(id), TypeMode::Regular)
     }
@@ -248,6 +269,18 @@ impl Type {
         Type::new(TypeNode::Array(Rc::new(t), len), TypeMode::Regular)
     }
 
+    pub fn map(key: Type, value: Type) -> Type {
+        Type::new(
+            TypeNode::Map(Rc::new(key), Rc::new(value)),
+            TypeMode::Regular,
+        )
+    }
+
+    /// Type of a parameter taking the remaining arguments
+    pub fn splat(t: Type) -> Type {
+        Type::new(t.node, TypeMode::Splat(None))
+    }
+
     pub fn function(params: Vec<Type>, return_type: Type, is_method: bool) -> Self {
         Type::new(
             TypeNode::Func(params, Rc::new(return_type), None, is_method),
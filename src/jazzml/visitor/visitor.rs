@@ -14,12 +14,15 @@ use std::path::Path;
 #[derive(Debug, Clone)]
 pub enum FlagContext {
     Block(Option<Type>),
+    /// Inside a loop body, only returns count towards the block type
+    Loop(Option<Type>),
     Nothing,
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Inside {
-    Loop,
+    /// Loop with its label
+    Loop(Option<String>),
     Calling(Pos),
     Splat(Option<usize>),
     Implement(Type),
@@ -50,6 +53,12 @@ pub struct Visitor<'v> {
     pub inside: Vec<Inside>,
 
     pub method_calls: HashMap<Pos, bool>,
+
+    /// Types of the modules imported at the top level, by import path
+    pub modules: HashMap<String, Type>,
+
+    /// Functions whose bodies are being visited, innermost last
+    defining: Vec<ExpressionNode>,
 }
 
 impl<'v> Visitor<'v> {
@@ -70,6 +79,10 @@ impl<'v> Visitor<'v> {
             inside: Vec::new(),
 
             method_calls: HashMap::new(),
+
+            modules: HashMap::new(),
+
+            defining: Vec::new(),
         }
     }
 
@@ -88,30 +101,46 @@ impl<'v> Visitor<'v> {
                 }
             }
 
-            Break => {
-                if self.inside.contains(&Inside::Loop) {
+            Import(ref path, ref name) => match self.modules.get(path).cloned() {
+                Some(t) => {
+                    self.assign(name.to_owned(), t);
+
                     Ok(())
-                } else {
+                }
+
+                None => Err(response!(
+                    Wrong("imports are only allowed at the top level"),
+                    self.source.file,
+                    statement.pos
+                )),
+            },
+
+            Extern(ref name, ref t) => {
+                // linked once when loading, not every time a block runs
+                if self.symtab.stack.len() > 1 {
                     return Err(response!(
-                        Wrong("can't break outside loop"),
+                        Wrong("externs are only allowed at the top level"),
                         self.source.file,
                         statement.pos
                     ));
                 }
-            }
 
-            Continue => {
-                if self.inside.contains(&Inside::Loop) {
+                if let TypeNode::Func(..) = t.node {
+                    self.assign(name.to_owned(), t.clone());
+
                     Ok(())
                 } else {
-                    return Err(response!(
-                        Wrong("can't continue outside loop"),
+                    Err(response!(
+                        Wrong(format!("extern `{}` must be a function, got `{}`", name, t)),
                         self.source.file,
                         statement.pos
-                    ));
+                    ))
                 }
             }
 
+            Break(ref label) => self.visit_jump("break", label, &statement.pos),
+            Continue(ref label) => self.visit_jump("continue", label, &statement.pos),
+
             Assignment(ref left, ref right) => {
                 self.visit_expression(left)?;
                 self.visit_expression(right)?;
@@ -123,8 +152,6 @@ impl<'v> Visitor<'v> {
 
                 Ok(())
             }
-
-            _ => Ok(()),
         }
     }
 
@@ -141,7 +168,7 @@ impl<'v> Visitor<'v> {
             Struct(ref params, _) => {
                 let mut name_buffer = Vec::new();
 
-                for &(ref name, _) in params.iter() {
+                for &(ref name, ref t, ref default) in params.iter() {
                     if name_buffer.contains(&name) {
                         return Err(response!(
                             Wrong(format!("field `{}` defined more than once", name)),
@@ -150,12 +177,60 @@ impl<'v> Visitor<'v> {
                         ));
                     }
 
+                    if let Some(ref default) = *default {
+                        self.visit_expression(default)?;
+
+                        let field_type = self.deid(t.clone())?;
+                        let default_type = self.type_expression(default)?;
+
+                        if !field_type
+                            .node
+                            .check_expression(&Parser::fold_expression(default)?.node)
+                            && field_type.node != default_type.node
+                        {
+                            return Err(response!(
+                                Wrong(format!(
+                                    "mismatched default for field `{}`, expected `{}` got `{}`",
+                                    name, field_type, default_type
+                                )),
+                                self.source.file,
+                                default.pos
+                            ));
+                        }
+                    }
+
                     name_buffer.push(&name)
                 }
 
                 Ok(())
             }
 
+            Enum(_, ref variants) => {
+                for (i, variant) in variants.iter().enumerate() {
+                    if variants[..i].contains(variant) {
+                        return Err(response!(
+                            Wrong(format!("variant `{}` defined more than once", variant)),
+                            self.source.file,
+                            expression.pos
+                        ));
+                    }
+                }
+
+                Ok(())
+            }
+
+            Range(ref start, ref end) => {
+                if start.is_none() || end.is_none() {
+                    return Err(response!(
+                        Wrong("open ranges are only allowed in slices"),
+                        self.source.file,
+                        expression.pos
+                    ));
+                }
+
+                self.visit_bounds(start, end)
+            }
+
             Neg(ref expr) => {
                 let expr_type = self.type_expression(expr)?;
 
@@ -187,7 +262,7 @@ impl<'v> Visitor<'v> {
             Initialization(ref left, ref args) => {
                 let struct_type = self.type_expression(&*left)?;
 
-                if struct_type.node != TypeNode::Any {
+                if !struct_type.node.strong_cmp(&TypeNode::Any) {
                     if let TypeNode::Struct(ref content, _) = struct_type.node {
                         if struct_type.mode.strong_cmp(&TypeMode::Undeclared) {
                             for arg in args.iter() {
@@ -316,20 +391,25 @@ impl<'v> Visitor<'v> {
                 }
             }
 
-            While(ref condition, ref body) => {
+            While(ref condition, ref body, ref label) => {
                 self.visit_expression(&*condition)?;
 
                 let condition_type = self.type_expression(&*condition)?.node;
 
                 if condition_type == TypeNode::Bool {
-                    self.inside.push(Inside::Loop);
+                    let depth = self.inside.len();
+                    self.inside.push(Inside::Loop(label.clone()));
 
                     self.push_scope();
 
+                    let flag_backup = self.enter_loop_body();
+
                     self.visit_expression(body)?;
 
                     let body_type = self.type_expression(body)?;
 
+                    self.flag = flag_backup;
+
                     if body_type.node != TypeNode::Nil {
                         let body_pos = if let Block(ref content) = body.node {
                             content.last().unwrap().pos.clone()
@@ -346,7 +426,7 @@ impl<'v> Visitor<'v> {
 
                     self.pop_scope();
 
-                    self.inside.pop();
+                    self.inside.truncate(depth);
 
                     Ok(())
                 } else {
@@ -361,11 +441,170 @@ impl<'v> Visitor<'v> {
                 }
             }
 
+            For(ref names, ref iterable, ref body, ref label) => {
+                self.visit_expression(iterable)?;
+
+                let mut iterable_type = self.type_expression(iterable)?;
+
+                if let TypeMode::Splat(_) = iterable_type.mode {
+                    iterable_type = Type::array(iterable_type.clone(), None)
+                }
+
+                let any = Type::from(TypeNode::Any);
+                let int = Type::from(TypeNode::Int);
+
+                let (key_type, value_type) = match iterable_type.node {
+                    TypeNode::Array(ref t, _) => (int, (**t).clone()),
+                    TypeNode::Str => (int, Type::from(TypeNode::Str)),
+                    TypeNode::Map(ref k, ref v) => ((**k).clone(), (**v).clone()),
+                    TypeNode::Struct(..) => (Type::from(TypeNode::Str), any),
+                    TypeNode::Any => (any.clone(), any),
+
+                    _ => {
+                        return Err(response!(
+                            Wrong(format!("can't iterate over `{}`", iterable_type)),
+                            self.source.file,
+                            iterable.pos
+                        ));
+                    }
+                };
+
+                let depth = self.inside.len();
+                self.inside.push(Inside::Loop(label.clone()));
+
+                self.push_scope();
+
+                if let [ref name] = names[..] {
+                    // elements of arrays and strings, keys of maps
+                    let t = match iterable_type.node {
+                        TypeNode::Map(..) | TypeNode::Struct(..) => key_type,
+                        _ => value_type,
+                    };
+
+                    self.assign(name.to_owned(), t);
+                } else {
+                    self.assign(names[0].to_owned(), key_type);
+                    self.assign(names[1].to_owned(), value_type);
+                }
+
+                let flag_backup = self.enter_loop_body();
+
+                self.visit_expression(body)?;
+
+                let body_type = self.type_expression(body)?;
+
+                self.flag = flag_backup;
+
+                if body_type.node != TypeNode::Nil {
+                    let body_pos = if let Block(ref content) = body.node {
+                        content.last().unwrap().pos.clone()
+                    } else {
+                        unreachable!()
+                    };
+
+                    return Err(response!(
+                        Wrong("mismatched types, expected `()`"),
+                        self.source.file,
+                        body_pos
+                    ));
+                }
+
+                self.pop_scope();
+
+                self.inside.truncate(depth);
+
+                Ok(())
+            }
+
+            Switch(ref subject, ref arms) => {
+                self.visit_expression(&*subject)?;
+
+                let subject_type = self.type_expression(&*subject)?;
+
+                let mut arm_type: Option<Type> = None;
+                // variant arms are all of one enum, the first one's
+                let mut enum_type: Option<Type> = None;
+
+                for &(ref pattern, ref body) in arms.iter() {
+                    self.push_scope();
+
+                    if let PatternNode::Variant(ref path) = pattern.node {
+                        match enum_type {
+                            Some(ref t) => self.visit_pattern(pattern, t)?,
+                            None => {
+                                self.visit_pattern(pattern, &subject_type)?;
+                                enum_type = Some(self.type_expression(path)?);
+                            }
+                        }
+                    } else {
+                        self.visit_pattern(pattern, &subject_type)?;
+                    }
+                    self.visit_expression(body)?;
+
+                    let body_type = self.type_expression(body)?;
+
+                    self.pop_scope();
+
+                    if let Some(ref arm_type) = arm_type {
+                        if *arm_type != body_type {
+                            return Err(response!(
+                                Wrong(format!(
+                                    "mismatched types, expected `{}` got `{}`",
+                                    arm_type, body_type
+                                )),
+                                self.source.file,
+                                body.pos
+                            ));
+                        }
+                    } else {
+                        arm_type = Some(body_type)
+                    }
+                }
+
+                if let TypeNode::Enum(ref variants, ..) = subject_type.node {
+                    if !arms.iter().any(|arm| arm.0.is_catch_all()) {
+                        let mut missing = Vec::new();
+
+                        for variant in variants.iter() {
+                            let covered = arms.iter().any(|arm| {
+                                if let PatternNode::Variant(ref path) = arm.0.node {
+                                    if let Index(_, ref name, _) = path.node {
+                                        return name.node == Identifier(variant.clone());
+                                    }
+                                }
+
+                                false
+                            });
+
+                            if !covered {
+                                missing.push(format!("`{}`", variant))
+                            }
+                        }
+
+                        if missing.len() > 0 {
+                            return Err(response!(
+                                Wrong(format!(
+                                    "non-exhaustive switch, missing variant{} {}",
+                                    if missing.len() > 1 { "s" } else { "" },
+                                    missing.join(", ")
+                                )),
+                                self.source.file,
+                                expression.pos
+                            ));
+                        }
+                    }
+                }
+
+                Ok(())
+            }
+
             Array(ref content) => {
                 if content.len() != 0 {
                     let t = self.type_expression(content.first().unwrap())?;
 
                     for element in content {
+                        self.visit_expression(element)?;
+
                         let element_type = self.type_expression(element)?;
 
                         if !t
@@ -387,8 +626,45 @@ impl<'v> Visitor<'v> {
                 Ok(())
             }
 
+            Map(ref entries) => {
+                if let Some(&(ref key, ref value)) = entries.first() {
+                    let key_type = self.type_expression(key)?;
+                    let value_type = self.type_expression(value)?;
+
+                    for &(ref key, ref value) in entries {
+                        self.visit_expression(key)?;
+                        self.visit_expression(value)?;
+
+                        for &(ref t, element, what) in
+                            [(&key_type, key, "keys"), (&value_type, value, "values")].iter()
+                        {
+                            let element_type = self.type_expression(element)?;
+
+                            if !t
+                                .node
+                                .check_expression(&Parser::fold_expression(element)?.node)
+                                && t.node != element_type.node
+                            {
+                                return Err(response!(
+                                    Wrong(format!(
+                                        "mismatched types in map {}, expected `{}` got `{}`",
+                                        what, t, element_type
+                                    )),
+                                    self.source.file,
+                                    element.pos
+                                ));
+                            }
+                        }
+                    }
+                }
+                Ok(())
+            }
+
             Call(ref expr, ref args) => {
                 self.visit_expression(expr)?;
+                for arg in args.iter() {
+                    self.visit_expression(arg)?;
+                }
 
                 self.inside.push(Inside::Calling(expr.pos.clone()));
 
@@ -396,8 +672,9 @@ impl<'v> Visitor<'v> {
 
                 if let TypeNode::Func(ref params, _, ref func, .., is_method) = expression_type.node
                 {
-                    // this is where we visit the func, no diggity
-                    if let Some(func) = func {
+                    // this is where we visit the func, no diggity. Calls from
+                    // its own body are typed by the signature alone
+                    if let Some(func) = func.as_ref().filter(|f| !self.defining.contains(f)) {
                         self.visit_expression(&Expression::new(
                             (**func).clone(),
                             expression.pos.clone(),
@@ -411,7 +688,8 @@ impl<'v> Visitor<'v> {
                     let mut actual_arg_len = args.len();
                     let mut type_buffer: Option<Type> = None;
 
-                    for (i, param_type) in params.iter().enumerate() {
+                    // missing arguments are reported below
+                    for (i, param_type) in params.iter().enumerate().take(args.len()) {
                         let param_type = self.deid(param_type.clone())?;
                         let arg_type = self.type_expression(&args[i])?;
 
@@ -507,7 +785,7 @@ impl<'v> Visitor<'v> {
 
                     let ident_type = self.type_expression(&ident)?;
 
-                    if let TypeNode::Struct(..) = ident_type.node {
+                    if let TypeNode::Struct(..) | TypeNode::Enum(..) = ident_type.node {
                         return_type = Type::from(ident_type.node)
                     } else {
                         return Err(response!(
@@ -560,17 +838,28 @@ impl<'v> Visitor<'v> {
                 self.symtab
                     .put_frame(Frame::from(frame_hash, self.symtab.stack.len()));
 
+                let depth = self.inside.len();
                 self.inside.push(Inside::Function);
 
-                self.visit_expression(body)?;
+                // returns of the body belong to this function, not the enclosing block
+                let flag_backup = self.flag.take();
 
-                self.symtab.revert_frame(); // we'll need those
+                // typing the body visits it again, so it's still being defined
+                self.defining.push(expression.node.clone());
+                let body_type = self.visit_expression(body).and_then(|_| {
+                    self.symtab.revert_frame(); // we'll need those
 
-                let body_type = self.type_expression(body)?;
+                    self.type_expression(body)
+                });
+                self.defining.pop();
+                let body_type = body_type?;
+
+                self.flag = flag_backup;
 
                 self.pop_scope(); // we don't need those anymore
 
-                self.inside.pop();
+                // indexing in the body leaves entries behind
+                self.inside.truncate(depth);
 
                 self.pop_scope();
 
@@ -588,7 +877,7 @@ impl<'v> Visitor<'v> {
                 }
             }
 
-            Index(ref left, ref index, _) => {
+            Index(ref left, ref index, is_arr) => {
                 let mut left_type = self.type_expression(left)?;
 
                 if let TypeMode::Splat(_) = left_type.mode {
@@ -596,30 +885,24 @@ impl<'v> Visitor<'v> {
                 }
 
                 match left_type.node {
-                    TypeNode::Array(_, ref len) => {
+                    TypeNode::Array(..) | TypeNode::Str if is_slice(index) => {
+                        self.inside.push(Inside::Nothing);
+
+                        if let Range(ref start, ref end) = index.node {
+                            self.visit_bounds(start, end)?;
+                        }
+                    }
+
+                    TypeNode::Array(..) => {
                         self.inside.push(Inside::Nothing);
 
                         self.visit_expression(index)?;
 
                         let index_type = self.type_expression(index)?;
 
+                        // arrays grow and shrink, indexing is bounds checked when it runs
                         match index_type.node {
-                            TypeNode::Int => {
-                                if let Int(ref a) = Parser::fold_expression(index)?.node {
-                                    if let Some(len) = len {
-                                        if *a as usize > *len {
-                                            return Err(response!(
-                                                Wrong(format!(
-                                                    "index out of bounds, len is {} got {}",
-                                                    len, a
-                                                )),
-                                                self.source.file,
-                                                left.pos
-                                            ));
-                                        }
-                                    }
-                                }
-                            }
+                            TypeNode::Any | TypeNode::Int => (),
 
                             _ => {
                                 return Err(response!(
@@ -634,6 +917,54 @@ impl<'v> Visitor<'v> {
                         }
                     }
 
+                    TypeNode::Map(ref key, _) => {
+                        self.inside.push(Inside::Nothing);
+
+                        if !is_arr {
+                            return Err(response!(
+                                Wrong(format!(
+                                    "can't access field of map `{}`, index it with `[]`",
+                                    left_type
+                                )),
+                                self.source.file,
+                                index.pos
+                            ));
+                        }
+
+                        self.visit_expression(index)?;
+
+                        let index_type = self.type_expression(index)?;
+
+                        if !key.node.check_expression(&Parser::fold_expression(index)?.node)
+                            && key.node != index_type.node
+                        {
+                            return Err(response!(
+                                Wrong(format!(
+                                    "mismatched map key, expected `{}` got `{}`",
+                                    key, index_type
+                                )),
+                                self.source.file,
+                                index.pos
+                            ));
+                        }
+                    }
+
+                    TypeNode::Str => {
+                        self.inside.push(Inside::Nothing);
+
+                        self.visit_expression(index)?;
+
+                        let index_type = self.type_expression(index)?;
+
+                        if index_type.node != TypeNode::Int {
+                            return Err(response!(
+                                Wrong(format!("can't index string with `{}`", index_type)),
+                                self.source.file,
+                                index.pos
+                            ));
+                        }
+                    }
+
                     TypeNode::Module(ref content) => {
                         self.inside.push(Inside::Nothing);
 
@@ -678,6 +1009,36 @@ impl<'v> Visitor<'v> {
                         }
                     }
 
+                    TypeNode::Enum(ref variants, ..) => {
+                        self.inside.push(Inside::Nothing);
+
+                        if let Identifier(ref name) = index.node {
+                            if !left_type.mode.strong_cmp(&TypeMode::Undeclared) {
+                                return Err(response!(
+                                    Wrong(format!("can't access variant `{}` on enum value", name)),
+                                    self.source.file,
+                                    index.pos
+                                ));
+                            }
+
+                            if !variants.contains(name) {
+                                return Err(response!(
+                                    Wrong(format!("no such enum variant `{}`", name)),
+                                    self.source.file,
+                                    index.pos
+                                ));
+                            }
+                        } else {
+                            let index_type = self.type_expression(index)?;
+
+                            return Err(response!(
+                                Wrong(format!("can't index enum with `{}`", index_type)),
+                                self.source.file,
+                                index.pos
+                            ));
+                        }
+                    }
+
                     TypeNode::Any => (),
 
                     _ => {
@@ -696,6 +1057,29 @@ impl<'v> Visitor<'v> {
         }
     }
 
+    // bounds of a range, either may be left out
+    fn visit_bounds(
+        &mut self,
+        start: &Option<Rc<Expression>>,
+        end: &Option<Rc<Expression>>,
+    ) -> Result<(), ()> {
+        for bound in start.iter().chain(end.iter()) {
+            self.visit_expression(bound)?;
+
+            let bound_type = self.type_expression(bound)?;
+
+            if bound_type.node != TypeNode::Int {
+                return Err(response!(
+                    Wrong(format!("range bound must be `int`, found `{}`", bound_type)),
+                    self.source.file,
+                    bound.pos
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
     fn visit_variable(&mut self, variable: &StatementNode, pos: &Pos) -> Result<(), ()> {
         use self::ExpressionNode::*;
 
@@ -707,7 +1091,7 @@ impl<'v> Visitor<'v> {
             if let TypeNode::Id(ref ident) = var_type.node {
                 let ident_type = self.type_expression(&ident)?;
 
-                if let TypeNode::Struct(..) = ident_type.node {
+                if let TypeNode::Struct(..) | TypeNode::Enum(..) = ident_type.node {
                     variable_type = Type::from(ident_type.node)
                 } else {
                     return Err(response!(
@@ -727,8 +1111,16 @@ impl<'v> Visitor<'v> {
                 }
 
                 let right_type = self.type_expression(&right)?;
-
-                if variable_type.node != TypeNode::Nil {
+                if let (TypeNode::Any, Struct(..)) | (TypeNode::Any, Enum(..)) =
+                    (&variable_type.node, &right.node)
+                {
+                    // binding a struct or enum declaration, the name becomes the type
+                    let node = match right_type.node {
+                        TypeNode::Enum(variants, id, _) => TypeNode::Enum(variants, id, name.clone()),
+                        node => node,
+                    };
+                    self.assign(name.to_owned(), Type::new(node, right_type.mode))
+                } else if variable_type.node != TypeNode::Nil {
                     if !variable_type
                         .node
                         .check_expression(&Parser::fold_expression(right)?.node)
@@ -736,7 +1128,7 @@ impl<'v> Visitor<'v> {
                     {
                         return Err(response!(
                             Wrong(format!(
-                                "mismatched types, expe cted type `{}` got `{}`",
+                                "mismatched types, expected type `{}` got `{}`",
                                 variable_type.node, right_type.node
                             )),
                             self.source.file,
@@ -807,6 +1199,11 @@ impl<'v> Visitor<'v> {
                 )
             }
 
+            Enum(ref id, ref variants) => Type::new(
+                TypeNode::Enum(variants.clone(), id.to_string(), "enum".to_owned()),
+                TypeMode::Undeclared,
+            ),
+
             Str(_) => Type::from(TypeNode::Str),
             Char(_) => Type::from(TypeNode::Char),
             Bool(_) => Type::from(TypeNode::Bool),
@@ -819,10 +1216,18 @@ impl<'v> Visitor<'v> {
                 Some(content.len()),
             ),
 
+            Map(ref entries) => {
+                if let Some(&(ref key, ref value)) = entries.first() {
+                    Type::map(self.type_expression(key)?, self.type_expression(value)?)
+                } else {
+                    Type::map(Type::from(TypeNode::Any), Type::from(TypeNode::Any))
+                }
+            }
+
             Initialization(ref name, ref content) => {
                 let struct_type = Type::from(self.type_expression(name)?.node);
 
-                if struct_type.node == TypeNode::Any {
+                if struct_type.node.strong_cmp(&TypeNode::Any) {
                     let mut new_content = HashMap::new();
 
                     for (name, ty) in content {
@@ -837,6 +1242,23 @@ impl<'v> Visitor<'v> {
 
             If(_, ref body, ..) => self.type_expression(body)?,
 
+            Switch(ref subject, ref arms) => {
+                if let Some(&(ref pattern, ref body)) = arms.first() {
+                    let subject_type = self.type_expression(&*subject)?;
+
+                    self.push_scope();
+
+                    self.visit_pattern(pattern, &subject_type)?;
+                    let body_type = self.type_expression(body)?;
+
+                    self.pop_scope();
+
+                    body_type
+                } else {
+                    Type::from(TypeNode::Nil)
+                }
+            }
+
             Index(ref array, ref index, _) => {
                 let mut kind = self.type_expression(array)?;
 
@@ -845,7 +1267,12 @@ impl<'v> Visitor<'v> {
                 }
 
                 match kind.node {
+                    TypeNode::Array(ref t, _) if is_slice(index) => {
+                        Type::array((**t).clone(), None)
+                    }
                     TypeNode::Array(ref t, _) => (**t).clone(),
+                    TypeNode::Map(_, ref t) => (**t).clone(),
+                    TypeNode::Str => Type::from(TypeNode::Str),
                     TypeNode::Any => Type::new(TypeNode::Any, kind.mode),
 
                     TypeNode::Module(ref content) => {
@@ -864,6 +1291,22 @@ impl<'v> Visitor<'v> {
                         }
                     }
 
+                    TypeNode::Enum(ref variants, ..) => {
+                        if let Identifier(ref name) = index.node {
+                            if variants.contains(name) {
+                                Type::from(kind.node.clone())
+                            } else {
+                                return Err(response!(
+                                    Wrong(format!("no such enum variant `{}`", name)),
+                                    self.source.file,
+                                    index.pos
+                                ));
+                            }
+                        } else {
+                            unreachable!()
+                        }
+                    }
+
                     TypeNode::Struct(ref content, ref struct_id) => {
                         if let Identifier(ref name) = index.node {
                             if !self.is_implemented(struct_id, name) {
@@ -919,6 +1362,8 @@ impl<'v> Visitor<'v> {
                     self.type_expression(expression)?.node
                 {
                     (**return_type).clone()
+                } else if self.type_expression(expression)?.node.strong_cmp(&TypeNode::Any) {
+                    Type::from(TypeNode::Any)
                 } else {
                     panic!(
                         "BAM! (please submit an issue): called {:#?}",
@@ -957,7 +1402,7 @@ impl<'v> Visitor<'v> {
                     for element in statements {
                         match element.node {
                             StatementNode::Expression(ref expression) => match expression.node {
-                                Block(_) | If(..) | While(..) => {
+                                Block(_) | If(..) | While(..) | For(..) => {
                                     self.type_expression(expression)?;
                                 }
 
@@ -968,7 +1413,9 @@ impl<'v> Visitor<'v> {
                                 let flag = self.flag.clone();
 
                                 if let Some(ref flag) = flag {
-                                    if let &FlagContext::Block(ref consistent) = flag {
+                                    if let FlagContext::Block(ref consistent)
+                                    | FlagContext::Loop(ref consistent) = *flag
+                                    {
                                         let return_type =
                                             if let Some(ref return_type) = *return_type {
                                                 self.type_expression(&return_type)?
@@ -986,6 +1433,9 @@ impl<'v> Visitor<'v> {
                           )
                         );
                                             }
+                                        } else if let FlagContext::Loop(_) = *flag {
+                                            self.flag =
+                                                Some(FlagContext::Loop(Some(return_type.clone())))
                                         } else {
                                             self.flag =
                                                 Some(FlagContext::Block(Some(return_type.clone())))
@@ -1184,6 +1634,8 @@ impl<'v> Visitor<'v> {
                 }
             }
 
+            Range(..) => Type::array(Type::from(TypeNode::Int), None),
+
             Neg(ref expr) => self.type_expression(expr)?,
             Not(_) => Type::from(TypeNode::Bool),
 
@@ -1193,6 +1645,122 @@ impl<'v> Visitor<'v> {
         self.deid(t)
     }
 
+    // checks `pattern` against the type of the switched value, binding its names
+    fn visit_pattern(&mut self, pattern: &Pattern, t: &Type) -> Result<(), ()> {
+        match pattern.node {
+            PatternNode::Wildcard => Ok(()),
+
+            PatternNode::Binding(ref name) => {
+                self.assign(name.to_owned(), Type::from(t.node.clone()));
+
+                Ok(())
+            }
+
+            PatternNode::Literal(ref literal) | PatternNode::Variant(ref literal) => {
+                self.visit_expression(literal)?;
+
+                let literal_type = self.type_expression(literal)?;
+
+                if !t
+                    .node
+                    .check_expression(&Parser::fold_expression(literal)?.node)
+                    && literal_type.node != t.node
+                {
+                    return Err(response!(
+                        Wrong(format!(
+                            "mismatched pattern, expected `{}` got `{}`",
+                            t, literal_type
+                        )),
+                        self.source.file,
+                        pattern.pos
+                    ));
+                }
+
+                Ok(())
+            }
+
+            PatternNode::Array(ref content) => {
+                let element_type = match t.node {
+                    TypeNode::Array(ref element_type, ref len) => {
+                        if let Some(len) = *len {
+                            if len != content.len() {
+                                return Err(response!(
+                                    Wrong(format!(
+                                        "mismatched pattern, expected {} element{} got {}",
+                                        len,
+                                        if len != 1 { "s" } else { "" },
+                                        content.len()
+                                    )),
+                                    self.source.file,
+                                    pattern.pos
+                                ));
+                            }
+                        }
+
+                        (**element_type).clone()
+                    }
+
+                    TypeNode::Any => Type::from(TypeNode::Any),
+
+                    _ => {
+                        return Err(response!(
+                            Wrong(format!("mismatched pattern, expected `{}` got array", t)),
+                            self.source.file,
+                            pattern.pos
+                        ));
+                    }
+                };
+
+                for element in content.iter() {
+                    self.visit_pattern(element, &element_type)?
+                }
+
+                Ok(())
+            }
+
+            PatternNode::Struct(ref name, ref fields) => {
+                self.visit_expression(name)?;
+
+                let struct_type = self.type_expression(name)?;
+
+                if let TypeNode::Struct(ref content, _) = struct_type.node {
+                    if struct_type.node != t.node {
+                        return Err(response!(
+                            Wrong(format!(
+                                "mismatched pattern, expected `{}` got `{}`",
+                                t, struct_type
+                            )),
+                            self.source.file,
+                            pattern.pos
+                        ));
+                    }
+
+                    for &(ref field, ref field_pattern) in fields.iter() {
+                        if let Some(field_type) = content.get(field) {
+                            let field_type = field_type.clone();
+
+                            self.visit_pattern(field_pattern, &field_type)?
+                        } else {
+                            return Err(response!(
+                                Wrong(format!("no such struct member `{}`", field)),
+                                self.source.file,
+                                field_pattern.pos
+                            ));
+                        }
+                    }
+
+                    Ok(())
+                } else {
+                    Err(response!(
+                        Wrong(format!("can't destructure non-struct: `{}`", struct_type)),
+                        self.source.file,
+                        name.pos
+                    ))
+                }
+            }
+        }
+    }
+
     // `ensure_implicit` gets mad at wannabe implicit returns
     fn visit_block(&mut self, content: &Vec<Statement>, ensure_implicits: bool) -> Result<(), ()> {
         for (i, statement) in content.iter().enumerate() {
@@ -1242,6 +1810,50 @@ impl<'v> Visitor<'v> {
         Ok(())
     }
 
+    // a loop body has no value, its blocks are checked for returns only
+    fn enter_loop_body(&mut self) -> Option<FlagContext> {
+        let flag_backup = self.flag.clone();
+
+        if let Some(FlagContext::Block(ref consistent)) = flag_backup {
+            self.flag = Some(FlagContext::Loop(consistent.clone()))
+        }
+
+        flag_backup
+    }
+
+    // `break` or `continue` must be inside a loop of the same function, with
+    // that label if given
+    fn visit_jump(&self, jump: &str, label: &Option<String>, pos: &Pos) -> Result<(), ()> {
+        let found = self
+            .inside
+            .iter()
+            .rev()
+            .take_while(|inside| **inside != Inside::Function)
+            .any(|inside| match (inside, label) {
+                (Inside::Loop(_), None) => true,
+                (Inside::Loop(Some(ref name)), Some(ref label)) => name == label,
+                _ => false,
+            });
+
+        if found {
+            return Ok(());
+        }
+
+        if let Some(ref label) = *label {
+            Err(response!(
+                Wrong(format!("can't {}, no enclosing loop labeled `{}`", jump, label)),
+                self.source.file,
+                pos
+            ))
+        } else {
+            Err(response!(
+                Wrong(format!("can't {} outside loop", jump)),
+                self.source.file,
+                pos
+            ))
+        }
+    }
+
     fn ensure_no_implicit(&self, expression: &Expression) -> Result<(), ()> {
         use self::ExpressionNode::*;
 
@@ -1255,10 +1867,16 @@ impl<'v> Visitor<'v> {
                                 self.ensure_no_implicit(expression)?;
                             }
 
-                            If(_, ref expr, _) | While(_, ref expr) => {
+                            If(_, ref expr, _) | While(_, ref expr, _) | For(_, _, ref expr, _) => {
                                 self.ensure_no_implicit(&*expr)?
                             }
 
+                            Switch(_, ref arms) => {
+                                for &(_, ref body) in arms.iter() {
+                                    self.ensure_no_implicit(body)?
+                                }
+                            }
+
                             EOF => (),
 
                             _ => {
@@ -1279,7 +1897,15 @@ impl<'v> Visitor<'v> {
 
             Call(..) => (),
 
-            If(_, ref expr, _) | While(_, ref expr) => self.ensure_no_implicit(&*expr)?,
+            If(_, ref expr, _) | While(_, ref expr, _) | For(_, _, ref expr, _) => {
+                self.ensure_no_implicit(&*expr)?
+            }
+
+            Switch(_, ref arms) => {
+                for &(_, ref body) in arms.iter() {
+                    self.ensure_no_implicit(body)?
+                }
+            }
 
             EOF => (),
 
@@ -1367,3 +1993,11 @@ impl<'v> Visitor<'v> {
         false
     }
 }
+
+// `a[start..end]`
+fn is_slice(index: &Expression) -> bool {
+    match index.node {
+        ExpressionNode::Range(..) => true,
+        _ => false,
+    }
+}
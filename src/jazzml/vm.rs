@@ -1,49 +1,87 @@
+use super::error::RuntimeError;
 use super::frame::Frame;
+use super::gc::{Gc, Pins};
 use super::opcodes::Opcode;
-use super::value::{ArrayRef, FuncKind, FuncRef, Function, Object, ObjectRef, Value};
+use super::stdlib;
+use super::value::{
+    ArrayRef, Closure, ClosureRef, Enum, EnumRef, FuncKind, FuncRef, Function, LineTable, NativeFn,
+    Object, ObjectRef, Struct, StructRef, Value, VAR_ARGS,
+};
 
 use fnv::FnvHashMap;
 use std::cell::RefCell;
+use std::rc::Rc;
 
 pub struct VirtualMachine {
+    /// Call stack, the running frame on top
+    pub frames: Vec<Frame>,
     pub functions: FnvHashMap<usize, FuncRef>,
     pub globals: FnvHashMap<Value, Value>,
     pub pool: FnvHashMap<usize, ObjectRef>,
     pub arrays: FnvHashMap<usize, ArrayRef>,
+    pub structs: FnvHashMap<usize, StructRef>,
+    pub enums: FnvHashMap<usize, EnumRef>,
+    pub closures: FnvHashMap<usize, ClosureRef>,
+    /// Line table of the top level code, set by `Compiler::finish`
+    pub lines: LineTable,
+    pub gc: Gc,
+    /// Values the host holds handles to, see `pin`
+    pub(crate) pins: Pins,
     fid: usize,
     oid: usize,
     aid: usize,
+    sid: usize,
+    eid: usize,
+    cid: usize,
 }
 
 impl VirtualMachine {
     pub fn new() -> VirtualMachine {
         VirtualMachine {
+            frames: vec![],
             functions: FnvHashMap::default(),
             globals: FnvHashMap::default(),
             pool: FnvHashMap::default(),
             arrays: FnvHashMap::default(),
+            structs: FnvHashMap::default(),
+            enums: FnvHashMap::default(),
+            closures: FnvHashMap::default(),
+            lines: LineTable::default(),
+            gc: Gc::new(),
+            pins: Pins::default(),
             fid: 0,
             oid: 0,
             aid: 0,
+            sid: 0,
+            eid: 0,
+            cid: 0,
         }
     }
 
+    /// Registers the natives and the string module as globals, once per VM.
+    /// `Engine::new` does, a bare VM needs it before compiling.
     pub fn init_builtins(&mut self) {
         use super::builtins::*;
-        self.register_native_func(format!("array_len"), &arr_len, 1);
-        self.register_native_func(format!("chars"), &chars, 1);
-        self.register_native_func(format!("array_pop"), &arr_pop, 1);
-        self.register_native_func(format!("array_push"), &arr_push, 2);
-        self.register_native_func(format!("concat"), &concat, -1);
-        self.register_native_func(format!("print"), &print, -1);
-        self.register_native_func(format!("new_obj"), &new_obj, -1);
-        self.register_native_func(format!("getc"), &get_char, -1);
-        self.register_native_func(format!("putc"), &put_char, -1);
-        self.register_native_func(format!("println"), &println, -1);
+        self.register_native_func(format!("array_len"), arr_len, 1);
+        self.register_native_func(format!("chars"), chars, 1);
+        self.register_native_func(format!("len"), arr_len, 1);
+        self.register_native_func(format!("keys"), map_keys, 1);
+        self.register_native_func(format!("values"), map_values, 1);
+        self.register_native_func(format!("has"), map_has, 2);
+        self.register_native_func(format!("remove"), map_remove, 2);
+        self.register_native_func(format!("array_pop"), arr_pop, 1);
+        self.register_native_func(format!("array_push"), arr_push, 2);
+        self.register_native_func(format!("concat"), concat, -1);
+        self.register_native_func(format!("print"), print, -1);
+        self.register_native_func(format!("new_obj"), new_obj, -1);
+        self.register_native_func(format!("getc"), get_char, -1);
+        self.register_native_func(format!("putc"), put_char, -1);
+        self.register_native_func(format!("println"), println, -1);
+        self.register_module(&stdlib::string::module());
         macro_rules! register_native {
             ($($fname: ident: $argc: expr),+) => {
                 $(
-                self.register_native_func(format!("__{}__",stringify!($fname)), &$fname, $argc);
+                self.register_native_func(format!("__{}__",stringify!($fname)), $fname, $argc);
                 )+
             };
         }
@@ -67,35 +105,60 @@ impl VirtualMachine {
         }
     }
 
-    pub fn run_instructions(&mut self, ins: Vec<Opcode>) -> Value {
-        let mut locals = FnvHashMap::default();
-        let mut frame = Frame::new_with_ins(self, &mut locals, ins);
+    pub fn run_instructions(&mut self, ins: Vec<Opcode>) -> Result<Value, RuntimeError> {
+        let nlocals = Frame::slots_used(&ins);
+        self.frames.push(Frame::new(ins, None, nlocals));
+        self.run_frames()
+    }
+
+    pub fn run_func(&mut self, func: usize, args: Vec<Value>) -> Result<Value, RuntimeError> {
+        if !self.functions.contains_key(&func) {
+            return Err(RuntimeError::new("function not defined"));
+        }
 
-        frame.run_frame()
+        self.call_value(&Value::FuncRef(func), args)
     }
 
-    pub fn run_func(&mut self, func: usize, args: Vec<Value>) -> Value {
-        let func: &FuncRef = &self
-            .functions
-            .get(&func)
-            .expect("function not defined")
-            .clone();
-        let func: &Function = &func.borrow();
+    /// Calls a function or closure with `args`, running it to completion.
+    pub fn call_value(&mut self, callee: &Value, args: Vec<Value>) -> Result<Value, RuntimeError> {
+        let (id, upvalues) = match callee {
+            Value::FuncRef(id) => (*id, vec![]),
+            Value::Closure(id) => {
+                let closure = self.get_closure(id)?.borrow().clone();
+                (closure.func, closure.upvalues)
+            }
+            _ => {
+                return Err(RuntimeError::new(format!(
+                    "Can't call value `{}`",
+                    callee.as_str(self)
+                )));
+            }
+        };
+        let function: Function = self.get_func(&id).borrow().clone();
+
+        if function.nargs != VAR_ARGS && function.nargs != args.len() as i32 {
+            return Err(RuntimeError::new(format!(
+                "`{}` expected {} argument(s) found {}",
+                function.name,
+                function.nargs,
+                args.len()
+            )));
+        }
 
-        match &func.kind {
+        match function.kind {
             FuncKind::Interpret(ins) => {
-                let mut args = {
-                    let mut temp = FnvHashMap::default();
+                let mut frame = Frame::new(ins, Some(id), function.locals);
+                frame.upvalues = upvalues;
 
-                    for (arg, arg_name) in args.iter().zip(&func.args) {
-                        temp.insert(arg_name.to_owned(), arg.clone());
+                if function.nargs == VAR_ARGS {
+                    frame.stack = args;
+                } else {
+                    for (slot, arg) in args.into_iter().enumerate() {
+                        frame.locals[slot] = arg;
                     }
-                    temp
-                };
-                let mut frame = Frame::new(self, &mut args);
-
-                frame.code = ins.clone();
-                frame.run_frame()
+                }
+                self.frames.push(frame);
+                self.run_frames()
             }
             FuncKind::Native(f) => f(self, args),
         }
@@ -111,9 +174,13 @@ impl VirtualMachine {
 
     pub fn new_func(&mut self, nargs: i32, args: Vec<String>) -> usize {
         let func = Function {
+            name: "<lambda>".to_owned(),
             kind: FuncKind::Interpret(vec![]),
             nargs,
             args,
+            captures: vec![],
+            lines: LineTable::default(),
+            locals: 0,
         };
         let func_id = self.fid;
         self.functions
@@ -130,9 +197,13 @@ impl VirtualMachine {
         args: Vec<String>,
     ) -> usize {
         let func = Function {
+            name: name.clone(),
+            locals: Frame::slots_used(&ins),
             kind: FuncKind::Interpret(ins),
             nargs,
             args,
+            captures: vec![],
+            lines: LineTable::default(),
         };
         self.globals
             .insert(Value::Str(name), Value::FuncRef(self.fid));
@@ -143,39 +214,125 @@ impl VirtualMachine {
         id
     }
 
-    pub fn register_native_func<T: Fn(&mut VirtualMachine, Vec<Value>) -> Value>(
-        &mut self,
-        name: String,
-        f: &'static T,
-        nargs: i32,
-    ) -> usize {
+    pub fn register_native_func<T>(&mut self, name: String, f: T, nargs: i32) -> usize
+    where
+        T: Fn(&mut VirtualMachine, Vec<Value>) -> Result<Value, RuntimeError> + 'static,
+    {
+        let id = self.new_native_func(name.clone(), Rc::new(f), nargs);
+        self.globals.insert(Value::Str(name), Value::FuncRef(id));
+        id
+    }
+
+    /// Checks that the global `name` is a native function taking `nargs`
+    /// arguments, as an `extern` declaration expects
+    pub fn link_native(&self, name: &str, nargs: i32) -> Result<(), String> {
+        let id = match self.globals.get(&Value::Str(name.to_owned())) {
+            Some(Value::FuncRef(id)) => *id,
+            _ => return Err(format!("extern `{}` isn't provided by the host", name)),
+        };
+
+        let function = self.get_func(&id).borrow();
+        match function.kind {
+            FuncKind::Native(_) => (),
+            FuncKind::Interpret(_) => {
+                return Err(format!("extern `{}` is bound to a script function", name));
+            }
+        }
+
+        if function.nargs != VAR_ARGS && nargs != VAR_ARGS && function.nargs != nargs {
+            return Err(format!(
+                "extern `{}` takes {} argument(s), the host's native takes {}",
+                name, nargs, function.nargs
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Creates a native function without binding it to a global
+    pub fn new_native_func(&mut self, name: String, f: NativeFn, nargs: i32) -> usize {
         let func = Function {
+            name,
             kind: FuncKind::Native(f),
             nargs,
             args: vec![],
+            captures: vec![],
+            lines: LineTable::default(),
+            locals: 0,
         };
         let id = self.fid;
-        self.globals
-            .insert(Value::Str(name), Value::FuncRef(self.fid));
         self.functions
             .insert(self.fid, FuncRef::new(RefCell::new(func)));
         self.fid += 1;
         id
     }
 
-    pub fn get_object(&self, s: &usize) -> &ObjectRef {
-        self.pool.get(s).unwrap()
+    pub fn get_object(&self, s: &usize) -> Result<&ObjectRef, RuntimeError> {
+        self.pool.get(s).ok_or_else(|| dangling("object", *s))
     }
 
+    /// Functions are never freed, and loaded code only refers to functions it
+    /// loaded, so every id in code is valid
     pub fn get_func(&self, s: &usize) -> &FuncRef {
         self.functions.get(s).unwrap()
     }
 
+    pub fn get_struct(&self, s: &usize) -> Result<&StructRef, RuntimeError> {
+        self.structs.get(s).ok_or_else(|| dangling("struct", *s))
+    }
+
+    pub fn register_struct(&mut self, s: Struct) -> usize {
+        let id = self.sid;
+
+        self.structs.insert(id, StructRef::new(RefCell::new(s)));
+        self.sid += 1;
+        id
+    }
+
+    pub fn get_enum(&self, s: &usize) -> Result<&EnumRef, RuntimeError> {
+        self.enums.get(s).ok_or_else(|| dangling("enum", *s))
+    }
+
+    pub fn register_enum(&mut self, e: Enum) -> usize {
+        let id = self.eid;
+
+        self.enums.insert(id, EnumRef::new(RefCell::new(e)));
+        self.eid += 1;
+        id
+    }
+
+    pub fn get_closure(&self, s: &usize) -> Result<&ClosureRef, RuntimeError> {
+        self.closures.get(s).ok_or_else(|| dangling("closure", *s))
+    }
+
+    pub fn register_closure(&mut self, c: Closure) -> usize {
+        let id = self.cid;
+
+        self.closures.insert(id, ClosureRef::new(RefCell::new(c)));
+        self.cid += 1;
+        self.allocated();
+        id
+    }
+
+    pub fn get_array(&self, s: &usize) -> Result<&ArrayRef, RuntimeError> {
+        self.arrays.get(s).ok_or_else(|| dangling("array", *s))
+    }
+
+    pub fn new_array(&mut self, elements: Vec<Value>) -> usize {
+        let id = self.aid;
+
+        self.arrays.insert(id, ArrayRef::new(elements));
+        self.aid += 1;
+        self.allocated();
+        id
+    }
+
     pub fn new_object(&mut self) -> usize {
         let id = self.oid;
         self.pool
             .insert(self.oid, ObjectRef::new(RefCell::new(Object::new())));
         self.oid += 1;
+        self.allocated();
         id
     }
 
@@ -184,6 +341,12 @@ impl VirtualMachine {
 
         self.pool.insert(id, ObjectRef::new(RefCell::new(obj)));
         self.oid += 1;
+        self.allocated();
         id
     }
 }
+
+// a heap value that was collected, or an id that never was allocated
+fn dangling(kind: &str, id: usize) -> RuntimeError {
+    RuntimeError::new(format!("No {} with id {}, it may have been collected", kind, id))
+}
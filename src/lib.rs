@@ -1,3 +1,4 @@
 pub mod jazzml;
 
 pub use self::jazzml::*;
+pub use self::jazzml::engine::{Engine, EvalError};
@@ -2,221 +2,441 @@ extern crate colored;
 
 pub mod jazzml;
 
-use self::jazzml::vm::VirtualMachine;
-
-use self::jazzml::codegen::Compiler;
-use self::jazzml::lexer::*;
-use self::jazzml::parser::*;
-use self::jazzml::source::*;
-use self::jazzml::visitor::*;
+use self::jazzml::engine::{Dump, Engine, EvalError};
 
 #[cfg(graphics)]
 pub mod glfw_bindings {
     extern crate glfw;
-    use super::jazzml::codegen::Compiler;
-    use super::jazzml::value::*;
-    use super::jazzml::vm::VirtualMachine;
+    use super::jazzml::engine::Engine;
+    use super::jazzml::error::RuntimeError;
     use glfw::ffi::*;
-    use std::mem::transmute;
-    extern "C" {
-        fn malloc(size: usize) -> *mut u8;
-    }
     use std::ffi::CString;
-    pub fn glfw_window_init(_vm: &mut VirtualMachine, args: Vec<Value>) -> Value {
-        let name = args[0].as_str(_vm);
-        let width = args[1].as_int(_vm);
-        let height = args[2].as_int(_vm);
+    use std::mem::transmute;
+
+    pub fn glfw_window_init(name: String, width: i64, height: i64) -> Result<i64, RuntimeError> {
+        let name = CString::new(name).map_err(|_| RuntimeError::new("Invalid window name"))?;
+
         unsafe {
-            let mut window = malloc(std::mem::size_of::<*mut GLFWwindow>()) as *mut _;
             glfwInit();
 
-            window = glfwCreateWindow(
-                width as _,
-                height as _,
-                CString::new(name).unwrap().as_ptr(),
-                0 as _,
-                0 as _,
-            );
+            let window = glfwCreateWindow(width as _, height as _, name.as_ptr(), 0 as _, 0 as _);
             if window.is_null() {
                 glfwTerminate();
-                return Value::Null;
+                return Err(RuntimeError::new("Failed to create window"));
             }
 
             glfwMakeContextCurrent(window);
-            return Value::Int(transmute(window));
+            Ok(transmute(window))
         }
     }
 
-    pub fn glfw_window_should_not_close(vm: &mut VirtualMachine, args: Vec<Value>) -> Value {
-        let ptr = args[0].as_int(vm);
-
-        unsafe {
-            let ret = glfwWindowShouldClose(transmute(ptr));
-
-            return Value::Bool(ret == 0);
-        }
+    pub fn glfw_window_should_not_close(ptr: i64) -> bool {
+        unsafe { glfwWindowShouldClose(transmute(ptr)) == 0 }
     }
 
-    pub fn glfw_swap_buffers(vm: &mut VirtualMachine, args: Vec<Value>) -> Value {
-        let ptr = args[0].as_int(vm);
-
+    pub fn glfw_swap_buffers(ptr: i64) {
         unsafe {
             glfwSwapBuffers(transmute(ptr));
         }
-
-        Value::Null
     }
-    pub fn glfw_poll_events(_vm: &mut VirtualMachine, _args: Vec<Value>) -> Value {
+
+    pub fn glfw_poll_events() {
         unsafe { glfwPollEvents() }
-        Value::Null
     }
 
-    pub fn glfw_terminate(_vm: &mut VirtualMachine, _args: Vec<Value>) -> Value {
+    pub fn glfw_terminate() {
         unsafe {
             glfwTerminate();
         }
-        Value::Null
     }
 
-    pub fn register_funcs(c: &mut Compiler) {
-        let id =
-            c.vm.register_native_func("glfwNewWindow".into(), &glfw_window_init, -1);
-        c.func_def.insert("glfwNewWindow".into(), id);
-        let id = c.vm.register_native_func(
-            "glfwWindowShouldNotClose".into(),
-            &glfw_window_should_not_close,
-            -1,
-        );
-        c.func_def.insert("glfwWindowShouldNotClose".into(), id);
-        let id =
-            c.vm.register_native_func("glfwSwapBuffers".into(), &glfw_swap_buffers, -1);
-        c.func_def.insert("glfwSwapBuffers".into(), id);
-        let id =
-            c.vm.register_native_func("glfwPollEvents".into(), &glfw_poll_events, -1);
-        c.func_def.insert("glfwPollEvents".into(), id);
-        let id =
-            c.vm.register_native_func("glfwTerminate".into(), &glfw_terminate, -1);
-        c.func_def.insert("glfwTerminate".into(), id);
+    pub fn register_funcs(engine: &mut Engine) {
+        engine.register_native("glfwNewWindow", glfw_window_init);
+        engine.register_native("glfwWindowShouldNotClose", glfw_window_should_not_close);
+        engine.register_native("glfwSwapBuffers", glfw_swap_buffers);
+        engine.register_native("glfwPollEvents", glfw_poll_events);
+        engine.register_native("glfwTerminate", glfw_terminate);
     }
 }
 
-use std::env::args;
-use std::fs::File;
-use std::io::Read;
+use self::jazzml::formatter;
+use self::jazzml::value::Value;
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, Read, Write};
+use std::path::Path;
+use std::process;
+use time::PreciseTime;
+
+const USAGE: &str = "\
+usage: jazz-ml-vm [command] [options] [script | -e code | -] [args...]
+
+commands:
+    run       run a script or a compiled .jmlc file, the default
+    check     type check a script without running it
+    compile   compile a script and what it imports to a .jmlc file
+    repl      evaluate lines as they are typed
+    fmt       reindent scripts in place, or print the script read from stdin
+
+options:
+    -e <code>         use <code> as the script, printing its value
+    -o <file>         where `compile` writes, the script with a .jmlc extension by default
+    --time            print how long running took
+    --check           make `fmt` list the scripts it would change instead
+    --dump-tokens     print the tokens of the script instead of running it
+    --dump-ast        print the syntax tree
+    --dump-types      print the types of the top level bindings
+    --dump-bytecode   print the disassembled bytecode
+    -h, --help        print this
+
+The script is read from stdin when it is `-` or missing. Arguments after it
+are given to the script as the `args` global, an array of strings.";
+
+// exit codes, the pipeline stages get one each
+const EXIT_RUNTIME: i32 = 1;
+const EXIT_USAGE: i32 = 2;
+
+fn exit_code(err: &EvalError) -> i32 {
+    match err {
+        EvalError::Runtime(_) => EXIT_RUNTIME,
+        EvalError::Io(_) => 3,
+        EvalError::Lex => 4,
+        EvalError::Parse => 5,
+        EvalError::Check => 6,
+        EvalError::Import => 7,
+        EvalError::Link => 8,
+        EvalError::Bytecode(_) => 9,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Command {
+    Run,
+    Check,
+    Compile,
+    Repl,
+    Fmt,
+}
+
+enum Script {
+    File(String),
+    /// Given with `-e`
+    Code(String),
+    Stdin,
+}
+
+struct Options {
+    command: Command,
+    script: Script,
+    /// Arguments after the script
+    args: Vec<String>,
+    out: Option<String>,
+    dumps: Vec<Dump>,
+    time: bool,
+    check: bool,
+    help: bool,
+}
+
+fn parse_args<I: Iterator<Item = String>>(args: I) -> Result<Options, String> {
+    let mut args = args.peekable();
+
+    let command = match args.peek().map(String::as_str) {
+        Some("run") => Some(Command::Run),
+        Some("check") => Some(Command::Check),
+        Some("compile") => Some(Command::Compile),
+        Some("repl") => Some(Command::Repl),
+        Some("fmt") => Some(Command::Fmt),
+        _ => None,
+    };
+    if command.is_some() {
+        args.next();
+    }
+
+    let mut options = Options {
+        command: command.unwrap_or(Command::Run),
+        script: Script::Stdin,
+        args: vec![],
+        out: None,
+        dumps: vec![],
+        time: false,
+        check: false,
+        help: false,
+    };
+
+    // options come before the script, everything after it is the script's
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-e" => {
+                let code = args.next().ok_or("`-e` needs the code to run")?;
+                options.script = Script::Code(code);
+                break;
+            }
+            "-o" => options.out = Some(args.next().ok_or("`-o` needs a file")?),
+            "--time" => options.time = true,
+            "--check" => options.check = true,
+            "--dump-tokens" => options.dumps.push(Dump::Tokens),
+            "--dump-ast" => options.dumps.push(Dump::Ast),
+            "--dump-types" => options.dumps.push(Dump::Types),
+            "--dump-bytecode" => options.dumps.push(Dump::Bytecode),
+            "-h" | "--help" => options.help = true,
+            "-" => break,
+            option if option.starts_with('-') => {
+                return Err(format!("unknown option `{}`", option));
+            }
+            _ => {
+                options.script = Script::File(arg);
+                break;
+            }
+        }
+    }
+    options.args = args.collect();
+
+    Ok(options)
+}
 
 fn main() {
-    let file = args().nth(1).unwrap();
-    let mut handle = File::open(&file).unwrap();
+    let options = match parse_args(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{}\n\n{}", message, USAGE);
+            process::exit(EXIT_USAGE);
+        }
+    };
+    if options.help {
+        println!("{}", USAGE);
+        return;
+    }
+
+    let mut engine = Engine::new();
+    // directories to look for imports in, like `PATH`
+    if let Some(dirs) = env::var_os("JAZZML_PATH") {
+        for dir in env::split_paths(&dirs) {
+            engine.add_search_path(dir);
+        }
+    }
+    #[cfg(graphics)]
+    glfw_bindings::register_funcs(&mut engine);
+    engine.bind("args", options.args.clone());
+
+    let result = match options.command {
+        Command::Run if !options.dumps.is_empty() => dump(&mut engine, &options),
+        Command::Run => run(&mut engine, &options),
+        Command::Check => check(&mut engine, &options),
+        Command::Compile => compile(&mut engine, &options),
+        Command::Repl => repl(&mut engine),
+        Command::Fmt => fmt(&options),
+    };
+
+    if let Err(code) = result {
+        process::exit(code);
+    }
+}
+
+// prints the errors the stages didn't report as they went
+fn report(err: EvalError) -> i32 {
+    match err {
+        EvalError::Io(_) | EvalError::Bytecode(_) | EvalError::Runtime(_) => eprintln!("{}", err),
+        _ => (),
+    }
+
+    exit_code(&err)
+}
+
+fn read_stdin() -> Result<String, i32> {
     let mut code = String::new();
+    io::stdin()
+        .read_to_string(&mut code)
+        .map_err(|err| report(err.into()))?;
+
+    Ok(code)
+}
+
+fn run(engine: &mut Engine, options: &Options) -> Result<(), i32> {
+    let start = PreciseTime::now();
 
-    handle.read_to_string(&mut code).unwrap();
-
-    let source = Source::from(
-        &file,
-        code.lines().map(|x| x.into()).collect::<Vec<String>>(),
-    );
-    let lexer = Lexer::default(code.chars().collect(), &source);
-
-    let mut tokens = Vec::new();
-
-    for token_result in lexer {
-        if let Ok(token) = token_result {
-            tokens.push(token)
-        } else {
-            return;
-        }
-    }
-
-    let mut parser = Parser::new(tokens, &source);
-
-    match parser.parse() {
-        Ok(ref ast) => {
-            let any = Type::from(TypeNode::Any);
-            let int = Type::from(TypeNode::Int);
-            let str = Type::from(TypeNode::Str);
-            let mut visitor = Visitor::new(ast, &source);
-            visitor.assign_str(
-                "print",
-                Type::function(
-                    vec![Type::from(TypeNode::Any)],
-                    Type::from(TypeNode::Any),
-                    false,
-                ),
-            );
-            visitor.assign_str(
-                "println",
-                Type::function(
-                    vec![Type::from(TypeNode::Any)],
-                    Type::from(TypeNode::Any),
-                    false,
-                ),
-            );
-            visitor.assign_str(
-                "new_obj",
-                Type::function(vec![], Type::from(TypeNode::Any), false),
-            );
-            visitor.assign_str(
-                "array_push",
-                Type::function(vec![any.clone(), any.clone()], any.clone(), false),
-            );
-            visitor.assign_str(
-                "array_pop",
-                Type::function(vec![any.clone()], any.clone(), false),
-            );
-            visitor.assign_str(
-                "array_len",
-                Type::function(vec![any.clone()], int.clone(), false),
-            );
-            visitor.assign_str(
-                "chars",
-                Type::function(vec![any.clone()], any.clone(), false),
-            );
-            visitor.assign_str(
-                "putc",
-                Type::function(vec![any.clone()], any.clone(), false),
-            );
-            visitor.assign_str(
-                "getc",
-                Type::function(vec![], any.clone(), false),
-            );
-            if cfg!(graphics) {
-                visitor.assign_str(
-                    "glfwNewWindow",
-                    Type::function(vec![str, int.clone(), int.clone()], int.clone(), false),
-                );
-                visitor.assign_str(
-                    "glfwWindowShouldNotClose",
-                    Type::function(vec![int.clone()], Type::from(TypeNode::Bool), false),
-                );
-                visitor.assign_str("glfwTerminate", Type::function(vec![], any.clone(), false));
-                visitor.assign_str("glfwPollEvents", Type::function(vec![], any.clone(), false));
-                visitor.assign_str(
-                    "glfwSwapBuffers",
-                    Type::function(vec![int], any.clone(), false),
-                );
+    let result = match options.script {
+        Script::File(ref path) if path.ends_with(".jmlc") => fs::read(path)
+            .map_err(EvalError::from)
+            .and_then(|bytes| engine.eval_bytecode(&bytes)),
+        Script::File(ref path) => engine.eval_file(path),
+        Script::Code(ref code) => engine.eval_line(code).map(|value| {
+            if value != Value::Null {
+                println!("{}", value.as_str(&engine.vm));
             }
-            match visitor.visit() {
-                Ok(_) => (),
-                _ => return,
+            value
+        }),
+        Script::Stdin => engine.eval_str(&read_stdin()?),
+    };
+
+    if options.time {
+        let ms = start.to(PreciseTime::now()).num_milliseconds();
+        eprintln!("ran in {} ms", ms);
+    }
+
+    result.map(|_| ()).map_err(report)
+}
+
+fn dump(engine: &mut Engine, options: &Options) -> Result<(), i32> {
+    let code = match options.script {
+        Script::File(_) => None,
+        Script::Code(ref code) => Some(code.clone()),
+        Script::Stdin => Some(read_stdin()?),
+    };
+
+    for dump in options.dumps.iter() {
+        let out = match (&options.script, &code) {
+            (Script::File(ref path), _) => engine.dump_file(path, *dump),
+            (_, Some(code)) => engine.dump_str(code, *dump),
+            _ => unreachable!(),
+        };
+        print!("{}", out.map_err(report)?);
+    }
+
+    Ok(())
+}
+
+fn check(engine: &mut Engine, options: &Options) -> Result<(), i32> {
+    match options.script {
+        Script::File(ref path) => engine.check_file(path),
+        Script::Code(ref code) => engine.check_str(code),
+        Script::Stdin => engine.check_str(&read_stdin()?),
+    }
+    .map_err(report)
+}
+
+fn compile(engine: &mut Engine, options: &Options) -> Result<(), i32> {
+    let (bytes, out) = match options.script {
+        Script::File(ref path) => {
+            let out = match options.out {
+                Some(ref out) => out.clone(),
+                None => Path::new(path)
+                    .with_extension("jmlc")
+                    .to_string_lossy()
+                    .into_owned(),
+            };
+            (engine.compile_file(path), out)
+        }
+        _ => {
+            let out = match options.out {
+                Some(ref out) => out.clone(),
+                None => {
+                    eprintln!("`-o` is needed to compile code that isn't in a file");
+                    return Err(EXIT_USAGE);
+                }
+            };
+            let code = match options.script {
+                Script::Code(ref code) => code.clone(),
+                _ => read_stdin()?,
+            };
+            (engine.compile_str(&code), out)
+        }
+    };
+
+    let bytes = bytes.map_err(report)?;
+    fs::write(&out, bytes).map_err(|err| report(err.into()))
+}
+
+fn repl(engine: &mut Engine) -> Result<(), i32> {
+    let stdin = io::stdin();
+    let mut code = String::new();
+
+    loop {
+        print!("{}", if code.is_empty() { "> " } else { ". " });
+        io::stdout().flush().map_err(|err| report(err.into()))?;
+
+        let mut line = String::new();
+        match stdin.lock().read_line(&mut line) {
+            Ok(0) => return Ok(()),
+            Ok(_) => code.push_str(&line),
+            Err(err) => return Err(report(err.into())),
+        }
+
+        // keep reading until every bracket is closed
+        if open_brackets(&code) > 0 {
+            continue;
+        }
+
+        match engine.eval_line(&code) {
+            Ok(ref value) if **value == Value::Null => (),
+            Ok(value) => println!("{}", value.as_str(&engine.vm)),
+            Err(err) => {
+                report(err);
             }
+        }
+        code.clear();
+    }
+}
 
-            use time::PreciseTime;
+// brackets opened and not closed yet, outside of strings and comments
+fn open_brackets(code: &str) -> i32 {
+    let mut open = 0;
+    let mut chars = code.chars();
 
-            let mut vm = VirtualMachine::new();
-            let start = PreciseTime::now();
-            let mut compiler = Compiler::new(&mut vm);
+    while let Some(ch) = chars.next() {
+        match ch {
+            '(' | '[' | '{' => open += 1,
+            ')' | ']' | '}' => open -= 1,
+            '"' => {
+                while let Some(ch) = chars.next() {
+                    match ch {
+                        '\\' => {
+                            chars.next();
+                        }
+                        '"' => break,
+                        _ => (),
+                    }
+                }
+            }
+            '#' => {
+                for ch in chars.by_ref() {
+                    if ch == '\n' {
+                        break;
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+
+    open
+}
 
-            compiler.compile(ast.clone());
+fn fmt(options: &Options) -> Result<(), i32> {
+    let files = match options.script {
+        Script::File(ref path) => {
+            let mut files = vec![path.clone()];
+            files.extend(options.args.iter().cloned());
+            files
+        }
+        Script::Code(ref code) => {
+            print!("{}", formatter::format("<eval>", code).map_err(report)?);
+            return Ok(());
+        }
+        Script::Stdin => {
+            let code = read_stdin()?;
+            print!("{}", formatter::format("<stdin>", &code).map_err(report)?);
+            return Ok(());
+        }
+    };
 
-            let ins = compiler.finish();
-            
-            let ret = vm.run_instructions(ins);
-            let end = PreciseTime::now();
-            let result = start.to(end).num_milliseconds();
+    let mut changed = false;
+    for file in files.iter() {
+        let code = fs::read_to_string(file).map_err(|err| report(err.into()))?;
+        let formatted = formatter::format(file, &code).map_err(report)?;
 
-            println!("RESULT: {} in {} ms", ret.as_str(&mut vm), result);
+        if formatted != code {
+            changed = true;
+            if options.check {
+                println!("{}", file);
+            } else {
+                fs::write(file, formatted).map_err(|err| report(err.into()))?;
+            }
         }
+    }
 
-        _ => (),
+    if options.check && changed {
+        Err(EXIT_RUNTIME)
+    } else {
+        Ok(())
     }
 }
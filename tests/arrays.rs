@@ -0,0 +1,129 @@
+extern crate jazz_ml_vm;
+
+mod common;
+
+use common::{compile, global, run, vm};
+use jazz_ml_vm::value::Value;
+use jazz_ml_vm::{Engine, EvalError};
+
+fn show(code: &str) -> String {
+    let vm = run(code);
+    global(&vm, "result").as_str(&vm)
+}
+
+fn error(code: &str) -> String {
+    let mut vm = vm();
+    let ins = compile(&mut vm, code);
+    vm.run_instructions(ins).unwrap_err().message
+}
+
+#[test]
+fn arrays_print_their_contents() {
+    assert_eq!(show("var result = [1, \"two\", [3.5]]"), "[1, two, [3.5]]");
+    assert_eq!(show("var result = []"), "[]");
+}
+
+#[test]
+fn cycles_print_as_ellipses() {
+    assert_eq!(show("var result = [1]\narray_push(result, result)"), "[1, [...]]");
+    assert_eq!(
+        show("var result = new_obj()\nresult.items = [result]"),
+        "[items: [[...]]]"
+    );
+    // the same array twice is not a cycle
+    assert_eq!(show("var a = [1]\nvar result = [a, a]"), "[[1], [1]]");
+}
+
+#[test]
+fn indexing_counts_negative_indices_from_the_end() {
+    assert_eq!(
+        show("var a = [1, 2, 3]\nvar result = [a[0], a[2], a[-1], a[-3]]"),
+        "[1, 3, 3, 1]"
+    );
+    assert_eq!(show("var result = \"hello\"[-2]"), "l");
+}
+
+#[test]
+fn grown_arrays_can_be_indexed() {
+    let mut engine = Engine::new();
+    let ret = engine
+        .eval_str("var xs = []\narray_push(xs, 1)\nreturn xs[0]")
+        .unwrap();
+    assert_eq!(ret.as_str(&engine.vm), "1");
+
+    // bounds are checked when indexing runs, not when it's type checked
+    match engine.eval_str("var ys = [1, 2]\narray_pop(ys)\nreturn ys[1]") {
+        Err(EvalError::Runtime(err)) => {
+            assert_eq!(err.message, "Index 1 out of bounds for length 1")
+        }
+        other => panic!("expected runtime error, got {:?}", other),
+    }
+}
+
+#[test]
+fn indexing_is_bounds_checked() {
+    assert_eq!(
+        error("var a = [1, 2]\na[2]"),
+        "Index 2 out of bounds for length 2"
+    );
+    assert_eq!(
+        error("var a = [1, 2]\na[-3]"),
+        "Index -3 out of bounds for length 2"
+    );
+    assert_eq!(
+        error("var a = []\na[0] = 1"),
+        "Index 0 out of bounds for length 0"
+    );
+}
+
+#[test]
+fn elements_are_assigned_in_place() {
+    let code = "
+var a = [1, 2, 3]
+var b = a
+b[1] = 20
+a[-1] = 30
+var result = a
+";
+    assert_eq!(show(code), "[1, 20, 30]");
+}
+
+#[test]
+fn slices_copy_part_of_an_array() {
+    let code = "
+var a = [1, 2, 3, 4]
+var result = [a[1..3], a[..2], a[2..], a[-3..-1], a[..], a[2..2]]
+";
+    assert_eq!(
+        show(code),
+        "[[2, 3], [1, 2], [3, 4], [2, 3], [1, 2, 3, 4], []]"
+    );
+    assert_eq!(show("var result = \"hello\"[1..4]"), "ell");
+
+    assert_eq!(
+        error("[1, 2][1..3]"),
+        "Slice bound 3 out of bounds for length 2"
+    );
+    assert_eq!(error("[1, 2][2..1]"), "Slice starts at 2 but ends at 1");
+}
+
+#[test]
+fn ranges_are_arrays_of_integers() {
+    assert_eq!(show("var n = 4\nvar result = 1..n + 1"), "[1, 2, 3, 4]");
+    assert_eq!(show("var result = 3..1"), "[]");
+}
+
+#[test]
+fn builtins_work_on_arrays() {
+    let code = "
+var a = chars(\"abc\")
+array_push(a, \"d\")
+var last = array_pop(a)
+array_push(a, last)
+var len = array_len(a)
+var result = a
+";
+    let vm = run(code);
+    assert_eq!(global(&vm, "result").as_str(&vm), "[a, b, c, d]");
+    assert_eq!(global(&vm, "len"), Value::Int(4));
+}
@@ -0,0 +1,238 @@
+extern crate jazz_ml_vm;
+
+use jazz_ml_vm::bytecode::{self, Unit, MAX_LOCALS, VERSION};
+use jazz_ml_vm::opcodes::Opcode;
+use jazz_ml_vm::value::{Capture, FuncKind, LineTable, Value};
+use jazz_ml_vm::vm::VirtualMachine;
+use jazz_ml_vm::{Engine, EvalError};
+
+fn fixture(name: &str) -> String {
+    format!("{}/tests/modules/{}", env!("CARGO_MANIFEST_DIR"), name)
+}
+
+// compiles in one engine and runs in a fresh one
+fn roundtrip(code: &str) -> String {
+    let bytes = Engine::new().compile_str(code).unwrap();
+
+    let mut engine = Engine::new();
+    let ret = engine.eval_bytecode(&bytes).unwrap();
+    ret.as_str(&engine.vm)
+}
+
+fn load_error(bytes: &[u8]) -> String {
+    match Engine::new().eval_bytecode(bytes) {
+        Err(EvalError::Bytecode(err)) => err.message,
+        other => panic!("expected bytecode error, got {:?}", other),
+    }
+}
+
+#[test]
+fn functions_and_closures_survive() {
+    let code = "
+let fib = func(n: int) : int {
+  if n < 2 { return n }
+  return fib(n - 1) + fib(n - 2)
+}
+let adder = func(n: int) : func(int) -> int {
+  return func(x: int) : int { return x + n }
+}
+var add = adder(10)
+var total = 0
+for i in 0..4 {
+  total = total + add(i)
+}
+return fib(10) ++ \" \" ++ total ++ \" \" ++ 2.5 * 2.0
+";
+    assert_eq!(roundtrip(code), "55 46 5");
+}
+
+#[test]
+fn switches_structs_and_enums_survive() {
+    let code = "
+let Kind = enum {
+  Small
+  Big
+}
+let Item = struct {
+  kind: Kind
+  name: string
+}
+let size = func(n: int) : any {
+  return switch n {
+    1 => Kind.Small
+    _ => Kind.Big
+  }
+}
+var item = new Item { kind: size(1), name: \"pin\" }
+return switch item {
+  Item { kind: Kind.Small, name } => name
+  _ => \"big\"
+}
+";
+    assert_eq!(roundtrip(code), "pin");
+}
+
+#[test]
+fn imported_modules_are_compiled_in() {
+    let bytes = Engine::new().compile_file(&fixture("main.jml")).unwrap();
+
+    let mut engine = Engine::new();
+    engine.eval_bytecode(&bytes).unwrap();
+    let result = engine.global("result").unwrap().as_str(&engine.vm);
+    assert_eq!(result, "[9, 12, 13, 10]");
+}
+
+#[test]
+fn runtime_errors_keep_their_file() {
+    let bytes = Engine::new()
+        .compile_str("let f = func(xs: [int]) : int {\n  return xs[3]\n}\nf([1])")
+        .unwrap();
+
+    let err = match Engine::new().eval_bytecode(&bytes) {
+        Err(EvalError::Runtime(err)) => err,
+        other => panic!("expected runtime error, got {:?}", other),
+    };
+    let files = err.trace.iter().map(|(_, loc)| {
+        let loc = loc.as_ref().unwrap();
+        format!("{}:{}", loc.file, (loc.pos.0).0)
+    });
+    assert_eq!(files.collect::<Vec<_>>(), vec!["<eval>:2", "<eval>:4"]);
+}
+
+#[test]
+fn externs_are_linked_when_loading() {
+    let code = "extern repeat: func(int, string) -> string\nreturn repeat(2, \"ab\")";
+
+    let mut host = Engine::new();
+    host.register_native("repeat", |n: i64, s: String| s.repeat(n as usize));
+    let bytes = host.compile_str(code).unwrap();
+
+    let mut engine = Engine::new();
+    engine.register_native("repeat", |n: i64, s: String| s.repeat(n as usize));
+    assert_eq!(
+        engine.eval_bytecode(&bytes).unwrap(),
+        Value::Str("abab".to_owned())
+    );
+
+    match Engine::new().eval_bytecode(&bytes) {
+        Err(EvalError::Link) => (),
+        other => panic!("expected link error, got {:?}", other),
+    }
+
+    // the declared signature is kept in the bytecode
+    let mut engine = Engine::new();
+    engine.register_native("repeat", |n: i64, s: String| vec![s; n as usize]);
+    match engine.eval_bytecode(&bytes) {
+        Err(EvalError::Link) => (),
+        other => panic!("expected link error, got {:?}", other),
+    }
+}
+
+#[test]
+fn other_versions_are_rejected() {
+    let mut bytes = Engine::new().compile_str("return 1").unwrap();
+    bytes[4..8].copy_from_slice(&(VERSION + 1).to_le_bytes());
+
+    assert_eq!(
+        load_error(&bytes),
+        format!(
+            "compiled for bytecode version {}, this VM reads version {}",
+            VERSION + 1,
+            VERSION
+        )
+    );
+}
+
+#[test]
+fn malformed_files_are_rejected() {
+    let bytes = Engine::new().compile_str("println(1)").unwrap();
+
+    assert_eq!(load_error(b"return 1"), "not a compiled JazzML file");
+    assert_eq!(
+        load_error(&bytes[..bytes.len() - 1]),
+        "unexpected end of bytecode"
+    );
+
+    let mut extra = bytes.clone();
+    extra.push(0);
+    assert_eq!(load_error(&extra), "trailing bytes after the last unit");
+}
+
+// bytecode for a program calling a function made of the given parts
+fn program(nargs: i32, locals: usize, captures: Vec<Capture>, code: Vec<Opcode>) -> Vec<u8> {
+    let mut vm = VirtualMachine::new();
+    let args = (0..nargs.max(0)).map(|i| format!("a{}", i)).collect();
+    let id = vm.new_func(nargs, args);
+    {
+        let mut func = vm.get_func(&id).borrow_mut();
+        func.name = "f".to_owned();
+        func.kind = FuncKind::Interpret(code);
+        func.captures = captures;
+        func.locals = locals;
+    }
+
+    let unit = Unit {
+        file: "<test>".to_owned(),
+        exports: None,
+        externs: vec![],
+        code: vec![Opcode::MakeClosure(id), Opcode::Ret],
+        lines: LineTable::default(),
+    };
+    bytecode::write(&vm, &[unit]).unwrap()
+}
+
+#[test]
+fn operands_are_checked_when_loading() {
+    let ret = vec![Opcode::PushNull, Opcode::Ret];
+    let load = |op: Opcode| vec![op, Opcode::Ret];
+
+    assert_eq!(
+        load_error(&program(0, 1, vec![], load(Opcode::LoadSlot(1)))),
+        "function `f`: slot 1 out of 1 locals"
+    );
+    let store = vec![Opcode::PushNull, Opcode::StoreCell(7), Opcode::Ret];
+    assert_eq!(
+        load_error(&program(0, 2, vec![], store)),
+        "function `f`: slot 7 out of 2 locals"
+    );
+    assert_eq!(
+        load_error(&program(2, 1, vec![], ret.clone())),
+        "function `f`: 1 locals can't hold 2 arguments"
+    );
+    assert_eq!(
+        load_error(&program(0, 0, vec![], load(Opcode::LoadUpvalue(0)))),
+        "function `f`: upvalue 0 out of 0 captures"
+    );
+    assert_eq!(
+        load_error(&program(0, MAX_LOCALS + 1, vec![], ret.clone())),
+        format!("function `f`: {} locals is too many", MAX_LOCALS + 1)
+    );
+    // the closure is made at the top level, which has no upvalues and here no
+    // locals either
+    assert_eq!(
+        load_error(&program(0, 0, vec![Capture::Upvalue(0)], ret.clone())),
+        "`<test>`: upvalue 0 out of 0 captures"
+    );
+    assert_eq!(
+        load_error(&program(0, 0, vec![Capture::Local(0)], ret)),
+        "`<test>`: slot 0 out of 0 locals"
+    );
+
+    // a function with enough room loads
+    let bytes = program(1, 2, vec![], load(Opcode::LoadSlot(1)));
+    Engine::new().eval_bytecode(&bytes).unwrap();
+}
+
+#[test]
+fn element_counts_are_not_trusted() {
+    let huge = usize::MAX / 16;
+    for op in [Opcode::Amake(huge), Opcode::Mmake(huge)].iter() {
+        let bytes = program(0, 0, vec![], vec![op.clone(), Opcode::Ret]);
+        let mut engine = Engine::new();
+        let f = engine.eval_bytecode(&bytes).unwrap().value().clone();
+        engine.set_global("f", f);
+
+        let err = engine.call_global("f", vec![]);
+        assert_eq!(err.unwrap_err().message, "No value to pop");
+    }
+}
@@ -0,0 +1,94 @@
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+fn jazz(args: &[&str], stdin: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_jazz-ml-vm"))
+        .args(args)
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(stdin.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+#[test]
+fn scripts_get_their_arguments() {
+    let output = jazz(&["-e", "println(args)", "a", "--b"], "");
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&output), "[a, --b]\n");
+
+    let output = jazz(&["run", "-", "x"], "println(len(args))");
+    assert_eq!(stdout(&output), "1\n");
+}
+
+#[test]
+fn expressions_print_their_value() {
+    let output = jazz(&["-e", "var x = 20\nx * 2 + 2"], "");
+    assert_eq!(stdout(&output), "42\n");
+
+    let output = jazz(&["repl"], "var xs = [\n1,\n2]\nlen(xs)\n");
+    assert!(stdout(&output).contains("2\n"), "{}", stdout(&output));
+}
+
+#[test]
+fn timing_is_opt_in() {
+    let output = jazz(&["-e", "1"], "");
+    assert!(output.stderr.is_empty());
+
+    let output = jazz(&["run", "--time", "-e", "1"], "");
+    assert!(String::from_utf8_lossy(&output.stderr).contains(" ms"));
+}
+
+#[test]
+fn stages_exit_with_their_own_code() {
+    let cases: &[(&[&str], i32)] = &[
+        (&["-e", "var a = [1]\nvar i = 3\na[i]"], 1),
+        (&["--bogus"], 2),
+        (&["missing.jml"], 3),
+        (&["-e", "var x = 1 @ 2"], 4),
+        (&["-e", "var = 1"], 5),
+        (&["-e", "var x: int = \"s\""], 6),
+        (&["-e", "import \"missing.jml\" as m"], 7),
+        (&["-e", "extern nope: func() -> int"], 8),
+        (&["check", "-e", "var x: int = \"s\""], 6),
+    ];
+
+    for (args, code) in cases.iter() {
+        assert_eq!(jazz(args, "").status.code(), Some(*code), "{:?}", args);
+    }
+}
+
+#[test]
+fn check_does_not_run() {
+    let output = jazz(&["check", "-e", "println(1)"], "");
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&output), "");
+}
+
+#[test]
+fn compiled_scripts_run() {
+    let out = format!("{}/cli_test.jmlc", env!("CARGO_TARGET_TMPDIR"));
+
+    let output = jazz(&["compile", "-o", &out, "-e", "println(args)"], "");
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&jazz(&[&out, "z"], "")), "[z]\n");
+}
+
+#[test]
+fn fmt_reads_stdin() {
+    let output = jazz(&["fmt"], "if true {\nprintln(1)\n}");
+    assert_eq!(stdout(&output), "if true {\n  println(1)\n}\n");
+}
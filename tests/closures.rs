@@ -0,0 +1,92 @@
+extern crate jazz_ml_vm;
+
+use jazz_ml_vm::{Engine, EvalError};
+
+fn eval(code: &str) -> String {
+    let mut engine = Engine::new();
+    let ret = engine.eval_str(code).unwrap();
+    ret.as_str(&engine.vm)
+}
+
+fn error(code: &str) -> EvalError {
+    match Engine::new().eval_str(code) {
+        Err(err) => err,
+        Ok(ret) => panic!("expected an error, got {:?}", ret),
+    }
+}
+
+#[test]
+fn captured_variables_outlive_their_function() {
+    let code = "
+let make_counter = func() : any {
+  var count = 0
+  return func() : int {
+    count = count + 1
+    return count
+  }
+}
+var a = make_counter()
+var b = make_counter()
+a()
+a()
+return [a(), b()]
+";
+    assert_eq!(eval(code), "[3, 1]");
+}
+
+#[test]
+fn closures_share_what_they_capture() {
+    let code = "
+let pair = func() : [any] {
+  var n = 0
+  let inc = func() : int {
+    n = n + 1
+    return n
+  }
+  let get = func() : int { return n }
+  return [inc, get]
+}
+var fs = pair()
+var inc = fs[0]
+var get = fs[1]
+inc()
+inc()
+return get()
+";
+    assert_eq!(eval(code), "2");
+}
+
+#[test]
+fn nested_closures_reach_outer_variables() {
+    let code = "
+let adder = func(a: int) : any {
+  return func(b: int) : any {
+    return func(c: int) : int { return a + b + c }
+  }
+}
+return adder(1)(20)(300)
+";
+    assert_eq!(eval(code), "321");
+}
+
+#[test]
+fn malformed_functions_dont_parse() {
+    match error("let f = func(x: int : int { return x }") {
+        EvalError::Parse => (),
+        other => panic!("expected parse error, got {:?}", other),
+    }
+}
+
+#[test]
+fn closure_bodies_are_checked() {
+    let code = "
+let outer = func() : any {
+  var s = \"text\"
+  return func() : int { return s }
+}
+";
+    match error(code) {
+        EvalError::Check => (),
+        other => panic!("expected type error, got {:?}", other),
+    }
+}
@@ -0,0 +1,44 @@
+#![allow(dead_code)]
+
+use jazz_ml_vm::codegen::Compiler;
+use jazz_ml_vm::lexer::*;
+use jazz_ml_vm::opcodes::Opcode;
+use jazz_ml_vm::parser::*;
+use jazz_ml_vm::source::*;
+use jazz_ml_vm::value::Value;
+use jazz_ml_vm::vm::VirtualMachine;
+
+pub fn compile(vm: &mut VirtualMachine, code: &str) -> Vec<Opcode> {
+    let source = Source::from("<test>", code.lines().map(|x| x.into()).collect());
+    let tokens = Lexer::default(code.chars().collect(), &source)
+        .map(|token| token.expect("lexing failed"))
+        .collect();
+    let ast = Parser::new(tokens, &source)
+        .parse()
+        .expect("parsing failed");
+
+    let mut compiler = Compiler::new(vm);
+    compiler.compile(ast);
+    compiler.finish()
+}
+
+/// A VM with the builtins registered, ready to compile for
+pub fn vm() -> VirtualMachine {
+    let mut vm = VirtualMachine::new();
+    vm.init_builtins();
+    vm
+}
+
+pub fn run(code: &str) -> VirtualMachine {
+    let mut vm = vm();
+    let ins = compile(&mut vm, code);
+    vm.run_instructions(ins).expect("runtime error");
+    vm
+}
+
+pub fn global(vm: &VirtualMachine, name: &str) -> Value {
+    vm.globals
+        .get(&Value::Str(name.to_owned()))
+        .expect("no such global")
+        .clone()
+}
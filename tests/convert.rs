@@ -0,0 +1,101 @@
+extern crate jazz_ml_vm;
+
+use std::collections::HashMap;
+
+use jazz_ml_vm::convert::{FromValue, IntoValue};
+use jazz_ml_vm::error::RuntimeError;
+use jazz_ml_vm::value::Value;
+use jazz_ml_vm::vm::VirtualMachine;
+use jazz_ml_vm::{Engine, EvalError};
+
+fn roundtrip<T: FromValue + IntoValue + Clone>(value: T) -> T {
+    let mut vm = VirtualMachine::new();
+    let value = value.into_value(&mut vm);
+    T::from_value(value, &vm).unwrap()
+}
+
+#[test]
+fn values_roundtrip() {
+    assert_eq!(roundtrip(-7i64), -7);
+    assert_eq!(roundtrip(2.5f64), 2.5);
+    assert_eq!(roundtrip(true), true);
+    assert_eq!(roundtrip("jazz".to_owned()), "jazz");
+    assert_eq!(roundtrip(vec![1i32, 2, 3]), vec![1, 2, 3]);
+    assert_eq!(roundtrip(Some(3u8)), Some(3));
+    assert_eq!(roundtrip(None::<u8>), None);
+    assert_eq!(
+        roundtrip((1i64, "a".to_owned(), false)),
+        (1, "a".to_owned(), false)
+    );
+
+    let mut map = HashMap::new();
+    map.insert("x".to_owned(), vec![1i64]);
+    assert_eq!(roundtrip(map.clone()), map);
+}
+
+#[test]
+fn mismatches_are_errors() {
+    let mut vm = VirtualMachine::new();
+    let pair = vec![1i64].into_value(&mut vm);
+
+    assert!(i64::from_value(Value::Str("1".into()), &vm).is_err());
+    assert!(u8::from_value(Value::Int(300), &vm).is_err());
+    assert!(String::from_value(Value::Int(1), &vm).is_err());
+    assert!(<(i64, i64)>::from_value(pair, &vm).is_err());
+}
+
+fn repeat(n: i64, s: String) -> Vec<String> {
+    (0..n).map(|_| s.clone()).collect()
+}
+
+fn checked_div(a: i64, b: i64) -> Result<i64, RuntimeError> {
+    if b == 0 {
+        return Err(RuntimeError::new("division by zero"));
+    }
+    Ok(a / b)
+}
+
+#[test]
+fn plain_functions_are_registered() {
+    let mut engine = Engine::new();
+    engine.register_native("repeat", repeat);
+    engine.register_native("checked_div", checked_div);
+    engine.register_native("join", |parts: Vec<String>| parts.join("-"));
+
+    let ret = engine.eval_str("return join(repeat(3, \"ab\"))").unwrap();
+    assert_eq!(ret, Value::Str("ab-ab-ab".into()));
+
+    let ret = engine.eval_str("return checked_div(84, 2)").unwrap();
+    assert_eq!(ret, Value::Int(42));
+
+    match engine.eval_str("return checked_div(1, 0)") {
+        Err(EvalError::Runtime(err)) => assert_eq!(err.message, "division by zero"),
+        _ => panic!("expected a runtime error"),
+    }
+
+    // signatures come from the Rust types
+    match engine.eval_str("repeat(\"3\", \"ab\")") {
+        Err(EvalError::Check) => (),
+        _ => panic!("expected a type error"),
+    }
+    match engine.eval_str("repeat(3)") {
+        Err(EvalError::Check) => (),
+        _ => panic!("expected a type error"),
+    }
+}
+
+#[test]
+fn maps_are_typed_by_their_values() {
+    let mut engine = Engine::new();
+    engine.register_native("total", |m: HashMap<String, i64>| m.values().sum::<i64>());
+
+    let ret = engine.eval_str("return total([\"a\": 1, \"b\": 2])").unwrap();
+    assert_eq!(ret, Value::Int(3));
+
+    for code in ["total([\"a\": \"1\"])", "total([1: 1])", "total([1])"].iter() {
+        match engine.eval_str(code) {
+            Err(EvalError::Check) => (),
+            other => panic!("expected a type error for {:?}, got {:?}", code, other),
+        }
+    }
+}
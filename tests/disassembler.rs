@@ -0,0 +1,125 @@
+extern crate fnv;
+extern crate jazz_ml_vm;
+
+mod common;
+
+use common::{compile, vm};
+use fnv::FnvHashMap;
+use jazz_ml_vm::disassembler::Disassembler;
+use jazz_ml_vm::engine::Dump;
+use jazz_ml_vm::opcodes::Opcode;
+use jazz_ml_vm::vm::VirtualMachine;
+use jazz_ml_vm::Engine;
+
+fn fixture(name: &str) -> String {
+    format!("{}/tests/modules/{}", env!("CARGO_MANIFEST_DIR"), name)
+}
+
+#[test]
+fn operands_are_resolved() {
+    let mut vm = VirtualMachine::new();
+    let id = vm.new_func(1, vec!["n".to_owned()]);
+    vm.get_func(&id).borrow_mut().name = "<lambda>".to_owned();
+
+    let mut names = FnvHashMap::default();
+    names.insert(id, "twice".to_owned());
+    let dis = Disassembler::new(&vm, &names);
+
+    assert_eq!(
+        dis.op(&Opcode::PushFunc(id)),
+        format!("PushFunc twice#{}", id)
+    );
+    assert_eq!(dis.op(&Opcode::JmpF(7)), "JmpF -> 7");
+    assert_eq!(
+        dis.op(&Opcode::PushStr("a\"b".to_owned())),
+        "PushStr \"a\\\"b\""
+    );
+    assert_eq!(dis.op(&Opcode::LoadSlot(2)), "LoadSlot 2");
+    assert_eq!(dis.op(&Opcode::Add), "Add");
+    assert_eq!(
+        dis.op(&Opcode::MakeStruct(
+            "Point".to_owned(),
+            vec![("x".to_owned(), false), ("y".to_owned(), true)]
+        )),
+        "MakeStruct Point { x, y = .. }"
+    );
+
+    // names the compiler didn't know come from the function itself
+    let empty = FnvHashMap::default();
+    let dis = Disassembler::new(&vm, &empty);
+    assert_eq!(
+        dis.op(&Opcode::MakeClosure(id)),
+        format!("MakeClosure <lambda>#{}", id)
+    );
+}
+
+#[test]
+fn jump_tables_are_listed_by_target() {
+    let mut vm = vm();
+    let ins = compile(
+        &mut vm,
+        "var x = 2\nvar y = switch x {\n  1 => \"one\"\n  2 => \"two\"\n  _ => \"many\"\n}",
+    );
+
+    let names = FnvHashMap::default();
+    let dis = Disassembler::new(&vm, &names);
+    let table = ins
+        .iter()
+        .find(|op| match op {
+            Opcode::JmpTable(..) => true,
+            _ => false,
+        })
+        .map(|op| dis.op(op))
+        .unwrap();
+
+    let targets = table
+        .trim_start_matches("JmpTable ")
+        .split(", ")
+        .map(|arm| arm.split(" -> ").collect::<Vec<&str>>())
+        .collect::<Vec<Vec<&str>>>();
+    assert_eq!(targets.len(), 3);
+    assert_eq!(
+        (targets[0][0], targets[1][0], targets[2][0]),
+        ("1", "2", "_")
+    );
+    assert!(targets[0][1].parse::<usize>().unwrap() < targets[1][1].parse::<usize>().unwrap());
+}
+
+#[test]
+fn programs_list_their_functions_and_lines() {
+    let mut engine = Engine::new();
+    let listing = engine
+        .dump_file(&fixture("shapes.jml"), Dump::Bytecode)
+        .unwrap();
+
+    // the imported module comes first
+    let counter = listing.find("lib/counter.jml\n").unwrap();
+    let shapes = listing.find("shapes.jml\n").unwrap();
+    assert!(counter < shapes);
+
+    assert!(listing.contains("PushFunc square#"));
+    assert!(listing.contains("\nfunc square#"));
+    assert!(listing.contains("(n) locals: 1\n      4 | return n * n\n      0   LoadSlot 0\n"));
+    assert!(listing.contains("\nfunc bump#"));
+
+    // nothing was run
+    assert!(engine.global(&fixture("shapes.jml")).is_none());
+}
+
+#[test]
+fn stages_can_be_dumped() {
+    let mut engine = Engine::new();
+    let path = fixture("shapes.jml");
+
+    let tokens = engine.dump_file(&path, Dump::Tokens).unwrap();
+    assert!(tokens.starts_with("   1:1    Keyword    \"import\"\n"));
+
+    let ast = engine.dump_file(&path, Dump::Ast).unwrap();
+    assert!(ast.contains("Import(\n        \"lib/counter.jml\",\n        \"c\",\n    )"));
+
+    let types = engine.dump_file(&path, Dump::Types).unwrap();
+    assert_eq!(
+        types,
+        "c: module\nsquare: fun(int) -> int\nbumps: fun() -> int\n"
+    );
+}
@@ -0,0 +1,149 @@
+extern crate jazz_ml_vm;
+
+use std::cell::Cell;
+use std::rc::Rc;
+
+use jazz_ml_vm::error::RuntimeError;
+use jazz_ml_vm::value::Value;
+use jazz_ml_vm::visitor::{Type, TypeNode};
+use jazz_ml_vm::vm::VirtualMachine;
+use jazz_ml_vm::{Engine, EvalError};
+
+fn double(vm: &mut VirtualMachine, args: Vec<Value>) -> Result<Value, RuntimeError> {
+    Ok(Value::Int(args[0].as_int(vm)? * 2))
+}
+
+#[test]
+fn eval_returns_top_level_return() {
+    let mut engine = Engine::new();
+    let ret = engine.eval_str("var x = 20\nreturn x + 22").unwrap();
+
+    assert_eq!(ret, Value::Int(42));
+}
+
+#[test]
+fn globals_persist_between_evaluations() {
+    let mut engine = Engine::new();
+    engine.eval_str("var base = 40").unwrap();
+    let ret = engine.eval_str("return base + 2").unwrap();
+
+    assert_eq!(ret, Value::Int(42));
+}
+
+#[test]
+fn evaluations_dont_register_builtins_again() {
+    let mut engine = Engine::new();
+    let functions = engine.vm.functions.len();
+
+    engine.eval_str("var keys = 5").unwrap();
+    for _ in 0..10 {
+        engine.eval_str("var n = 1").unwrap();
+    }
+
+    assert_eq!(engine.global("keys"), Some(&Value::Int(5)));
+    assert_eq!(engine.vm.functions.len(), functions);
+}
+
+#[test]
+fn host_sets_globals_and_calls_functions() {
+    let mut engine = Engine::new();
+    engine.set_global("offset", Value::Int(2));
+    engine
+        .eval_str(
+            "
+let add = func(a: int) : int {
+  return a + offset
+}
+",
+        )
+        .unwrap();
+
+    let ret = engine.call_global("add", vec![Value::Int(40)]).unwrap();
+    assert_eq!(ret, Value::Int(42));
+
+    let err = engine.call_global("add", vec![]).unwrap_err();
+    assert!(err.message.contains("expected 1"), "{}", err.message);
+    assert!(engine.call_global("missing", vec![]).is_err());
+}
+
+#[test]
+fn registered_functions_are_type_checked() {
+    let int = Type::from(TypeNode::Int);
+
+    let mut engine = Engine::new();
+    engine.register_fn(
+        "double",
+        Type::function(vec![int.clone()], int, false),
+        double,
+    );
+
+    let ret = engine.eval_str("return double(21)").unwrap();
+    assert_eq!(ret, Value::Int(42));
+
+    match engine.eval_str("return double(\"x\")") {
+        Err(EvalError::Check) => (),
+        _ => panic!("expected a type error"),
+    }
+}
+
+#[test]
+fn natives_capture_host_state() {
+    let calls = Rc::new(Cell::new(0));
+    let any = Type::from(TypeNode::Any);
+
+    let mut engine = Engine::new();
+    let counter = calls.clone();
+    engine.register_fn(
+        "tick",
+        Type::function(vec![], any.clone(), false),
+        move |_, _| {
+            counter.set(counter.get() + 1);
+            Ok(Value::Int(counter.get()))
+        },
+    );
+    engine.register_fn("fail", Type::function(vec![], any, false), |_, _| {
+        Err(RuntimeError::new("raised by host"))
+    });
+
+    let ret = engine.eval_str("tick()\ntick()\nreturn tick()").unwrap();
+    assert_eq!(ret, Value::Int(3));
+    assert_eq!(calls.get(), 3);
+
+    match engine.eval_str("fail()") {
+        Err(EvalError::Runtime(err)) => assert_eq!(err.message, "raised by host"),
+        _ => panic!("expected a runtime error"),
+    }
+}
+
+#[test]
+fn lines_return_their_trailing_expression() {
+    let mut engine = Engine::new();
+    assert_eq!(engine.eval_line("var x = 40").unwrap(), Value::Null);
+    assert_eq!(engine.eval_line("x + 2").unwrap(), Value::Int(42));
+    assert_eq!(engine.eval_line("x = 1\nx * 3").unwrap(), Value::Int(3));
+}
+
+#[test]
+fn checking_declares_without_running() {
+    let mut engine = Engine::new();
+    engine.check_str("var x = 1\nprintln(x)").unwrap();
+    assert!(engine.global("x").is_none());
+
+    match engine.check_str("var s: string = x") {
+        Err(EvalError::Check) => (),
+        other => panic!("expected type error, got {:?}", other),
+    }
+}
+
+#[test]
+fn bound_values_are_typed() {
+    let mut engine = Engine::new();
+    engine.bind("args", vec!["a".to_owned(), "b".to_owned()]);
+
+    let ret = engine.eval_str("return args[1] ++ len(args)").unwrap();
+    assert_eq!(ret, Value::Str("b2".to_owned()));
+    match engine.eval_str("var n: int = args[0]") {
+        Err(EvalError::Check) => (),
+        other => panic!("expected type error, got {:?}", other),
+    }
+}
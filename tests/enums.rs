@@ -0,0 +1,80 @@
+extern crate jazz_ml_vm;
+
+use jazz_ml_vm::{Engine, EvalError};
+use std::process::Command;
+
+const ENUMS: &str = "
+let Color = enum {
+  Red
+  Green
+}
+let Shape = enum {
+  Red
+  Square
+}
+";
+
+fn eval(code: &str) -> String {
+    let mut engine = Engine::new();
+    let ret = engine.eval_str(&format!("{}{}", ENUMS, code)).unwrap();
+    ret.as_str(&engine.vm)
+}
+
+fn error(code: &str) -> EvalError {
+    match Engine::new().eval_str(&format!("{}{}", ENUMS, code)) {
+        Err(err) => err,
+        Ok(ret) => panic!("expected an error, got {:?}", ret),
+    }
+}
+
+// what `jazz-ml-vm check` prints for `code`
+fn check(code: &str) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_jazz-ml-vm"))
+        .args(&["check", "-e", &format!("{}{}", ENUMS, code)])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(6));
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+#[test]
+fn variants_compare_and_print() {
+    assert_eq!(eval("return Color.Red"), "Red");
+    assert_eq!(eval("return Color"), "<enum Color>");
+    assert_eq!(
+        eval("var c: Color = Color.Green\nreturn [c == Color.Green, c == Color.Red]"),
+        "[true, false]"
+    );
+}
+
+#[test]
+fn variants_must_be_identifiers() {
+    match error("let Bad = enum {\n  Red\n  1\n}") {
+        EvalError::Parse => (),
+        other => panic!("expected parse error, got {:?}", other),
+    }
+}
+
+#[test]
+fn declarations_and_uses_are_checked() {
+    let cases = [
+        "let Twice = enum {\n  A\n  A\n}",
+        "var c = Color.Blue",
+        "var s: Shape = Color.Red",
+    ];
+    for code in cases.iter() {
+        match error(code) {
+            EvalError::Check => (),
+            other => panic!("expected type error for {:?}, got {:?}", code, other),
+        }
+    }
+}
+
+#[test]
+fn mismatches_name_the_enums() {
+    assert!(check("var s: Shape = Color.Red")
+        .contains("mismatched types, expected type `Shape` got `Color`"));
+
+    let out = check("var c = Color.Red\nvar n = switch c {\n  Shape.Red => 1\n  _ => 2\n}");
+    assert!(out.contains("mismatched pattern, expected `Color` got `Shape`"), "{}", out);
+}
@@ -0,0 +1,88 @@
+extern crate jazz_ml_vm;
+
+use jazz_ml_vm::value::Value;
+use jazz_ml_vm::{Engine, EvalError};
+
+fn engine() -> Engine {
+    let mut engine = Engine::new();
+    engine.register_native("repeat", |n: i64, s: String| s.repeat(n as usize));
+    engine
+}
+
+#[test]
+fn externs_call_host_natives() {
+    let mut engine = engine();
+    let ret = engine
+        .eval_str("extern repeat: func(int, string) -> string\nreturn repeat(2, \"ab\")")
+        .unwrap();
+    assert_eq!(ret, Value::Str("abab".to_owned()));
+
+    // declarations stay valid for later evaluations
+    let ret = engine
+        .eval_str("extern repeat: func(int, string) -> string\nreturn repeat(1, \"c\")")
+        .unwrap();
+    assert_eq!(ret, Value::Str("c".to_owned()));
+}
+
+#[test]
+fn calls_are_checked_against_the_declaration() {
+    for code in &[
+        "extern repeat: func(int, string) -> string\nrepeat(\"x\", 2)",
+        "extern repeat: func(int, string) -> string\nvar n: int = repeat(1, \"x\")",
+        "extern repeat: int",
+        "let f = func() {\n  extern repeat: func(int, string) -> string\n}",
+    ] {
+        match engine().eval_str(code) {
+            Err(EvalError::Check) => (),
+            other => panic!("expected type error for {:?}, got {:?}", code, other),
+        }
+    }
+}
+
+#[test]
+fn missing_natives_fail_to_link() {
+    for code in &[
+        "extern missing: func(int) -> int",
+        "extern repeat: func(int) -> string",
+        "let f = func() {}\nextern f: func()",
+    ] {
+        match engine().eval_str(code) {
+            Err(EvalError::Link) => (),
+            other => panic!("expected link error for {:?}, got {:?}", code, other),
+        }
+    }
+}
+
+#[test]
+fn signatures_must_match_the_host() {
+    for code in &[
+        "extern repeat: func(string, string) -> string",
+        "extern repeat: func(int, string) -> int",
+        "extern repeat: func(int, [string]) -> string",
+    ] {
+        match engine().eval_str(code) {
+            Err(EvalError::Link) => (),
+            other => panic!("expected link error for {:?}, got {:?}", code, other),
+        }
+    }
+
+    // natives taking any accept whatever the extern declares
+    let mut engine = engine();
+    engine.register_native("show", |v: Value| v == Value::Null);
+    engine
+        .eval_str("extern show: func(int) -> bool
+show(1)")
+        .unwrap();
+}
+
+#[test]
+fn linking_happens_before_running() {
+    let mut engine = engine();
+    let code = "var ran = true\nextern missing: func()";
+
+    match engine.eval_str(code) {
+        Err(EvalError::Link) => (),
+        other => panic!("expected link error, got {:?}", other),
+    }
+    assert!(engine.global("ran").is_none());
+}
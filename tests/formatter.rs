@@ -0,0 +1,64 @@
+extern crate jazz_ml_vm;
+
+use jazz_ml_vm::formatter::format;
+use jazz_ml_vm::EvalError;
+
+fn fmt(code: &str) -> String {
+    format("<test>", code).unwrap()
+}
+
+#[test]
+fn blocks_are_reindented() {
+    let code = "
+let f = func(n: int) : int {
+      if n > 0 {
+ return n
+    } else {
+        return -n
+}
+}
+";
+    assert_eq!(
+        fmt(code),
+        "let f = func(n: int) : int {\n  if n > 0 {\n    return n\n  } else {\n    return -n\n  }\n}\n"
+    );
+}
+
+#[test]
+fn brackets_closed_on_their_line_keep_the_indentation() {
+    let code = "var xs = [\n1, [2, 3],\n  f(4)\n]\nprintln(xs)";
+    assert_eq!(
+        fmt(code),
+        "var xs = [\n  1, [2, 3],\n  f(4)\n]\nprintln(xs)\n"
+    );
+}
+
+#[test]
+fn blank_lines_and_trailing_whitespace_are_dropped() {
+    let code = "\n\nvar x = 1   \n\n\n\nvar y = 2\t\n\n";
+    assert_eq!(fmt(code), "var x = 1\n\nvar y = 2\n");
+}
+
+#[test]
+fn strings_and_comments_are_left_alone() {
+    let code = "# a { comment\nvar s = \"{\n   (kept\"\nif true {\n# }\nprintln(s)\n}";
+    assert_eq!(
+        fmt(code),
+        "# a { comment\nvar s = \"{\n   (kept\"\nif true {\n  # }\n  println(s)\n}\n"
+    );
+}
+
+#[test]
+fn formatting_is_idempotent() {
+    let code = include_str!("../examples/lexer.jml");
+    let once = fmt(code);
+    assert_eq!(fmt(&once), once);
+}
+
+#[test]
+fn code_that_does_not_lex_is_rejected() {
+    match format("<test>", "var x = 1 @ 2") {
+        Err(EvalError::Lex) => (),
+        other => panic!("expected lexer error, got {:?}", other),
+    }
+}
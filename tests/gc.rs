@@ -0,0 +1,103 @@
+extern crate jazz_ml_vm;
+
+mod common;
+
+use common::{compile, global, vm};
+use jazz_ml_vm::value::Value;
+use jazz_ml_vm::Engine;
+use jazz_ml_vm::vm::VirtualMachine;
+
+fn run_with_threshold(code: &str, threshold: usize) -> VirtualMachine {
+    let mut vm = vm();
+    vm.set_gc_threshold(threshold);
+
+    let ins = compile(&mut vm, code);
+    vm.run_instructions(ins).expect("runtime error");
+    vm
+}
+
+#[test]
+fn garbage_is_collected_while_running() {
+    let vm = run_with_threshold(
+        "
+var i = 0
+while i < 1000 {
+  var tmp = [i, i + 1]
+  i = i + 1
+}
+",
+        64,
+    );
+
+    let stats = vm.gc_stats();
+    assert!(stats.collections > 0);
+    assert!(stats.freed > 900, "{:?}", stats);
+    assert!(vm.arrays.len() < 100, "{} arrays alive", vm.arrays.len());
+}
+
+#[test]
+fn reachable_values_survive() {
+    let mut vm = run_with_threshold(
+        "
+let make = func(x: any) : any {
+  var inner = [x]
+  return func() : any { inner }
+}
+var outer = new_obj()
+outer.items = [[1, 2], [3]]
+var keep = make(7)
+var i = 0
+while i < 200 {
+  var tmp = [i]
+  i = i + 1
+}
+",
+        16,
+    );
+    vm.collect();
+
+    let outer = match global(&vm, "outer") {
+        Value::ObjectRef(id) => id,
+        v => panic!("{:?}", v),
+    };
+    assert!(vm.pool.contains_key(&outer));
+    // outer and the `string` module
+    assert_eq!(vm.pool.len(), 2);
+    // items with its two arrays, the captured array and the last `tmp`, top
+    // level variables being globals
+    assert_eq!(vm.arrays.len(), 5);
+    assert_eq!(vm.closures.len(), 1);
+}
+
+#[test]
+fn unreachable_values_are_freed_on_collect() {
+    let mut vm = run_with_threshold("var a = [[1], [2], [3]]", 1 << 20);
+    assert_eq!(vm.arrays.len(), 4);
+
+    vm.globals.insert(Value::Str("a".into()), Value::Null);
+    assert_eq!(vm.collect(), 4);
+    assert!(vm.arrays.is_empty());
+    // the `string` module
+    assert_eq!(vm.gc_stats().live, 1);
+}
+
+#[test]
+fn values_returned_to_the_host_survive_later_runs() {
+    let mut engine = Engine::new();
+    engine.vm.set_gc_threshold(16);
+
+    let kept = engine.eval_str("return [[1, 2], [3, 4]]").unwrap();
+    engine
+        .eval_str("var i = 0\nwhile i < 200 {\n  var tmp = [i]\n  i = i + 1\n}")
+        .unwrap();
+    assert!(engine.vm.gc_stats().collections > 0);
+    assert_eq!(kept.as_str(&engine.vm), "[[1, 2], [3, 4]]");
+
+    let id = match *kept {
+        Value::Array(id) => id,
+        ref v => panic!("{:?}", v),
+    };
+    drop(kept);
+    engine.vm.collect();
+    assert!(engine.vm.get_array(&id).is_err());
+}
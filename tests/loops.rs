@@ -0,0 +1,182 @@
+extern crate jazz_ml_vm;
+
+mod common;
+
+use common::{compile, global, run, vm};
+use jazz_ml_vm::{Engine, EvalError};
+
+fn show(code: &str) -> String {
+    let vm = run(code);
+    global(&vm, "result").as_str(&vm)
+}
+
+fn error(code: &str) -> String {
+    let mut vm = vm();
+    let ins = compile(&mut vm, code);
+    vm.run_instructions(ins).unwrap_err().message
+}
+
+#[test]
+fn for_loops_over_arrays() {
+    let code = "
+var result = []
+for x in [1, 2, 3] {
+  array_push(result, x * 10)
+}
+for i, x in [\"a\", \"b\"] {
+  array_push(result, x ++ i)
+}
+";
+    assert_eq!(show(code), "[10, 20, 30, a0, b1]");
+}
+
+#[test]
+fn for_loops_over_ranges() {
+    let code = "
+var result = []
+var n = 3
+for i in 0..n {
+  array_push(result, i)
+}
+for i in n..0 {
+  array_push(result, i)
+}
+for i, x in 5..7 {
+  array_push(result, [i, x])
+}
+";
+    assert_eq!(show(code), "[0, 1, 2, [0, 5], [1, 6]]");
+}
+
+#[test]
+fn for_loops_over_strings_and_maps() {
+    let code = "
+var result = []
+for c in \"héy\" {
+  array_push(result, c)
+}
+var m = [\"k\": 1]
+for k in m {
+  array_push(result, k)
+}
+for k, v in m {
+  array_push(result, v)
+}
+";
+    assert_eq!(show(code), "[h, é, y, k, 1]");
+}
+
+#[test]
+fn break_and_continue_in_for_loops() {
+    let code = "
+var result = []
+for x in 0..10 {
+  if x == 1 { continue }
+  if x == 4 { break }
+  array_push(result, x)
+}
+var pairs = 0
+for i in 0..3 {
+  for j in [0, 1, 2] {
+    if j == 1 { continue }
+    pairs = pairs + 1
+  }
+}
+array_push(result, pairs)
+";
+    assert_eq!(show(code), "[0, 2, 3, 6]");
+}
+
+#[test]
+fn for_loops_in_functions() {
+    let code = "
+let sum = func(xs: any) : any {
+  var total = 0
+  for x in xs {
+    total = total + x
+  }
+  return total
+}
+var result = sum([1, 2, 3]) + sum(1..5)
+";
+    assert_eq!(show(code), "16");
+}
+
+#[test]
+fn iterating_over_other_values_fails() {
+    assert_eq!(error("var x = 1\nfor y in x {}"), "Can't iterate over `1`");
+
+    match Engine::new().eval_str("for x in 1 {}") {
+        Err(EvalError::Check) => (),
+        other => panic!("expected type error, got {:?}", other),
+    }
+}
+
+#[test]
+fn break_in_nested_while_loops() {
+    let code = "
+var hits = 0
+var i = 0
+while i < 5 {
+  var j = 0
+  while true {
+    if j == 2 { break }
+    j = j + 1
+  }
+  i = i + 1
+  if i == 2 { continue }
+  if i == 4 { break }
+  hits = hits + j
+}
+var result = [hits, i]
+";
+    assert_eq!(show(code), "[4, 4]");
+}
+
+#[test]
+fn labeled_break_and_continue() {
+    let code = "
+var result = []
+outer: for i in 0..4 {
+  var j = 0
+  while j < 4 {
+    j = j + 1
+    if j == 2 { continue outer }
+    if i == 3 { break outer }
+    array_push(result, [i, j])
+  }
+}
+";
+    assert_eq!(show(code), "[[0, 1], [1, 1], [2, 1]]");
+}
+
+#[test]
+fn loops_in_typed_functions() {
+    let code = "
+let first_even = func(xs: [int]) : int {
+  for x in xs {
+    if x % 2 == 1 { continue }
+    return x
+  }
+  return -1
+}
+var result = [first_even([1, 3, 4, 6]), first_even([1])]
+";
+    assert_eq!(show(code), "[4, -1]");
+}
+
+#[test]
+fn jumps_are_checked() {
+    for code in &[
+        "break",
+        "while true { let f = func() { continue } }",
+        "a: while true { for x in [1] { break b } }",
+        "a: var x = 1",
+        "let f = func() : int { while true { return \"s\" }\nreturn 1 }",
+    ] {
+        match Engine::new().eval_str(code) {
+            Err(EvalError::Check) | Err(EvalError::Parse) => (),
+            other => panic!("expected error for {:?}, got {:?}", code, other),
+        }
+    }
+}
@@ -0,0 +1,146 @@
+extern crate jazz_ml_vm;
+
+use jazz_ml_vm::codegen::Compiler;
+use jazz_ml_vm::lexer::Pos;
+use jazz_ml_vm::parser::{Expression, ExpressionNode, Statement, StatementNode};
+use jazz_ml_vm::value::Value;
+use jazz_ml_vm::visitor::{Type, TypeNode};
+use jazz_ml_vm::vm::VirtualMachine;
+use jazz_ml_vm::{Engine, EvalError};
+use std::rc::Rc;
+
+fn eval(code: &str) -> String {
+    let mut engine = Engine::new();
+    let ret = engine.eval_str(code).unwrap();
+    ret.as_str(&engine.vm)
+}
+
+fn check_error(code: &str) {
+    match Engine::new().eval_str(code) {
+        Err(EvalError::Check) => (),
+        other => panic!("expected type error, got {:?}", other),
+    }
+}
+
+#[test]
+fn maps_print_their_entries() {
+    assert_eq!(eval("return [\"b\": 2, \"a\": 1]"), "[a: 1, b: 2]");
+    assert_eq!(eval("return [:]"), "[:]");
+    assert_eq!(
+        eval("return [\n  1: [true],\n  2: [false],\n]"),
+        "[1: [true], 2: [false]]"
+    );
+}
+
+#[test]
+fn keys_can_be_any_value() {
+    let code = "
+var m: [any: string] = [:]
+m[1] = \"int\"
+m[1.5] = \"float\"
+m[true] = \"bool\"
+m[\"1\"] = \"string\"
+return [m[1], m[1.5], m[true], m[\"1\"]]
+";
+    assert_eq!(eval(code), "[int, float, bool, string]");
+    assert_eq!(eval("var m = [\"a\": 1]\nreturn m[\"b\"]"), "null");
+}
+
+// the parser names fields by identifiers, other expressions are keys
+#[test]
+fn fields_named_by_expressions_compile() {
+    let pos = Pos((1, String::new()), (0, 0));
+    let expr = |node: ExpressionNode| Expression::new(node, pos.clone());
+    let field = || {
+        expr(ExpressionNode::Index(
+            Rc::new(expr(ExpressionNode::Identifier("obj".into()))),
+            Rc::new(expr(ExpressionNode::Str("a".into()))),
+            false,
+        ))
+    };
+    let new_obj = expr(ExpressionNode::Call(
+        Rc::new(expr(ExpressionNode::Identifier("new_obj".into()))),
+        vec![],
+    ));
+    let ast = vec![
+        Statement::new(
+            StatementNode::Variable(true, Type::from(TypeNode::Nil), "obj".into(), Some(new_obj)),
+            pos.clone(),
+        ),
+        Statement::new(
+            StatementNode::Assignment(field(), expr(ExpressionNode::Int(1))),
+            pos.clone(),
+        ),
+        Statement::new(StatementNode::Return(Some(Rc::new(field()))), pos.clone()),
+    ];
+
+    let mut vm = VirtualMachine::new();
+    vm.init_builtins();
+    let mut compiler = Compiler::new(&mut vm);
+    compiler.compile(ast);
+    let ins = compiler.finish();
+
+    assert_eq!(vm.run_instructions(ins).unwrap(), Value::Int(1));
+}
+
+#[test]
+fn entries_are_assigned_in_place() {
+    let code = "
+var m = [\"a\": 1]
+var alias = m
+alias[\"a\"] = 10
+alias[\"b\"] = 20
+return m
+";
+    assert_eq!(eval(code), "[a: 10, b: 20]");
+}
+
+#[test]
+fn builtins_work_on_maps() {
+    let code = "
+var m = [\"a\": 1, \"b\": 2, \"c\": 3]
+var removed = remove(m, \"b\")
+return [removed, remove(m, \"x\"), has(m, \"a\"), has(m, \"b\"), len(m)]
+";
+    assert_eq!(eval(code), "[2, null, true, false, 2]");
+
+    let mut engine = Engine::new();
+    engine
+        .eval_str("var m = [1: 10, 2: 20]\nvar ks = keys(m)\nvar vs = values(m)")
+        .unwrap();
+
+    let pairs = |name: &str| match engine.global(name) {
+        Some(Value::Array(id)) => engine.vm.get_array(id).unwrap().vec.borrow().clone(),
+        other => panic!("{:?}", other),
+    };
+    let (ks, vs) = (pairs("ks"), pairs("vs"));
+    assert_eq!(ks.len(), 2);
+    for (k, v) in ks.iter().zip(vs.iter()) {
+        match (k, v) {
+            (Value::Int(k), Value::Int(v)) => assert_eq!(k * 10, *v),
+            other => panic!("{:?}", other),
+        }
+    }
+}
+
+#[test]
+fn len_works_on_every_collection() {
+    assert_eq!(
+        eval("return [len([1, 2]), len(\"héllo\"), len([:])]"),
+        "[2, 5, 0]"
+    );
+}
+
+#[test]
+fn usage_is_type_checked() {
+    check_error("var m = [\"a\": 1, 2: 3]");
+    check_error("var m = [\"a\": 1, \"b\": \"c\"]");
+    check_error("var m = [\"a\": 1]\nm[1]");
+    check_error("var m = [\"a\": 1]\nvar s: string = m[\"a\"]");
+    check_error("var m = [\"a\": 1]\nm[\"b\"] = true");
+    check_error("var m = [\"a\": 1]\nm.a");
+    check_error("var m: [string: int] = [1: 1]");
+    check_error("var ks = keys([1, 2])");
+    check_error("var found = has(\"ab\", \"a\")");
+    check_error("let P = struct {\n  x: int\n}\nvar old = remove(new P { x: 1 }, \"x\")");
+}
@@ -0,0 +1,126 @@
+extern crate jazz_ml_vm;
+
+use jazz_ml_vm::{Engine, EvalError};
+use std::path::Path;
+
+fn fixture(name: &str) -> String {
+    format!("{}/tests/modules/{}", env!("CARGO_MANIFEST_DIR"), name)
+}
+
+fn import_error(engine: &mut Engine, code: &str) -> EvalError {
+    engine.eval_str(code).unwrap_err()
+}
+
+#[test]
+fn modules_are_loaded_once() {
+    let mut engine = Engine::new();
+    engine.eval_file(&fixture("main.jml")).unwrap();
+
+    // both imports of the counter share its state
+    let result = engine.global("result").unwrap().as_str(&engine.vm);
+    assert_eq!(result, "[9, 12, 13, 10]");
+}
+
+#[test]
+fn module_globals_are_namespaced() {
+    let mut engine = Engine::new();
+    engine.eval_file(&fixture("main.jml")).unwrap();
+
+    assert!(engine.global("bump").is_none());
+    assert!(engine.global("_count").is_none());
+    assert!(engine.global("counter").is_some());
+}
+
+#[test]
+fn exports_show_the_current_module_state() {
+    let mut engine = Engine::new();
+    engine.add_search_path(fixture("lib"));
+
+    let code = "
+import \"log.jml\" as log
+var before = log.count
+log.log(\"one\")
+log.log(\"two\")
+return [\"\" ++ before, \"\" ++ log.count, log.last]
+";
+    let ret = engine.eval_str(code).unwrap();
+    assert_eq!(ret.as_str(&engine.vm), "[0, 2, two]");
+
+    // a later import sees the same state
+    let ret = engine
+        .eval_str("import \"log.jml\" as l\nl.log(\"three\")\nreturn l.count")
+        .unwrap();
+    assert_eq!(ret.as_str(&engine.vm), "3");
+}
+
+#[test]
+fn imports_use_the_search_path() {
+    let mut engine = Engine::new();
+    engine.add_search_path(fixture("vendor"));
+
+    let ret = engine
+        .eval_str("import \"greet.jml\" as g\nreturn g.greet(\"you\")")
+        .unwrap();
+    assert_eq!(ret.as_str(&engine.vm), "hello you");
+}
+
+#[test]
+fn runtime_errors_name_the_file() {
+    let mut engine = Engine::new();
+    engine.add_search_path(fixture("lib"));
+
+    let code = "import \"pick.jml\" as p\np.pick([1], 4)";
+    let err = match engine.eval_str(code) {
+        Err(EvalError::Runtime(err)) => err,
+        other => panic!("expected runtime error, got {:?}", other),
+    };
+
+    let files = err.trace.iter().map(|(_, loc)| {
+        let loc = loc.as_ref().unwrap();
+        let file = Path::new(&loc.file).file_name().unwrap();
+        format!(
+            "{}:{}:{}",
+            file.to_str().unwrap(),
+            (loc.pos.0).0,
+            (loc.pos.1).0
+        )
+    });
+    assert_eq!(
+        files.collect::<Vec<_>>(),
+        vec!["pick.jml:2:10", "<eval>:2:1"]
+    );
+    assert!(format!("{}", err).contains("pick.jml:2:10"), "{}", err);
+}
+
+#[test]
+fn bad_imports_are_reported() {
+    let mut engine = Engine::new();
+    engine.add_search_path(fixture(""));
+
+    match import_error(&mut engine, "import \"missing.jml\" as m") {
+        EvalError::Import => (),
+        other => panic!("expected import error, got {:?}", other),
+    }
+    match import_error(&mut engine, "import \"cycle_a.jml\" as a") {
+        EvalError::Import => (),
+        other => panic!("expected import error, got {:?}", other),
+    }
+}
+
+#[test]
+fn module_members_are_type_checked() {
+    let mut engine = Engine::new();
+    engine.add_search_path(fixture("lib"));
+
+    for code in &[
+        "import \"counter.jml\" as c\nc._count",
+        "import \"counter.jml\" as c\nc.missing()",
+        "import \"counter.jml\" as c\nvar s: string = c.bump()",
+        "let f = func() {\n  import \"counter.jml\" as c\n}",
+    ] {
+        match engine.eval_str(code) {
+            Err(EvalError::Check) => (),
+            other => panic!("expected type error for {:?}, got {:?}", code, other),
+        }
+    }
+}
@@ -0,0 +1,113 @@
+extern crate jazz_ml_vm;
+
+use jazz_ml_vm::{Engine, EvalError};
+
+fn eval(code: &str) -> String {
+    let mut engine = Engine::new();
+    let ret = engine.eval_str(code).unwrap();
+    ret.as_str(&engine.vm)
+}
+
+fn runtime_error(code: &str) -> String {
+    match Engine::new().eval_str(code) {
+        Err(EvalError::Runtime(err)) => err.message,
+        other => panic!("expected runtime error, got {:?}", other),
+    }
+}
+
+#[test]
+fn and_or_short_circuit() {
+    let code = "
+var calls = 0
+let hit = func(b: bool) : bool {
+  calls = calls + 1
+  return b
+}
+var skipped = [false and hit(true), true or hit(false)]
+var before = calls
+var taken = [true and hit(false), false or hit(true), true and hit(true)]
+";
+    let mut engine = Engine::new();
+    engine.eval_str(code).unwrap();
+
+    let show = |name: &str| engine.global(name).unwrap().as_str(&engine.vm);
+    assert_eq!(show("skipped"), "[false, true]");
+    assert_eq!(show("before"), "0");
+    assert_eq!(show("taken"), "[false, true, true]");
+    assert_eq!(show("calls"), "3");
+}
+
+#[test]
+fn and_or_truth_tables() {
+    assert_eq!(
+        eval("return [true and true, true and false, false and true, false and false]"),
+        "[true, false, false, false]"
+    );
+    assert_eq!(
+        eval("return [true or true, true or false, false or true, false or false]"),
+        "[true, true, true, false]"
+    );
+    // the right side would index out of bounds
+    assert_eq!(
+        eval("var xs = [1]\nvar i = 1\nreturn i < array_len(xs) and xs[i] == 1"),
+        "false"
+    );
+}
+
+#[test]
+fn comparisons() {
+    assert_eq!(
+        eval("return [1 <= 1, 2 <= 1, 1.5 <= 2.0, 2 >= 2, 1 >= 2, 2.5 >= 2.5]"),
+        "[true, false, true, true, false, true]"
+    );
+    assert_eq!(
+        eval("return [\"a\" <= \"b\", \"a\" >= \"b\", \"b\" > \"a\", \"b\" < \"a\"]"),
+        "[true, false, true, false]"
+    );
+    assert_eq!(
+        eval("var t = true\nreturn [t == true, t == false, t != false]"),
+        "[true, false, true]"
+    );
+}
+
+#[test]
+fn powers() {
+    assert_eq!(eval("return 2 ^ 10"), "1024");
+    assert_eq!(eval("return 3 ^ 0"), "1");
+    assert_eq!(eval("return 2.0 ^ 0.5"), "1.4142135623730951");
+    assert_eq!(eval("return 2 * 3 ^ 2"), "18");
+
+    assert_eq!(
+        runtime_error("var x = 2 ^ -1"),
+        "Can't apply `^` to `2` and `-1`"
+    );
+    assert_eq!(runtime_error("var x = 2 ^ 64"), "Overflow in `2 ^ 64`");
+}
+
+#[test]
+fn unary_operators() {
+    assert_eq!(eval("var x = 4\nreturn [-x, -(x + 1), -2]"), "[-4, -5, -2]");
+    assert_eq!(
+        eval("var f = 1.5\nreturn [-f, -(f * 2.0), -2.5]"),
+        "[-1.5, -3, -2.5]"
+    );
+    assert_eq!(
+        eval("var t = true\nreturn [not t, not (1 > 2), not not t]"),
+        "[false, true, true]"
+    );
+}
+
+#[test]
+fn operand_types_are_checked() {
+    for code in &[
+        "var x = not 1",
+        "var x = -\"a\"",
+        "var x = 1 and true",
+        "var x = \"a\" ^ 2",
+    ] {
+        match Engine::new().eval_str(code) {
+            Err(EvalError::Check) => (),
+            other => panic!("expected type error for {:?}, got {:?}", code, other),
+        }
+    }
+}
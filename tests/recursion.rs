@@ -0,0 +1,106 @@
+extern crate jazz_ml_vm;
+
+use jazz_ml_vm::codegen::Compiler;
+use jazz_ml_vm::lexer::*;
+use jazz_ml_vm::parser::*;
+use jazz_ml_vm::source::*;
+use jazz_ml_vm::value::Value;
+use jazz_ml_vm::vm::VirtualMachine;
+
+fn run(code: &str) -> VirtualMachine {
+    let source = Source::from("<test>", code.lines().map(|x| x.into()).collect());
+    let tokens = Lexer::default(code.chars().collect(), &source)
+        .map(|token| token.expect("lexing failed"))
+        .collect();
+    let ast = Parser::new(tokens, &source)
+        .parse()
+        .expect("parsing failed");
+
+    let mut vm = VirtualMachine::new();
+    vm.init_builtins();
+    let ins = {
+        let mut compiler = Compiler::new(&mut vm);
+        compiler.compile(ast);
+        compiler.finish()
+    };
+    vm.run_instructions(ins).expect("runtime error");
+    vm
+}
+
+fn global(vm: &VirtualMachine, name: &str) -> Value {
+    vm.globals
+        .get(&Value::Str(name.to_owned()))
+        .expect("no such global")
+        .clone()
+}
+
+#[test]
+fn fibonacci() {
+    let vm = run("
+let fib = func(a: int) : int {
+  if a < 2 {
+    return a
+  }
+  return fib(a - 1) + fib(a - 2)
+}
+var result = fib(15)
+");
+
+    assert_eq!(global(&vm, "result"), Value::Int(610));
+}
+
+#[test]
+fn callee_locals_are_isolated() {
+    let vm = run("
+let fact = func(n: int) : int {
+  var m = n
+  if n < 2 {
+    return 1
+  }
+  var rest = fact(n - 1)
+  return m * rest
+}
+var result = fact(6)
+");
+
+    assert_eq!(global(&vm, "result"), Value::Int(720));
+}
+
+#[test]
+fn mutual_recursion() {
+    let vm = run("
+let is_even = func(n: int) : bool {
+  if n == 0 {
+    return true
+  }
+  return is_odd(n - 1)
+}
+let is_odd = func(n: int) : bool {
+  if n == 0 {
+    return false
+  }
+  return is_even(n - 1)
+}
+var even = is_even(10)
+var odd = is_odd(7)
+");
+
+    assert_eq!(global(&vm, "even"), Value::Bool(true));
+    assert_eq!(global(&vm, "odd"), Value::Bool(true));
+}
+
+#[test]
+fn call_stack_unwinds() {
+    let vm = run("
+let count = func(n: int) : int {
+  if n == 0 {
+    return 0
+  }
+  return 1 + count(n - 1)
+}
+var result = count(200)
+");
+
+    assert_eq!(global(&vm, "result"), Value::Int(200));
+    assert!(vm.frames.is_empty());
+}
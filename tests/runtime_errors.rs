@@ -0,0 +1,185 @@
+extern crate jazz_ml_vm;
+
+mod common;
+
+use common::{compile, vm};
+use jazz_ml_vm::error::{Location, RuntimeError};
+use jazz_ml_vm::opcodes::Opcode;
+use jazz_ml_vm::value::Value;
+use jazz_ml_vm::vm::VirtualMachine;
+
+fn fail(vm: &mut VirtualMachine, code: &str) -> RuntimeError {
+    let ins = compile(vm, code);
+    match vm.run_instructions(ins) {
+        Ok(value) => panic!("expected an error, got `{}`", value.as_str(vm)),
+        Err(err) => err,
+    }
+}
+
+#[test]
+fn error_carries_opcode_and_trace() {
+    let mut vm = vm();
+    let err = fail(
+        &mut vm,
+        "
+let inner = func(x: any) : any {
+    x(1)
+}
+
+let outer = func(x: any) : any {
+    inner(x)
+    0
+}
+
+outer(3)
+",
+    );
+
+    assert_eq!(format!("{:?}", err.opcode), "Some(TailCall(1))");
+    let names = err.trace.iter().map(|(name, _)| name.as_str());
+    assert_eq!(
+        names.collect::<Vec<_>>(),
+        vec!["inner", "outer", "<top level>"]
+    );
+    assert!(err.message.contains("3"));
+}
+
+#[test]
+fn error_points_at_source_lines() {
+    let mut vm = vm();
+    let err = fail(
+        &mut vm,
+        "
+let inner = func(x: any) : any {
+    x(1)
+}
+
+let outer = func(x: any) : any {
+    inner(x)
+    0
+}
+
+outer(3)
+",
+    );
+
+    let line = |loc: &Option<Location>| loc.as_ref().map(|loc| (loc.pos.0).0);
+    assert_eq!(line(&err.pos), Some(3));
+    assert_eq!(
+        err.trace
+            .iter()
+            .map(|(_, pos)| line(pos))
+            .collect::<Vec<_>>(),
+        vec![Some(3), Some(7), Some(11)]
+    );
+}
+
+#[test]
+fn arity_mismatch_is_reported() {
+    let mut vm = vm();
+    let err = fail(
+        &mut vm,
+        "
+let add = func(a: int, b: int) : int {
+    a + b
+}
+
+add(1)
+",
+    );
+
+    assert!(err.message.contains("`add` expected 2"), "{}", err.message);
+}
+
+#[test]
+fn native_errors_propagate() {
+    let mut vm = vm();
+    let err = fail(&mut vm, "var x = 1 + new_obj()");
+
+    assert_eq!(err.trace.len(), 1);
+    assert_eq!(err.trace[0].0, "<top level>");
+    assert!(err.message.starts_with("Can't"), "{}", err.message);
+}
+
+#[test]
+fn machine_is_reusable_after_error() {
+    let mut vm = vm();
+    fail(
+        &mut vm,
+        "
+let boom = func() : int {
+    1 - \"nope\"
+}
+
+boom()
+",
+    );
+    assert!(vm.frames.is_empty());
+
+    let ins = compile(&mut vm, "var y = 2 * 21");
+    vm.run_instructions(ins).expect("runtime error");
+    assert_eq!(
+        vm.globals.get(&Value::Str("y".into())),
+        Some(&Value::Int(42))
+    );
+}
+
+#[test]
+fn division_by_zero_is_an_error() {
+    let mut vm = vm();
+    for code in ["var x = 1 / 0", "var x = 1 % 0", "var zero = 0\nvar x = 7 / zero"].iter() {
+        let err = fail(&mut vm, code);
+        assert!(err.message.starts_with("Division by zero"), "{}", err.message);
+    }
+
+    let err = fail(&mut vm, "var zero = 0\nvar x = 7 % zero");
+    assert_eq!(err.message, "Division by zero in `7 % 0`");
+}
+
+#[test]
+fn integer_overflow_is_an_error() {
+    let mut vm = vm();
+    let cases = [
+        "var max = (half - 1) + half\nvar x = max + 1",
+        "var min = (0 - half) - half\nvar x = min - 1",
+        "var x = half * 2",
+        "var min = (0 - half) - half\nvar x = min / (0 - 1)",
+        "var min = (0 - half) - half\nvar x = -min",
+    ];
+    for code in cases.iter() {
+        let err = fail(&mut vm, &format!("var half = 2 ^ 62\n{}", code));
+        assert!(err.message.starts_with("Integer overflow"), "{}", err.message);
+    }
+    assert!(vm.frames.is_empty());
+}
+
+#[test]
+fn method_calls_check_their_argument_count() {
+    let mut vm = vm();
+    let ins = compile(
+        &mut vm,
+        "var obj = new_obj()\nobj.add = func(a: int, b: int) : int { return a + b }",
+    );
+    vm.run_instructions(ins).expect("runtime error");
+
+    // `obj.add(1)`, calls on fields compile to `CallObj`
+    let call = |argc: usize| {
+        let mut ins = (0..argc as i64).map(Opcode::PushInt).collect::<Vec<_>>();
+        ins.extend(vec![
+            Opcode::PushStr("obj".into()),
+            Opcode::LoadGlobal,
+            Opcode::PushStr("add".into()),
+            Opcode::CallObj(argc),
+            Opcode::Ret,
+        ]);
+        ins
+    };
+    for argc in [1, 3].iter() {
+        let err = vm.run_instructions(call(*argc)).unwrap_err();
+        assert_eq!(
+            err.message,
+            format!("`<lambda>` expected 2 argument(s) found {}", argc)
+        );
+    }
+    assert_eq!(vm.run_instructions(call(2)).unwrap(), Value::Int(1));
+}
@@ -0,0 +1,126 @@
+extern crate jazz_ml_vm;
+
+use jazz_ml_vm::stdlib::NativeModule;
+use jazz_ml_vm::value::Value;
+use jazz_ml_vm::{Engine, EvalError};
+
+fn eval(code: &str) -> String {
+    let mut engine = Engine::new();
+    let ret = engine.eval_str(code).unwrap();
+    ret.as_str(&engine.vm)
+}
+
+fn runtime_error(code: &str) -> String {
+    match Engine::new().eval_str(code) {
+        Err(EvalError::Runtime(err)) => err.message,
+        other => panic!("expected runtime error, got {:?}", other),
+    }
+}
+
+#[test]
+fn searching_and_slicing() {
+    assert_eq!(eval("return string.len(\"héllo\")"), "5");
+    assert_eq!(eval("return string.substr(\"héllo\", 1, -1)"), "éll");
+    assert_eq!(eval("return string.find(\"héllo\", \"l\")"), "2");
+    assert_eq!(eval("return string.find(\"hello\", \"x\")"), "-1");
+    assert_eq!(eval("return string.contains(\"hello\", \"ell\")"), "true");
+    assert_eq!(eval("return string.starts_with(\"hello\", \"he\")"), "true");
+    assert_eq!(eval("return string.ends_with(\"hello\", \"he\")"), "false");
+}
+
+#[test]
+fn transforming() {
+    assert_eq!(
+        eval("return string.split(\"a,b,,c\", \",\")"),
+        "[a, b, , c]"
+    );
+    assert_eq!(eval("return string.join([\"a\", \"b\"], \", \")"), "a, b");
+    assert_eq!(eval("return string.trim(\" \\t x \")"), "x");
+    assert_eq!(
+        eval("return string.replace(\"aXbX\", \"X\", \"-\")"),
+        "a-b-"
+    );
+    assert_eq!(
+        eval("return string.to_upper(\"abc\") ++ string.to_lower(\"DEF\")"),
+        "ABCdef"
+    );
+    assert_eq!(eval("return string.repeat(\"ab\", 3)"), "ababab");
+}
+
+#[test]
+fn characters() {
+    assert_eq!(eval("return string.char_code(\"a\")"), "97");
+    assert_eq!(eval("return string.from_char_code(65)"), "A");
+    assert_eq!(eval("return string.is_digit(\"042\")"), "true");
+    assert_eq!(eval("return string.is_digit(\"\")"), "false");
+    assert_eq!(eval("return string.is_alpha(\"ab1\")"), "false");
+    assert_eq!(eval("return string.is_alnum(\"ab1\")"), "true");
+    assert_eq!(eval("return string.is_space(\" \\n\")"), "true");
+
+    assert_eq!(
+        runtime_error("string.char_code(\"ab\")"),
+        "Expected a single character, found `ab`"
+    );
+    // 2^32 + 65 would be `A` if truncated to 32 bits
+    assert_eq!(
+        runtime_error("string.from_char_code(4294967361)"),
+        "Invalid character code 4294967361"
+    );
+    assert_eq!(
+        runtime_error("string.from_char_code(0 - 1)"),
+        "Invalid character code -1"
+    );
+}
+
+#[test]
+fn parsing_numbers() {
+    assert_eq!(eval("return string.parse_int(\" 41 \") + 1"), "42");
+    assert_eq!(eval("return string.parse_float(\"2.5\")"), "2.5");
+
+    assert_eq!(eval("return string.parse_int(\"4x\")"), "null");
+    assert_eq!(eval("return string.parse_int(\"99999999999999999999\")"), "null");
+    assert_eq!(eval("return string.parse_float(\"\")"), "null");
+}
+
+#[test]
+fn formatting() {
+    assert_eq!(
+        eval("return string.format(\"{} + {} = {2}\", 1, 2.5, [3])"),
+        "1 + 2.5 = [3]"
+    );
+    assert_eq!(eval("return string.format(\"{{{}}}\", true)"), "{true}");
+    assert_eq!(eval("return \"n = \" ++ 4"), "n = 4");
+
+    assert_eq!(
+        runtime_error("string.format(\"{} {}\", 1)"),
+        "No argument 1 for format string, 1 given"
+    );
+}
+
+#[test]
+fn signatures_are_type_checked() {
+    let mut engine = Engine::new();
+
+    match engine.eval_str("string.len(1)") {
+        Err(EvalError::Check) => (),
+        other => panic!("expected type error, got {:?}", other),
+    }
+    match engine.eval_str("let n: int = string.substr(\"abc\", 0, 1)") {
+        Err(EvalError::Check) => (),
+        other => panic!("expected type error, got {:?}", other),
+    }
+}
+
+#[test]
+fn hosts_register_modules() {
+    let mut module = NativeModule::new("math");
+    module.native("double", |x: i64| x * 2);
+
+    let mut engine = Engine::new();
+    engine.register_module(module);
+
+    assert_eq!(
+        engine.eval_str("return math.double(21)").unwrap(),
+        Value::Int(42)
+    );
+}
@@ -0,0 +1,93 @@
+extern crate jazz_ml_vm;
+
+use jazz_ml_vm::{Engine, EvalError};
+
+fn eval(code: &str) -> String {
+    let mut engine = Engine::new();
+    let ret = engine.eval_str(code).unwrap();
+    ret.as_str(&engine.vm)
+}
+
+fn error(code: &str) -> EvalError {
+    match Engine::new().eval_str(code) {
+        Err(err) => err,
+        Ok(ret) => panic!("expected an error, got {:?}", ret),
+    }
+}
+
+const TOKEN: &str = "
+let Token = struct {
+  kind: string
+  lexeme: string = \"\"
+  line: int = 1
+}
+";
+
+#[test]
+fn missing_fields_take_their_defaults() {
+    let code = format!(
+        "{}var token = new Token {{ kind: \"name\", lexeme: \"foo\" }}\ntoken.line = token.line + 1\nreturn token.kind ++ \" \" ++ token.lexeme ++ \" \" ++ token.line",
+        TOKEN
+    );
+    assert_eq!(eval(&code), "name foo 2");
+}
+
+#[test]
+fn defaults_are_fresh_for_every_instance() {
+    let code = "
+let Bag = struct {
+  items: [int] = []
+  tags: [string: int] = [:]
+}
+var a = new Bag {}
+var b = new Bag {}
+array_push(a.items, 1)
+a.tags[\"x\"] = 1
+return [len(a.items), len(b.items), len(b.tags)]
+";
+    assert_eq!(eval(code), "[1, 0, 0]");
+}
+
+#[test]
+fn defaults_see_the_variables_around_the_struct() {
+    let code = "
+var start = 10
+let Counter = struct {
+  count: int = start * 2
+}
+start = 0
+return new Counter {}.count
+";
+    assert_eq!(eval(code), "0");
+}
+
+#[test]
+fn malformed_declarations_dont_parse() {
+    match error("let Token = struct { kind string }") {
+        EvalError::Parse => (),
+        other => panic!("expected parse error, got {:?}", other),
+    }
+}
+
+#[test]
+fn field_types_are_checked() {
+    let wrong_field = format!("{}var t = new Token {{ kind: 1 }}", TOKEN);
+    let wrong_default = "let T = struct {\n  n: int = \"one\"\n}";
+    for code in [wrong_field.as_str(), wrong_default].iter() {
+        match error(code) {
+            EvalError::Check => (),
+            other => panic!("expected type error, got {:?}", other),
+        }
+    }
+}
+
+#[test]
+fn bad_initializations_are_runtime_errors() {
+    let code = format!("{}var t = new Token {{ lexeme: \"x\" }}", TOKEN);
+    match error(&code) {
+        EvalError::Runtime(err) => {
+            assert_eq!(err.message, "Missing field `kind` in initialization of `Token`")
+        }
+        other => panic!("expected runtime error, got {:?}", other),
+    }
+}
@@ -0,0 +1,123 @@
+extern crate jazz_ml_vm;
+
+use jazz_ml_vm::{Engine, EvalError};
+
+const ENUMS: &str = "
+let Color = enum {
+  Red
+  Green
+}
+let Shape = enum {
+  Red
+  Square
+}
+";
+
+fn eval(code: &str) -> String {
+    let mut engine = Engine::new();
+    let ret = engine.eval_str(&format!("{}{}", ENUMS, code)).unwrap();
+    ret.as_str(&engine.vm)
+}
+
+fn error(code: &str) -> EvalError {
+    match Engine::new().eval_str(&format!("{}{}", ENUMS, code)) {
+        Err(err) => err,
+        Ok(ret) => panic!("expected an error, got {:?}", ret),
+    }
+}
+
+#[test]
+fn literals_pick_their_arm() {
+    let code = "
+let name = func(n: int) : string {
+  return switch n {
+    1 => \"one\"
+    2 => \"two\"
+    _ => \"many\"
+  }
+}
+return [name(1), name(2), name(3)]
+";
+    assert_eq!(eval(code), "[one, two, many]");
+}
+
+#[test]
+fn int_arms_match_equal_floats() {
+    let code = "
+let name = func(x: float) : string {
+  return switch x {
+    1 => \"one\"
+    _ => \"other\"
+  }
+}
+return [name(1.0), name(1.5)]
+";
+    assert_eq!(eval(code), "[one, other]");
+}
+
+#[test]
+fn variants_only_match_their_own_enum() {
+    let code = "
+let name = func(c: any) : string {
+  return switch c {
+    Color.Red => \"red\"
+    Color.Green => \"green\"
+    _ => \"other\"
+  }
+}
+return [name(Color.Red), name(Shape.Red), name(Color.Green)]
+";
+    assert_eq!(eval(code), "[red, other, green]");
+}
+
+#[test]
+fn patterns_destructure_and_bind() {
+    let code = "
+let Point = struct {
+  x: int
+  y: int
+}
+let describe = func(p: Point) : string {
+  return switch p {
+    Point { x: 0, y } => \"on y at \" ++ y
+    other => \"at \" ++ other.x
+  }
+}
+var pairs = switch [1, 2] {
+  [a, b] => a + b
+  _ => 0
+}
+return [describe(new Point { x: 0, y: 3 }), describe(new Point { x: 4, y: 0 }), \"\" ++ pairs]
+";
+    assert_eq!(eval(code), "[on y at 3, at 4, 3]");
+}
+
+#[test]
+fn arms_need_an_arrow() {
+    match error("var n = switch 1 {\n  1 \"one\"\n}") {
+        EvalError::Parse => (),
+        other => panic!("expected parse error, got {:?}", other),
+    }
+}
+
+#[test]
+fn arms_are_checked() {
+    let cases = [
+        // a variant of another enum
+        "let f = func(c: Color) : int {\n  return switch c {\n    Shape.Red => 1\n    _ => 2\n  }\n}",
+        // variants of two enums
+        "let f = func(c: any) : int {\n  return switch c {\n    Color.Red => 1\n    Shape.Square => 2\n    _ => 3\n  }\n}",
+        // a missing variant
+        "var n = switch Color.Red {\n  Color.Red => 1\n}",
+        // arms of different types
+        "var n = switch 1 {\n  1 => 1\n  _ => \"many\"\n}",
+        // an argument of the wrong enum
+        "let f = func(c: Color) : int { return 1 }\nprintln(f(Shape.Red))",
+    ];
+    for code in cases.iter() {
+        match error(code) {
+            EvalError::Check => (),
+            other => panic!("expected type error for {:?}, got {:?}", code, other),
+        }
+    }
+}
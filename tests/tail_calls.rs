@@ -0,0 +1,122 @@
+extern crate jazz_ml_vm;
+
+mod common;
+
+use common::{compile, global, vm};
+use jazz_ml_vm::error::RuntimeError;
+use jazz_ml_vm::value::Value;
+use jazz_ml_vm::vm::VirtualMachine;
+use jazz_ml_vm::{Engine, EvalError};
+
+fn depth(vm: &mut VirtualMachine, _: Vec<Value>) -> Result<Value, RuntimeError> {
+    Ok(Value::Int(vm.frames.len() as i64))
+}
+
+fn run(code: &str) -> VirtualMachine {
+    let mut vm = vm();
+    vm.register_native_func("depth".into(), depth, 0);
+
+    let ins = compile(&mut vm, code);
+    vm.run_instructions(ins).expect("runtime error");
+    vm
+}
+
+#[test]
+fn tail_recursion_runs_in_constant_stack() {
+    let vm = run("
+let loop = func(n: int, acc: int) : int {
+  if n == 0 {
+    return acc
+  }
+  return loop(n - 1, acc + 1)
+}
+var result = loop(100000, 0)
+");
+
+    assert_eq!(global(&vm, "result"), Value::Int(100000));
+}
+
+#[test]
+fn tail_calls_reuse_the_frame() {
+    let vm = run("
+let loop = func(n: int) : int {
+  if n == 0 {
+    depth()
+  } else {
+    loop(n - 1)
+  }
+}
+var shallow = loop(1)
+var deep = loop(500)
+");
+
+    assert_eq!(global(&vm, "deep"), global(&vm, "shallow"));
+}
+
+#[test]
+fn implicit_return_of_last_expression() {
+    let vm = run("
+let fib = func(a: int) : int {
+  if a > 2 {
+    fib(a - 1) + fib(a - 2)
+  } else {
+    1
+  }
+}
+let unit = func() : any {
+  var x = 1
+}
+var result = fib(10)
+var empty = unit()
+");
+
+    assert_eq!(global(&vm, "result"), Value::Int(55));
+    assert_eq!(global(&vm, "empty"), Value::Null);
+}
+
+#[test]
+fn elif_branches_are_tail_positions() {
+    let vm = run("
+let collatz = func(n: int, steps: int) : int {
+  if n == 1 {
+    steps
+  } elif n % 2 == 0 {
+    collatz(n / 2, steps + 1)
+  } else {
+    collatz(3 * n + 1, steps + 1)
+  }
+}
+var result = collatz(27, 0)
+");
+
+    assert_eq!(global(&vm, "result"), Value::Int(111));
+}
+
+#[test]
+fn self_recursive_functions_type_check_and_run() {
+    let mut engine = Engine::new();
+    let ret = engine
+        .eval_str(
+            "
+let count = func(n: int, acc: int) : int {
+  return if n == 0 { acc } else { count(n - 1, acc + 1) }
+}
+let down = func(n: int) : int {
+  if n == 0 {
+    return 0
+  } else {
+    return down(n - 1)
+  }
+}
+return [count(100000, 0), down(100000)]
+",
+        )
+        .unwrap();
+    assert_eq!(ret.as_str(&engine.vm), "[100000, 0]");
+
+    // calls from the body are still checked against the signature
+    match engine.eval_str("let go = func(n: int) : int {\n  return go(\"x\")\n}") {
+        Err(EvalError::Check) => (),
+        other => panic!("expected type error, got {:?}", other),
+    }
+}
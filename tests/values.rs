@@ -0,0 +1,115 @@
+extern crate fnv;
+extern crate jazz_ml_vm;
+extern crate rand;
+
+use fnv::FnvHashMap;
+use jazz_ml_vm::value::{Object, Value};
+use jazz_ml_vm::Engine;
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
+use std::collections::hash_map::DefaultHasher;
+use std::f64;
+use std::hash::{Hash, Hasher};
+
+const CASES: usize = 2000;
+
+fn hash(value: &Value) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn float(f: f64) -> Value {
+    Value::Float(f.to_bits())
+}
+
+// small domains so that equal values come up often
+fn random_value(rng: &mut SmallRng) -> Value {
+    match rng.gen_range(0, 12) {
+        0 => Value::Int(rng.gen_range(-3, 3)),
+        1 => {
+            let specials = [0.0, -0.0, 1.0, -1.5, f64::INFINITY, f64::NAN, -f64::NAN];
+            float(specials[rng.gen_range(0, specials.len())])
+        }
+        2 => float(f64::from_bits(rng.gen())),
+        3 => Value::Bool(rng.gen()),
+        4 => Value::Str(["", "a", "b", "null"][rng.gen_range(0, 4)].to_owned()),
+        5 => Value::Array(rng.gen_range(0, 3)),
+        6 => Value::ObjectRef(rng.gen_range(0, 3)),
+        7 => Value::FuncRef(rng.gen_range(0, 3)),
+        8 => Value::Closure(rng.gen_range(0, 3)),
+        9 => Value::StructRef(rng.gen_range(0, 3)),
+        10 => Value::Variant(rng.gen_range(0, 2), rng.gen_range(0, 2)),
+        _ => Value::Null,
+    }
+}
+
+#[test]
+fn equality_is_an_equivalence() {
+    let mut rng = SmallRng::seed_from_u64(17);
+
+    for _ in 0..CASES {
+        let a = random_value(&mut rng);
+        let b = random_value(&mut rng);
+        let c = random_value(&mut rng);
+
+        assert_eq!(a, a.clone());
+        assert_eq!(a == b, b == a, "{:?} {:?}", a, b);
+        if a == b && b == c {
+            assert_eq!(a, c);
+        }
+    }
+}
+
+#[test]
+fn equal_values_hash_alike() {
+    let mut rng = SmallRng::seed_from_u64(42);
+
+    for _ in 0..CASES {
+        let a = random_value(&mut rng);
+        let b = random_value(&mut rng);
+
+        assert_eq!(hash(&a), hash(&a.clone()), "{:?}", a);
+        if a == b {
+            assert_eq!(hash(&a), hash(&b), "{:?} {:?}", a, b);
+        }
+    }
+}
+
+#[test]
+fn every_value_finds_itself_as_a_key() {
+    let mut rng = SmallRng::seed_from_u64(7);
+    let mut map = FnvHashMap::default();
+
+    for i in 0..CASES {
+        let key = random_value(&mut rng);
+        map.insert(key.clone(), Value::Int(i as i64));
+        assert_eq!(map.get(&key), Some(&Value::Int(i as i64)), "{:?}", key);
+    }
+}
+
+#[test]
+fn floats_compare_as_keys() {
+    assert_eq!(float(0.0), float(-0.0));
+    assert_eq!(hash(&float(0.0)), hash(&float(-0.0)));
+    assert_eq!(float(f64::NAN), float(-f64::NAN));
+    assert_eq!(hash(&float(f64::NAN)), hash(&float(-f64::NAN)));
+    assert_ne!(float(1.0), Value::Int(1));
+    assert_ne!(Value::Array(1), Value::ObjectRef(1));
+}
+
+#[test]
+fn null_is_a_key() {
+    let mut obj = Object::new();
+    obj.store(Value::Null, Value::Int(1));
+    obj.store(Value::Null, Value::Int(2));
+    assert_eq!(obj.map.len(), 1);
+    assert_eq!(obj.load(&Value::Null), &Value::Int(2));
+
+    let mut engine = Engine::new();
+    engine.set_global("key", Value::Null);
+    let ret = engine
+        .eval_str("var m: [any: int] = [:]\nm[key] = 1\nm[key] = m[key] + 1\nreturn m")
+        .unwrap();
+    assert_eq!(ret.as_str(&engine.vm), "[null: 2]");
+}